@@ -330,7 +330,7 @@ where
     use std::io::BufRead;
 
     thread::spawn(move || {
-        let pgo = is_pgo.then_some("│").unwrap_or_default().dim();
+        let pgo = if is_pgo { "│" } else { "" }.dim();
         let kind = phase.styled(format!("{}│", phase.abbrev()));
         let tag = format!("{}{pgo}{kind}", "│".dim());
 
@@ -376,7 +376,7 @@ fn breakpoint_line(
 
     let has_key = |line: &str, key: &str| {
         line.split_once(':')
-            .map_or(false, |(leading, _)| leading.trim().ends_with(key))
+            .is_some_and(|(leading, _)| leading.trim().ends_with(key))
     };
 
     let mut lines = recipe
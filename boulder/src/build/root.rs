@@ -5,7 +5,7 @@
 use std::collections::HashSet;
 use std::{fs, io};
 
-use moss::{repository, runtime, Installation};
+use moss::{client::install, repository, runtime, Installation};
 use stone_recipe::{tuning::Toolchain, Upstream};
 use thiserror::Error;
 
@@ -23,8 +23,11 @@ pub fn populate(
 
     let rootfs = builder.paths.rootfs().host;
 
-    // Create the moss client
-    let installation = Installation::open(&builder.env.moss_dir)?;
+    // Create the moss client, waiting on any concurrent
+    // build which is using the shared cache
+    let installation = Installation::open(&builder.env.moss_dir)?
+        .with_lock_wait(true)
+        .with_exclusive_lock(true);
     let mut moss_client =
         moss::Client::with_explicit_repositories("boulder", installation, repositories)?.ephemeral(rootfs)?;
 
@@ -42,7 +45,15 @@ pub fn populate(
     timing.finish(initialize_timer);
 
     // Install packages
-    let install_timing = moss_client.install(&packages, true)?;
+    let install_timing = moss_client
+        .install(
+            &packages,
+            install::Options {
+                yes: true,
+                ..Default::default()
+            },
+        )?
+        .timing;
 
     timing.record(timing::Populate::Resolve, install_timing.resolve);
     timing.record(timing::Populate::Fetch, install_timing.fetch);
@@ -33,7 +33,7 @@ pub enum Subcommand {
             help = "profile repositories",
             value_parser = parse_repository,
             help = "repository to add to profile, can be passed multiple times",
-            long_help = "repository to add to profile\n\nExample: --repo name=volatile,uri=https://dev.serpentos.com/volatile/x86_64/stone.index,priority=100"
+            long_help = "repository to add to profile, mirror may be repeated to add fallback uris\n\nExample: --repo name=volatile,uri=https://dev.serpentos.com/volatile/x86_64/stone.index,priority=100"
         )]
         repos: Vec<(repository::Id, Repository)>,
     },
@@ -50,6 +50,12 @@ fn parse_repository(s: &str) -> Result<(repository::Id, Repository), String> {
         .split(',')
         .filter_map(|kv| kv.split_once('='))
         .collect::<HashMap<_, _>>();
+    // Mirrors may be repeated, so aren't collected into the map
+    let mirrors = s
+        .split(',')
+        .filter_map(|kv| kv.strip_prefix("mirror="))
+        .map(|uri| uri.parse::<Url>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
 
     let id = repository::Id::new(key_values.get("name").ok_or("missing name")?.to_string());
     let uri = key_values
@@ -69,6 +75,7 @@ fn parse_repository(s: &str) -> Result<(repository::Id, Repository), String> {
         Repository {
             description: String::default(),
             uri,
+            mirrors,
             priority: repository::Priority::new(priority),
         },
     ))
@@ -101,6 +108,9 @@ pub fn list(manager: profile::Manager) -> Result<(), Error> {
             .sorted_by(|(_, a), (_, b)| a.priority.cmp(&b.priority).reverse())
         {
             println!(" - {} = {} [{}]", id, repo.uri, repo.priority);
+            for mirror in &repo.mirrors {
+                println!("     mirror {mirror}");
+            }
         }
     }
 
@@ -132,7 +142,9 @@ pub fn add<'a>(
 pub fn update<'a>(env: &'a Env, manager: profile::Manager<'a>, profile: &profile::Id) -> Result<(), Error> {
     let repos = manager.repositories(profile)?.clone();
 
-    let installation = Installation::open(&env.moss_dir)?;
+    let installation = Installation::open(&env.moss_dir)?
+        .with_lock_wait(true)
+        .with_exclusive_lock(true);
     let mut moss_client = moss::Client::with_explicit_repositories("boulder", installation, repos)?;
     runtime::block_on(moss_client.refresh_repositories())?;
 
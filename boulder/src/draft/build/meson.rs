@@ -47,6 +47,7 @@ fn scan_meson(state: &mut State, path: &Path) -> Result<(), Error> {
             state.add_dependency(Dependency {
                 kind: dependency::Kind::PkgConfig,
                 name,
+                constraint: None,
             });
         }
     }
@@ -64,6 +65,7 @@ fn scan_meson(state: &mut State, path: &Path) -> Result<(), Error> {
             state.add_dependency(Dependency {
                 kind: dependency::Kind::Binary,
                 name,
+                constraint: None,
             });
         }
     }
@@ -147,6 +147,11 @@ fn resolve_packages(
             .into_iter()
             .map(|dep| parser.parse_content(&dep))
             .collect::<Result<_, _>>()?;
+        package.conflicts = package
+            .conflicts
+            .into_iter()
+            .map(|conflict| parser.parse_content(&conflict))
+            .collect::<Result<_, _>>()?;
         package.paths = package
             .paths
             .into_iter()
@@ -172,6 +177,7 @@ fn resolve_packages(
                 let prev = entry.remove();
 
                 package.run_deps = package.run_deps.into_iter().chain(prev.run_deps).sorted().collect();
+                package.conflicts = package.conflicts.into_iter().chain(prev.conflicts).sorted().collect();
                 package.paths = package
                     .paths
                     .into_iter()
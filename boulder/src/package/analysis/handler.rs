@@ -107,6 +107,7 @@ pub fn pkg_config(bucket: &mut BucketMut, info: &mut PathInfo) -> Result<Respons
         bucket.dependencies.insert(Dependency {
             kind,
             name: dep.to_string(),
+            constraint: None,
         });
     }
 
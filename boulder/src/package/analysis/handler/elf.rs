@@ -118,6 +118,7 @@ fn parse_dynamic_section(
                 bucket.dependencies.insert(Dependency {
                     kind: dependency::Kind::SharedLibrary,
                     name: format!("{name}({machine_isa})"),
+                    constraint: None,
                 });
             }
         }
@@ -187,6 +188,7 @@ fn parse_interp_section(elf: &mut elf::ElfStream<AnyEndian, File>, bucket: &mut
         bucket.dependencies.insert(Dependency {
             kind: dependency::Kind::Interpreter,
             name: format!("{content}({machine_isa})"),
+            constraint: None,
         });
     }
 }
@@ -202,9 +202,9 @@ fn layout_from_metadata(
         } else if file_type.is_dir() {
             layout::Entry::Directory(target)
         } else if file_type.is_char_device() {
-            layout::Entry::CharacterDevice(target)
+            layout::Entry::CharacterDevice(metadata.rdev(), target)
         } else if file_type.is_block_device() {
-            layout::Entry::BlockDevice(target)
+            layout::Entry::BlockDevice(metadata.rdev(), target)
         } else if file_type.is_fifo() {
             layout::Entry::Fifo(target)
         } else if file_type.is_socket() {
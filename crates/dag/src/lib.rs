@@ -3,8 +3,9 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use petgraph::{
+    algo::astar,
     prelude::DiGraph,
-    visit::{Dfs, Topo, Walker},
+    visit::{Dfs, EdgeRef, Topo, Walker},
 };
 
 use self::subgraph::subgraph;
@@ -82,6 +83,23 @@ where
         self.0.node_indices().map(|i| &self.0[i])
     }
 
+    /// Iterate all edges as `(from, to)` pairs
+    pub fn iter_edges(&self) -> impl Iterator<Item = (&'_ N, &'_ N)> {
+        self.0
+            .edge_references()
+            .map(|e| (&self.0[e.source()], &self.0[e.target()]))
+    }
+
+    /// Returns the shortest path from `from` to `to`, including both ends
+    pub fn shortest_path(&self, from: &N, to: &N) -> Option<Vec<&'_ N>> {
+        let start = self.get_index(from)?;
+        let goal = self.get_index(to)?;
+
+        let (_, path) = astar(&self.0, start, |i| i == goal, |_| 1, |_| 0)?;
+
+        Some(path.into_iter().map(|i| &self.0[i]).collect())
+    }
+
     /// Perform a depth-first search, given the start index
     pub fn dfs(&self, start: NodeIndex) -> impl Iterator<Item = &'_ N> {
         let dfs = Dfs::new(&self.0, start);
@@ -113,3 +131,26 @@ where
         self.0.node_indices().find(|i| self.0[*i] == *node)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shortest_path() {
+        let mut dag = Dag::new();
+        let a = dag.add_node_or_get_index('a');
+        let b = dag.add_node_or_get_index('b');
+        let c = dag.add_node_or_get_index('c');
+        let d = dag.add_node_or_get_index('d');
+        dag.add_edge(a, b);
+        dag.add_edge(b, c);
+        dag.add_edge(c, d);
+        dag.add_edge(a, d);
+
+        assert_eq!(dag.shortest_path(&'a', &'d'), Some(vec![&'a', &'d']));
+        assert_eq!(dag.shortest_path(&'b', &'d'), Some(vec![&'b', &'c', &'d']));
+        assert_eq!(dag.shortest_path(&'d', &'a'), None);
+        assert_eq!(dag.iter_edges().count(), 4);
+    }
+}
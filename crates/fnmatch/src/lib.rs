@@ -17,11 +17,12 @@
 use std::{
     collections::{HashMap, HashSet},
     convert::Infallible,
+    fmt,
     str::FromStr,
 };
 
 use regex::Regex;
-use serde::{de, Deserialize};
+use serde::{de, Deserialize, Serialize};
 use thiserror::Error;
 #[derive(Debug)]
 enum Fragment {
@@ -221,7 +222,7 @@ fn fragment_to_regex_str(fragment: &Fragment) -> (String, Vec<String>) {
         Fragment::BackSlash => "\\".into(),
         Fragment::ForwardSlash => "\\/".into(),
         Fragment::Dot => "\\.".into(),
-        Fragment::Text(t) => t.clone(),
+        Fragment::Text(t) => regex::escape(t),
         Fragment::Group(id, elements) => {
             let elements = elements
                 .iter()
@@ -272,6 +273,21 @@ impl<'de> Deserialize<'de> for Pattern {
     }
 }
 
+impl Serialize for Pattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
 impl PartialEq for Pattern {
     fn eq(&self, other: &Self) -> bool {
         self.pattern == other.pattern && self.groups == other.groups
@@ -317,4 +333,20 @@ pub mod path_tests {
         let wide = k.match_path("/usr/lib/modules/6.6.67-51.kvm/kernel/net/netfilter/nft_hash.ko.zst");
         assert!(wide.is_none());
     }
+
+    /// Text is matched literally rather than as regex syntax
+    #[test]
+    fn test_literal_text() {
+        let plus = "/usr/lib/libstdc++.so*".parse::<Pattern>().unwrap();
+        assert!(plus.match_path("/usr/lib/libstdc++.so.6").is_some());
+        assert!(plus.match_path("/usr/lib/libstdcc.so.6").is_none());
+
+        let brackets = "/usr/share/[0-9]/(name:*)".parse::<Pattern>().unwrap();
+        assert!(brackets.match_path("/usr/share/[0-9]/file").is_some());
+        assert!(brackets.match_path("/usr/share/1/file").is_none());
+
+        let anchors = "/usr/share/a|b$".parse::<Pattern>().unwrap();
+        assert!(anchors.match_path("/usr/share/a|b$").is_some());
+        assert!(anchors.match_path("/usr/share/a").is_none());
+    }
 }
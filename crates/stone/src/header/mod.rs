@@ -41,9 +41,9 @@ pub struct AgnosticHeader {
 
 impl AgnosticHeader {
     fn decode<R: Read>(mut reader: R) -> Result<Self, io::Error> {
-        let magic = reader.read_array()?;
-        let data = reader.read_array()?;
-        let version = reader.read_array()?;
+        let magic = reader.read_byte_array()?;
+        let data = reader.read_byte_array()?;
+        let version = reader.read_byte_array()?;
 
         Ok(Self { magic, data, version })
     }
@@ -78,6 +78,12 @@ impl Header {
         }
     }
 
+    pub fn file_type(&self) -> v1::FileType {
+        match self {
+            Header::V1(header) => header.file_type,
+        }
+    }
+
     pub fn encode<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
         let version = u32::to_be_bytes(self.version() as u32);
 
@@ -39,10 +39,10 @@ pub enum Entry {
     Regular(u128, String),
     Symlink(String, String),
     Directory(String),
-
-    // not properly supported
-    CharacterDevice(String),
-    BlockDevice(String),
+    /// Device number (`st_rdev`) + target
+    CharacterDevice(u64, String),
+    /// Device number (`st_rdev`) + target
+    BlockDevice(u64, String),
     Fifo(String),
     Socket(String),
 }
@@ -53,8 +53,8 @@ impl Entry {
             Entry::Regular(hash, _) => hash.to_be_bytes().to_vec(),
             Entry::Symlink(source, _) => source.as_bytes().to_vec(),
             Entry::Directory(_) => vec![],
-            Entry::CharacterDevice(_) => vec![],
-            Entry::BlockDevice(_) => vec![],
+            Entry::CharacterDevice(device, _) => device.to_be_bytes().to_vec(),
+            Entry::BlockDevice(device, _) => device.to_be_bytes().to_vec(),
             Entry::Fifo(_) => vec![],
             Entry::Socket(_) => vec![],
         }
@@ -65,8 +65,8 @@ impl Entry {
             Entry::Regular(_, target) => target,
             Entry::Symlink(_, target) => target,
             Entry::Directory(target) => target,
-            Entry::CharacterDevice(target) => target,
-            Entry::BlockDevice(target) => target,
+            Entry::CharacterDevice(_, target) => target,
+            Entry::BlockDevice(_, target) => target,
             Entry::Fifo(target) => target,
             Entry::Socket(target) => target,
         }
@@ -77,8 +77,8 @@ impl Entry {
             Entry::Regular(..) => 1,
             Entry::Symlink(..) => 2,
             Entry::Directory(_) => 3,
-            Entry::CharacterDevice(_) => 4,
-            Entry::BlockDevice(_) => 5,
+            Entry::CharacterDevice(..) => 4,
+            Entry::BlockDevice(..) => 5,
             Entry::Fifo(_) => 6,
             Entry::Socket(_) => 7,
         }
@@ -117,7 +117,7 @@ impl Record for Layout {
             t => return Err(DecodeError::UnknownFileType(t)),
         };
 
-        let _padding = reader.read_array::<11>()?;
+        let _padding = reader.read_byte_array::<11>()?;
 
         // Make the layout entry *usable*
         let entry = match file_type {
@@ -132,11 +132,21 @@ impl Record for Layout {
                 sanitize(reader.read_string(target_length as u64)?),
             ),
             FileType::Directory => Entry::Directory(sanitize(reader.read_string(target_length as u64)?)),
-            _ => {
-                if source_length > 0 {
-                    let _ = reader.read_vec(source_length as usize);
-                }
-                unreachable!()
+            FileType::CharacterDevice => {
+                let device = read_device(&mut reader, source_length)?;
+                Entry::CharacterDevice(device, sanitize(reader.read_string(target_length as u64)?))
+            }
+            FileType::BlockDevice => {
+                let device = read_device(&mut reader, source_length)?;
+                Entry::BlockDevice(device, sanitize(reader.read_string(target_length as u64)?))
+            }
+            FileType::Fifo => {
+                let _ = reader.read_vec(source_length as usize)?;
+                Entry::Fifo(sanitize(reader.read_string(target_length as u64)?))
+            }
+            FileType::Socket => {
+                let _ = reader.read_vec(source_length as usize)?;
+                Entry::Socket(sanitize(reader.read_string(target_length as u64)?))
             }
         };
 
@@ -172,3 +182,62 @@ impl Record for Layout {
         4 + 4 + 4 + 4 + 2 + 2 + 1 + 11 + self.entry.source().len() + self.entry.target().len()
     }
 }
+
+/// Device numbers are stored as the big endian source of device entries, older
+/// stones recorded none so they decode as `0`
+fn read_device<R: Read>(reader: &mut R, source_length: u16) -> Result<u64, DecodeError> {
+    let source = reader.read_vec(source_length as usize)?;
+
+    Ok(source.try_into().map(u64::from_be_bytes).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn special_files_roundtrip() {
+        let entries = [
+            Entry::CharacterDevice(0x0501, "dev/console".into()),
+            Entry::BlockDevice(0x0800, "dev/sda".into()),
+            Entry::Fifo("run/initctl".into()),
+            Entry::Socket("run/socket".into()),
+        ];
+
+        for entry in entries {
+            let layout = Layout {
+                uid: 0,
+                gid: 5,
+                mode: 0o20620,
+                tag: 0,
+                entry,
+            };
+
+            let mut bytes = vec![];
+            layout.encode(&mut bytes).unwrap();
+            assert_eq!(bytes.len(), layout.size());
+
+            let decoded = Layout::decode(bytes.as_slice()).unwrap();
+            assert_eq!(decoded, layout);
+        }
+    }
+
+    #[test]
+    fn device_without_number() {
+        let layout = Layout {
+            uid: 0,
+            gid: 0,
+            mode: 0o20600,
+            tag: 0,
+            entry: Entry::Fifo("dev/null".into()),
+        };
+
+        // Older stones don't record a device number, recreate one by patching the file type
+        let mut bytes = vec![];
+        layout.encode(&mut bytes).unwrap();
+        bytes[20] = FileType::CharacterDevice as u8;
+
+        let decoded = Layout::decode(bytes.as_slice()).unwrap();
+        assert_eq!(decoded.entry, Entry::CharacterDevice(0, "dev/null".into()));
+    }
+}
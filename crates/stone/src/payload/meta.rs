@@ -132,6 +132,14 @@ pub enum Tag {
     SourcePath = 19,
     // Ref/commit of the upstream source
     SourceRef = 20,
+    // Repository index specific (Hash of the package a delta applies to, starts a new delta)
+    DeltaFrom = 21,
+    // Repository index specific (relative URI of a delta)
+    DeltaURI = 22,
+    // Repository index specific (Delta hash)
+    DeltaHash = 23,
+    // Repository index specific (Delta size on disk)
+    DeltaSize = 24,
 }
 
 /// Helper to decode a dependency's encoded kind
@@ -176,11 +184,15 @@ impl Record for Meta {
             18 => Tag::SourceURI,
             19 => Tag::SourcePath,
             20 => Tag::SourceRef,
+            21 => Tag::DeltaFrom,
+            22 => Tag::DeltaURI,
+            23 => Tag::DeltaHash,
+            24 => Tag::DeltaSize,
             t => return Err(DecodeError::UnknownMetaTag(t)),
         };
 
         let kind = reader.read_u8()?;
-        let _padding = reader.read_array::<1>()?;
+        let _padding = reader.read_byte_array::<1>()?;
 
         // Remove null terminated byte from string
         let sanitize = |s: String| s.trim_end_matches('\0').to_string();
@@ -0,0 +1,96 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Detection of the system triggers run against, used to evaluate inhibitors
+
+use std::{
+    collections::BTreeSet,
+    fmt, fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use crate::format::Inhibitors;
+
+/// Markers left behind by the initramfs of a live session
+const LIVE_MARKERS: &[&str] = &["/run/initramfs/live", "/run/livedev"];
+
+/// A detectable kind of environment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    /// Not the running system, i.e. an image being built or a chroot
+    Chroot,
+    /// A live session, booted from installation media
+    Live,
+}
+
+impl Kind {
+    /// Name of the kind, as used by [`Inhibitors::environment`]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Chroot => "chroot",
+            Kind::Live => "live",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The system triggers run against
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    /// Root of the target system, inhibitor paths are relative to it
+    pub root: PathBuf,
+    /// Detected kinds of environment
+    pub kinds: BTreeSet<Kind>,
+}
+
+impl Environment {
+    /// Detect the environment of the system at `root`
+    pub fn detect(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let is_host = root.canonicalize().is_ok_and(|root| root == Path::new("/"));
+
+        let mut kinds = BTreeSet::new();
+
+        if !is_host || is_chrooted() {
+            kinds.insert(Kind::Chroot);
+        }
+        // Only the running system can be a live session
+        if is_host && LIVE_MARKERS.iter().any(|marker| Path::new(marker).exists()) {
+            kinds.insert(Kind::Live);
+        }
+
+        Self { root, kinds }
+    }
+
+    /// Returns the first reason `inhibitors` prevent a trigger from running, if any
+    pub fn inhibits(&self, inhibitors: &Inhibitors) -> Option<String> {
+        if let Some(kind) = self
+            .kinds
+            .iter()
+            .find(|kind| inhibitors.environment.iter().any(|name| name == kind.name()))
+        {
+            return Some(format!("{kind} environment"));
+        }
+
+        inhibitors
+            .paths
+            .iter()
+            .find(|path| fs::symlink_metadata(self.root.join(path.trim_start_matches('/'))).is_ok())
+            .map(|path| format!("{path} exists"))
+    }
+}
+
+/// Returns true if our root differs from that of init
+fn is_chrooted() -> bool {
+    match (fs::metadata("/"), fs::metadata("/proc/1/root")) {
+        (Ok(ours), Ok(init)) => (ours.dev(), ours.ino()) != (init.dev(), init.ino()),
+        _ => false,
+    }
+}
@@ -2,13 +2,13 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, fmt};
 
 use fnmatch::Pattern;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Filter matched paths to a specific kind
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum PathKind {
     Directory,
@@ -16,57 +16,87 @@ pub enum PathKind {
 }
 
 /// Execution handlers for a trigger
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(untagged)]
 pub enum Handler {
     Run { run: String, args: Vec<String> },
     Delete { delete: Vec<String> },
 }
 
+/// A [`Handler`] with all variables substituted, along with the trigger it belongs to
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct CompiledHandler(Handler);
+pub struct CompiledHandler {
+    trigger: String,
+    handler: Handler,
+}
 
 impl CompiledHandler {
     pub fn handler(&self) -> &Handler {
-        &self.0
+        &self.handler
+    }
+
+    /// Name of the trigger this handler belongs to
+    pub fn trigger(&self) -> &str {
+        &self.trigger
     }
 }
 
 impl Handler {
     /// Substitute all paths using matched variables
-    pub fn compiled(&self, with_match: &fnmatch::Match) -> CompiledHandler {
+    pub fn compiled(&self, trigger: &str, with_match: &fnmatch::Match) -> CompiledHandler {
+        let substitute = |input: &String| {
+            let mut output = input.clone();
+            for (key, value) in &with_match.variables {
+                output = output.replace(&format!("$({key})"), value);
+            }
+            output
+        };
+
+        let handler = match self {
+            Handler::Run { run, args } => Handler::Run {
+                run: substitute(run),
+                args: args.iter().map(substitute).collect(),
+            },
+            Handler::Delete { delete } => Handler::Delete {
+                delete: delete.iter().map(substitute).collect(),
+            },
+        };
+
+        CompiledHandler {
+            trigger: trigger.to_string(),
+            handler,
+        }
+    }
+}
+
+impl fmt::Display for Handler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Handler::Run { run, args } => {
-                let mut run = run.clone();
-                for (key, value) in &with_match.variables {
-                    run = run.replace(&format!("$({key})"), value);
+                write!(f, "{run}")?;
+                for arg in args {
+                    write!(f, " {arg}")?;
                 }
-                let args = args
-                    .iter()
-                    .map(|a| {
-                        let mut a = a.clone();
-                        for (key, value) in &with_match.variables {
-                            a = a.replace(&format!("$({key})"), value);
-                        }
-                        a
-                    })
-                    .collect();
-                CompiledHandler(Handler::Run { run, args })
+                Ok(())
             }
-            Handler::Delete { delete } => CompiledHandler(Handler::Delete { delete: delete.clone() }),
+            Handler::Delete { delete } => write!(f, "delete {}", delete.join(" ")),
         }
     }
 }
 
 /// Inhibitors prevent handlers from running based on some constraints
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Inhibitors {
+    /// Absolute paths within the target root, any of which existing inhibits the trigger
+    #[serde(default)]
     pub paths: Vec<String>,
+    /// Environments such as `chroot` or `live`, any of which being detected inhibits the trigger
+    #[serde(default)]
     pub environment: Vec<String>,
 }
 
 /// Map handlers to a path pattern and kind filter
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PathDefinition {
     pub handlers: Vec<String>,
     #[serde(rename = "type")]
@@ -74,7 +104,7 @@ pub struct PathDefinition {
 }
 
 /// Serialization format of triggers
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Trigger {
     /// Unique (global scope) identifier
     pub name: String,
@@ -88,9 +118,16 @@ pub struct Trigger {
     /// Run after this trigger name
     pub after: Option<String>,
 
+    /// Run after this trigger name, which must exist and not be inhibited
+    pub needs: Option<String>,
+
     /// Optional inhibitors
     pub inhibitors: Option<Inhibitors>,
 
+    /// Match every path of the new state, rather than only those changed by the transaction
+    #[serde(default)]
+    pub always: bool,
+
     /// Map glob / patterns to their configuration
     pub paths: BTreeMap<Pattern, PathDefinition>,
 
@@ -100,7 +137,7 @@ pub struct Trigger {
 
 #[cfg(test)]
 mod tests {
-    use crate::format::Trigger;
+    use crate::format::{Handler, Trigger};
 
     #[test]
     fn test_trigger_file() {
@@ -115,4 +152,22 @@ mod tests {
         eprintln!("trigger: {trigger:?}");
         eprintln!("match: {result:?}");
     }
+
+    #[test]
+    fn test_compiled_delete() {
+        let handler = Handler::Delete {
+            delete: vec!["/usr/lib/modules/$(version)/modules.dep".into()],
+        };
+        let pattern = "/usr/lib/modules/(version:*)/kernel"
+            .parse::<fnmatch::Pattern>()
+            .unwrap();
+        let result = pattern.match_path("/usr/lib/modules/6.6.7/kernel").unwrap();
+
+        let compiled = handler.compiled("depmod", &result);
+        assert_eq!(compiled.trigger(), "depmod");
+        assert_eq!(
+            compiled.handler().to_string(),
+            "delete /usr/lib/modules/6.6.7/modules.dep"
+        );
+    }
 }
@@ -6,15 +6,19 @@
 
 use std::collections::{BTreeMap, BTreeSet};
 
-use format::Trigger;
+use format::{PathKind, Trigger};
 use thiserror::Error;
 
+pub use self::environment::Environment;
+
+pub mod environment;
 pub mod format;
 
 /// Grouped management of a set of triggers
 pub struct Collection<'a> {
     handlers: Vec<ExtractedHandler>,
     triggers: BTreeMap<String, &'a Trigger>,
+    inhibited: BTreeMap<String, String>,
     hits: BTreeMap<String, BTreeSet<format::CompiledHandler>>,
 }
 
@@ -22,6 +26,8 @@ pub struct Collection<'a> {
 struct ExtractedHandler {
     id: String,
     pattern: fnmatch::Pattern,
+    kind: Option<PathKind>,
+    always: bool,
     handler: format::Handler,
 }
 
@@ -29,15 +35,71 @@ struct ExtractedHandler {
 pub enum Error {
     #[error("missing handler reference in {0}: {1}")]
     MissingHandler(String, String),
+
+    #[error("unknown trigger: {0}")]
+    UnknownTrigger(String),
+
+    #[error("trigger {0} needs {1}, which doesn't exist")]
+    MissingDependency(String, String),
+
+    #[error("trigger {0} is ordered relative to {1}, which doesn't exist")]
+    MissingOrdering(String, String),
+
+    #[error("trigger {0} needs {1}, which must run after it")]
+    CyclicDependency(String, String),
 }
 
 impl<'a> Collection<'a> {
-    /// Create a new [Collection] using the given triggers
-    pub fn new(triggers: impl IntoIterator<Item = &'a Trigger>) -> Result<Self, Error> {
+    /// Create a new [Collection] using the given triggers, skipping any inhibited by the `environment`
+    pub fn new(triggers: impl IntoIterator<Item = &'a Trigger>, environment: &Environment) -> Result<Self, Error> {
+        let trigger_set = triggers
+            .into_iter()
+            .map(|trigger| (trigger.name.clone(), trigger))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut inhibited = BTreeMap::new();
+
+        for trigger in trigger_set.values() {
+            if let Some(needs) = &trigger.needs {
+                if !trigger_set.contains_key(needs) {
+                    return Err(Error::MissingDependency(trigger.name.clone(), needs.clone()));
+                }
+            }
+
+            for other in [&trigger.before, &trigger.after].into_iter().flatten() {
+                if !trigger_set.contains_key(other) {
+                    return Err(Error::MissingOrdering(trigger.name.clone(), other.clone()));
+                }
+            }
+
+            if let Some(reason) = trigger
+                .inhibitors
+                .as_ref()
+                .and_then(|inhibitors| environment.inhibits(inhibitors))
+            {
+                inhibited.insert(trigger.name.clone(), reason);
+            }
+        }
+
+        // A trigger can't run without the trigger it needs
+        loop {
+            let blocked = trigger_set
+                .values()
+                .filter(|trigger| !inhibited.contains_key(&trigger.name))
+                .filter_map(|trigger| {
+                    let needs = trigger.needs.as_ref().filter(|needs| inhibited.contains_key(*needs))?;
+                    Some((trigger.name.clone(), format!("needs inhibited trigger {needs}")))
+                })
+                .collect::<Vec<_>>();
+
+            if blocked.is_empty() {
+                break;
+            }
+            inhibited.extend(blocked);
+        }
+
         let mut handlers = vec![];
-        let mut trigger_set = BTreeMap::new();
-        for trigger in triggers.into_iter() {
-            trigger_set.insert(trigger.name.clone(), trigger);
+        for trigger in trigger_set.values() {
             for (p, def) in trigger.paths.iter() {
                 for used_handler in def.handlers.iter() {
                     // Ensure we have a corresponding handler
@@ -45,9 +107,16 @@ impl<'a> Collection<'a> {
                         .handlers
                         .get(used_handler)
                         .ok_or(Error::MissingHandler(trigger.name.clone(), used_handler.clone()))?;
+
+                    if inhibited.contains_key(&trigger.name) {
+                        continue;
+                    }
+
                     handlers.push(ExtractedHandler {
                         id: trigger.name.clone(),
                         pattern: p.clone(),
+                        kind: def.kind,
+                        always: trigger.always,
                         handler: handler.clone(),
                     });
                 }
@@ -57,16 +126,49 @@ impl<'a> Collection<'a> {
         Ok(Self {
             handlers,
             triggers: trigger_set,
+            inhibited,
             hits: BTreeMap::new(),
         })
     }
 
-    /// Process a batch set of paths and record the "hit"
-    pub fn process_paths(&mut self, paths: impl Iterator<Item = String>) {
-        let results = paths.into_iter().flat_map(|p| {
+    /// Triggers prevented from running, along with the reason
+    pub fn inhibited(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.inhibited
+            .iter()
+            .map(|(name, reason)| (name.as_str(), reason.as_str()))
+    }
+
+    /// Process a batch set of paths, along with their kind if not a regular file, and record the "hit"
+    pub fn process_paths(&mut self, paths: impl Iterator<Item = (String, Option<PathKind>)>) {
+        self.process(paths, |_| true);
+    }
+
+    /// Process the paths of a transaction, recording hits for triggers matching any `changed`
+    /// (added, modified or removed) path, or any of `all` paths for triggers marked `always`
+    pub fn process_changes(
+        &mut self,
+        all: impl Iterator<Item = (String, Option<PathKind>)>,
+        changed: impl Iterator<Item = (String, Option<PathKind>)>,
+    ) {
+        self.process(all, |h| h.always);
+        self.process(changed, |h| !h.always);
+    }
+
+    fn process(
+        &mut self,
+        paths: impl Iterator<Item = (String, Option<PathKind>)>,
+        filter: impl Fn(&ExtractedHandler) -> bool + Copy,
+    ) {
+        let results = paths.into_iter().flat_map(|(p, kind)| {
             self.handlers
                 .iter()
-                .filter_map(move |h| h.pattern.match_path(&p).map(|m| (h.id.clone(), h.handler.compiled(&m))))
+                .filter(move |h| filter(h))
+                .filter(move |h| h.kind.is_none_or(|wanted| Some(wanted) == kind))
+                .filter_map(move |h| {
+                    h.pattern
+                        .match_path(&p)
+                        .map(|m| (h.id.clone(), h.handler.compiled(&h.id, &m)))
+                })
         });
 
         for (id, handler) in results {
@@ -87,14 +189,32 @@ impl<'a> Collection<'a> {
             let _ = graph.add_node_or_get_index(id.clone());
         }
 
-        // add dependency ordering for the toplevel IDs
-        for id in self.hits.keys() {
-            let lookup = self
-                .triggers
-                .get(id)
-                .ok_or(Error::MissingHandler(id.clone(), id.clone()))?;
+        let lookups = self
+            .hits
+            .keys()
+            .map(|id| {
+                self.triggers
+                    .get(id)
+                    .map(|lookup| (id, *lookup))
+                    .ok_or(Error::UnknownTrigger(id.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Hard dependencies first, so they always take precedence over ordering hints
+        for (id, lookup) in &lookups {
+            if let Some(needs) = &lookup.needs {
+                let node = graph.add_node_or_get_index((*id).clone());
+                let needed = graph.add_node_or_get_index(needs.clone());
+
+                if !graph.add_edge(needed, node) {
+                    return Err(Error::CyclicDependency((*id).clone(), needs.clone()));
+                }
+            }
+        }
 
-            let node = graph.add_node_or_get_index(id.clone());
+        // add dependency ordering for the toplevel IDs
+        for (id, lookup) in &lookups {
+            let node = graph.add_node_or_get_index((*id).clone());
 
             // This runs *before* B
             if let Some(before) = lookup
@@ -126,3 +246,155 @@ impl<'a> Collection<'a> {
         Ok(results)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn trigger(yaml: &str) -> Trigger {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn environment() -> Environment {
+        Environment {
+            root: env!("CARGO_MANIFEST_DIR").into(),
+            kinds: BTreeSet::from([environment::Kind::Chroot]),
+        }
+    }
+
+    const HANDLERS: &str = "
+description: test
+handlers:
+    run:
+        run: /usr/bin/true
+        args: [\"$(name)\"]
+paths:
+    \"/usr/lib/(name:*)\":
+        handlers: [run]
+";
+
+    #[test]
+    fn inhibitors() {
+        let triggers = [
+            trigger(&format!(
+                "name: chroot\ninhibitors:\n    environment: [chroot]\n{HANDLERS}"
+            )),
+            trigger(&format!(
+                "name: path\ninhibitors:\n    paths: [/Cargo.toml]\n{HANDLERS}"
+            )),
+            trigger(&format!("name: missing\ninhibitors:\n    paths: [/nope]\n{HANDLERS}")),
+            trigger(&format!("name: dependent\nneeds: path\n{HANDLERS}")),
+        ];
+
+        let mut collection = Collection::new(&triggers, &environment()).unwrap();
+        let inhibited = collection.inhibited().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(inhibited, ["chroot", "dependent", "path"]);
+
+        collection.process_paths([("/usr/lib/foo".to_string(), None)].into_iter());
+        assert_eq!(collection.bake().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn needs() {
+        let triggers = [
+            trigger(&format!("name: a\nneeds: b\n{HANDLERS}")),
+            trigger(&format!("name: b\n{}", HANDLERS.replace("/usr/bin/true", "/usr/bin/b"))),
+        ];
+        let missing = [trigger(&format!("name: a\nneeds: b\n{HANDLERS}"))];
+        let cyclic = [
+            trigger(&format!("name: a\nneeds: b\n{HANDLERS}")),
+            trigger(&format!("name: b\nneeds: a\n{HANDLERS}")),
+        ];
+
+        assert!(matches!(
+            Collection::new(&missing, &environment()),
+            Err(Error::MissingDependency(a, b)) if a == "a" && b == "b"
+        ));
+
+        let mut collection = Collection::new(&cyclic, &environment()).unwrap();
+        collection.process_paths([("/usr/lib/foo".to_string(), None)].into_iter());
+        assert!(matches!(collection.bake(), Err(Error::CyclicDependency(..))));
+
+        let mut collection = Collection::new(&triggers, &environment()).unwrap();
+        collection.process_paths([("/usr/lib/foo".to_string(), None)].into_iter());
+        let order = collection
+            .bake()
+            .unwrap()
+            .into_iter()
+            .filter_map(|handler| match handler.handler() {
+                format::Handler::Run { run, .. } => Some(run.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(order, ["/usr/bin/b", "/usr/bin/true"]);
+    }
+
+    #[test]
+    fn missing_ordering() {
+        for ordering in ["before", "after"] {
+            let triggers = [trigger(&format!("name: a\n{ordering}: b\n{HANDLERS}"))];
+
+            assert!(matches!(
+                Collection::new(&triggers, &environment()),
+                Err(Error::MissingOrdering(a, b)) if a == "a" && b == "b"
+            ));
+        }
+    }
+
+    #[test]
+    fn path_kinds() {
+        let triggers = [trigger(
+            "
+name: dirs
+description: test
+handlers:
+    run:
+        run: /usr/bin/true
+        args: [\"$(name)\"]
+paths:
+    \"/usr/lib/(name:*)\":
+        handlers: [run]
+        type: directory
+",
+        )];
+
+        let mut collection = Collection::new(&triggers, &Environment::default()).unwrap();
+        collection.process_paths(
+            [
+                ("/usr/lib/file".to_string(), None),
+                ("/usr/lib/link".to_string(), Some(PathKind::Symlink)),
+            ]
+            .into_iter(),
+        );
+        assert!(collection.bake().unwrap().is_empty());
+
+        collection.process_paths([("/usr/lib/dir".to_string(), Some(PathKind::Directory))].into_iter());
+        assert_eq!(collection.bake().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn changes() {
+        let triggers = [
+            trigger(&format!("name: changed\n{HANDLERS}")),
+            trigger(&format!(
+                "name: always\nalways: true\n{}",
+                HANDLERS.replace("/usr/bin/true", "/usr/bin/always")
+            )),
+        ];
+        let all = || ["/usr/lib/a", "/usr/lib/b"].map(|p| (p.to_string(), None)).into_iter();
+
+        let mut collection = Collection::new(&triggers, &environment()).unwrap();
+        collection.process_changes(all(), [].into_iter());
+        let handlers = collection.bake().unwrap();
+        assert_eq!(handlers.len(), 2);
+        assert!(handlers.iter().all(|handler| handler.trigger() == "always"));
+
+        collection.process_changes(all(), [("/usr/lib/a".to_string(), None)].into_iter());
+        let handlers = collection.bake().unwrap();
+        assert_eq!(handlers.len(), 3);
+        assert_eq!(
+            handlers.iter().filter(|handler| handler.trigger() == "changed").count(),
+            1
+        );
+    }
+}
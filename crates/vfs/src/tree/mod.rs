@@ -25,6 +25,18 @@ pub enum Kind {
 
     // Symlink to somewhere else.
     Symlink(String),
+
+    // Character device node
+    CharacterDevice,
+
+    // Block device node
+    BlockDevice,
+
+    // Named pipe
+    Fifo,
+
+    // UNIX socket
+    Socket,
 }
 
 /// Simple generic interface for blittable files while retaining details
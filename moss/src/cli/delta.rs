@@ -0,0 +1,227 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use clap::{arg, value_parser, ArgMatches, Command};
+use moss::package::{Meta, MissingMetaFieldError};
+use sha2::{Digest, Sha256};
+use stone::{
+    header::v1::FileType,
+    payload::{self, layout, meta},
+    read::PayloadKind,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("delta")
+        .about("Generate a delta between two releases of a package")
+        .long_about(
+            "Generate a delta stone containing only the assets of NEW missing from OLD \n\
+             \n\
+             Deltas written alongside packages are advertised by `moss index`, and fetched \n\
+             in place of NEW by clients that already have the assets of OLD",
+        )
+        .arg(arg!(<OLD> "previous release of the package").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(<NEW> "new release of the package").value_parser(value_parser!(PathBuf)))
+        .arg(
+            arg!(-o --output <DIR> "Directory to write the delta to, defaults to that of NEW")
+                .value_parser(value_parser!(PathBuf)),
+        )
+}
+
+pub fn handle(args: &ArgMatches) -> Result<(), Error> {
+    let old_path = args.get_one::<PathBuf>("OLD").unwrap();
+    let new_path = args.get_one::<PathBuf>("NEW").unwrap();
+
+    let old = read_package(old_path)?;
+    let mut new_file = File::open(new_path)?;
+    let mut new_reader = stone::read(&mut new_file)?;
+    if new_reader.header.file_type() != FileType::Binary {
+        return Err(Error::NotAPackage(new_path.clone()));
+    }
+    let new = new_reader.payloads()?.collect::<Result<Vec<_>, _>>()?;
+
+    let old_meta = package_meta(&old)?;
+    let new_meta = package_meta(&new)?;
+    if old_meta.name != new_meta.name {
+        return Err(Error::DifferentPackages(
+            old_meta.name.to_string(),
+            new_meta.name.to_string(),
+        ));
+    }
+
+    let old_hash = hash_file(old_path)?;
+    let new_hash = hash_file(new_path)?;
+
+    // Assets already present for anyone with the old release
+    let old_assets = old
+        .iter()
+        .filter_map(PayloadKind::layout)
+        .flat_map(|p| &p.body)
+        .filter_map(|layout| match &layout.entry {
+            layout::Entry::Regular(hash, _) => Some(*hash),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    let mut seen = HashSet::new();
+    let indices = new
+        .iter()
+        .filter_map(PayloadKind::index)
+        .flat_map(|p| &p.body)
+        .collect::<Vec<_>>();
+    let missing = indices
+        .iter()
+        .filter(|index| !old_assets.contains(&index.digest) && seen.insert(index.digest))
+        .collect::<Vec<_>>();
+
+    let dir = match args.get_one::<PathBuf>("output") {
+        Some(dir) => dir.clone(),
+        None => new_path.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    let stem = new_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| Error::NotAPackage(new_path.clone()))?;
+    let out_path = dir.join(format!("{stem}.delta-{}.stone", &old_hash[..12]));
+
+    // Written aside & only moved into place once complete, so failures leave nothing behind
+    let mut out_file = tempfile::Builder::new()
+        .permissions(fs::Permissions::from_mode(0o644))
+        .tempfile_in(&dir)?;
+    let mut writer = stone::Writer::new(out_file.as_file_mut(), FileType::Delta)?;
+
+    // Identify the releases this delta goes from & to
+    let meta = new
+        .iter()
+        .find_map(PayloadKind::meta)
+        .map(|p| p.body.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .chain([
+            payload::Meta {
+                tag: meta::Tag::DeltaFrom,
+                kind: meta::Kind::String(old_hash),
+            },
+            payload::Meta {
+                tag: meta::Tag::PackageHash,
+                kind: meta::Kind::String(new_hash),
+            },
+        ])
+        .collect::<Vec<_>>();
+    writer.add_payload(meta.as_slice())?;
+
+    for payload in &new {
+        match payload {
+            PayloadKind::Attributes(attributes) => writer.add_payload(attributes.body.as_slice())?,
+            PayloadKind::Layout(layouts) => writer.add_payload(layouts.body.as_slice())?,
+            _ => {}
+        }
+    }
+
+    if missing.is_empty() {
+        writer.finalize()?;
+    } else {
+        let content = new.iter().find_map(PayloadKind::content).ok_or(Error::MissingContent)?;
+
+        // Unpack the full content so we can pick the missing assets from it
+        let mut content_file = tempfile::tempfile_in(&dir)?;
+        new_reader.unpack_content(content, &mut content_file)?;
+
+        let mut buffer = tempfile::tempfile_in(&dir)?;
+
+        let size = missing.iter().map(|index| index.end - index.start).sum();
+        let mut writer = writer.with_content(&mut buffer, Some(size), 1)?;
+
+        for index in &missing {
+            content_file.seek(SeekFrom::Start(index.start))?;
+            writer.add_content(&mut (&mut content_file).take(index.end - index.start))?;
+        }
+
+        writer.finalize()?;
+    }
+
+    out_file.persist(&out_path).map_err(|error| error.error)?;
+
+    println!(
+        "{} {} {}",
+        "Delta written to".green(),
+        out_path.display(),
+        format!(
+            "({} of {} assets, {} of {} bytes)",
+            missing.len(),
+            indices.len(),
+            fs::metadata(&out_path)?.len(),
+            fs::metadata(new_path)?.len(),
+        )
+        .dim()
+    );
+
+    Ok(())
+}
+
+/// Read the payloads of the package at `path`
+fn read_package(path: &Path) -> Result<Vec<PayloadKind>, Error> {
+    let mut file = File::open(path)?;
+    let mut reader = stone::read(&mut file)?;
+
+    if reader.header.file_type() != FileType::Binary {
+        return Err(Error::NotAPackage(path.to_path_buf()));
+    }
+
+    let payloads = reader.payloads()?.collect::<Result<Vec<_>, _>>()?;
+
+    Ok(payloads)
+}
+
+fn package_meta(payloads: &[PayloadKind]) -> Result<Meta, Error> {
+    let payload = payloads
+        .iter()
+        .find_map(PayloadKind::meta)
+        .ok_or(Error::MissingMetaPayload)?;
+
+    Ok(Meta::from_stone_payload(&payload.body)?)
+}
+
+fn hash_file(path: &Path) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+
+    #[error("stone read")]
+    StoneRead(#[from] stone::read::Error),
+
+    #[error("stone write")]
+    StoneWrite(#[from] stone::write::Error),
+
+    #[error("not a package: {0:?}")]
+    NotAPackage(PathBuf),
+
+    #[error("can't generate a delta from {0} to {1}")]
+    DifferentPackages(String, String),
+
+    #[error("meta payload missing")]
+    MissingMetaPayload,
+
+    #[error("content payload missing")]
+    MissingContent,
+
+    #[error(transparent)]
+    MissingMetaField(#[from] MissingMetaFieldError),
+}
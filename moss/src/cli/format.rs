@@ -0,0 +1,61 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Machine-readable output for query commands
+
+use std::io::{self, Write};
+
+use clap::{ArgMatches, ValueEnum};
+use serde::Serialize;
+use thiserror::Error;
+
+/// Serialization format requested with the global `--format` option
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// Returns the requested format, or `None` for human readable output
+    pub fn get(args: &ArgMatches) -> Option<Self> {
+        args.get_one::<Self>("format").copied()
+    }
+
+    /// Serialize `value` to stdout
+    pub fn print<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        let mut stdout = io::stdout().lock();
+
+        match self {
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut stdout, value)?;
+                writeln!(stdout)?;
+            }
+            Format::Yaml => serde_yaml::to_writer(&mut stdout, value)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Print a directed graph of `edges` in DOT format
+pub fn print_dot<'a>(edges: impl IntoIterator<Item = (&'a str, &'a str)>) {
+    println!("digraph {{");
+    for (from, to) in edges {
+        println!("    {from:?} -> {to:?};");
+    }
+    println!("}}");
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+
+    #[error("yaml")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("io")]
+    Io(#[from] io::Error),
+}
@@ -0,0 +1,138 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgMatches, Command};
+use moss::{
+    client::{self, Client},
+    dependency, environment,
+    hold::{self, Hold},
+    package::{self, Flags},
+    repository, Installation,
+};
+use serde::Serialize;
+use thiserror::Error;
+use tui::Styled;
+
+use super::format::{self, Format};
+
+pub fn command() -> Command {
+    Command::new("hold")
+        .about("Hold packages at their installed version")
+        .long_about(
+            "Hold a package at its installed version, so `install` and `sync` keep it as is \n\
+             \n\
+             With --repo or --version the package is pinned instead, so it's only ever \n\
+             installed from the given repository, or at versions satisfying the given \n\
+             constraint such as `6.8`, `< 7` or `release >= 12`. \n\
+             \n\
+             Without a package, all holds are listed",
+        )
+        .arg(arg!([NAME] "package to hold").value_parser(clap::value_parser!(String)))
+        .arg(
+            arg!(--repo <ID> "Pin the package to this repository")
+                .requires("NAME")
+                .value_parser(clap::value_parser!(String)),
+        )
+        .arg(
+            arg!(--version <CONSTRAINT> "Pin the package to versions satisfying this constraint")
+                .requires("NAME")
+                .value_parser(clap::value_parser!(String)),
+        )
+}
+
+/// A configured hold
+#[derive(Debug, Serialize)]
+struct Entry {
+    package: String,
+    #[serde(flatten)]
+    hold: Hold,
+}
+
+/// Handle execution of `moss hold`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    let Some(name) = args.get_one::<String>("NAME") else {
+        return list(args, &client);
+    };
+    let name = package::Name::from(name.clone());
+
+    let version = args
+        .get_one::<String>("version")
+        .map(|version| {
+            hold::parse_version(version)
+                .map(|constraint| constraint.to_string())
+                .map_err(|error| Error::InvalidVersion(version.clone(), error))
+        })
+        .transpose()?;
+    let hold = Hold {
+        repository: args.get_one::<String>("repo").cloned().map(repository::Id::new),
+        version,
+    };
+
+    if hold.is_pin() {
+        let candidates = client.held_candidates(&name, &hold)?.unwrap_or_default();
+        if candidates.is_empty() {
+            return Err(Error::NoCandidate(name.to_string(), hold.to_string()));
+        }
+    } else if client
+        .registry
+        .by_name(&name, Flags::new().with_installed())
+        .next()
+        .is_none()
+    {
+        return Err(Error::NotInstalled(name.to_string()));
+    }
+
+    let pinned = hold.is_pin().then(|| format!(" ({hold})"));
+    client.hold(&name, hold)?;
+
+    println!(
+        "{} {}{}",
+        "Held".green(),
+        name.to_string().bold(),
+        pinned.unwrap_or_default().dim()
+    );
+
+    Ok(())
+}
+
+/// List all configured holds
+fn list(args: &ArgMatches, client: &Client) -> Result<(), Error> {
+    let entries = client
+        .holds()
+        .into_iter()
+        .map(|(package, hold)| Entry { package, hold })
+        .collect::<Vec<_>>();
+
+    if let Some(format) = Format::get(args) {
+        format.print(&entries)?;
+    } else if entries.is_empty() {
+        println!("No packages are held");
+    } else {
+        for entry in &entries {
+            println!("{} {}", entry.package.clone().bold(), format!("({})", entry.hold).dim());
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("package not installed: {0}")]
+    NotInstalled(String),
+
+    #[error("invalid version constraint: {0}")]
+    InvalidVersion(String, #[source] dependency::ParseError),
+
+    #[error("{0} can't be {1}, no candidate matches")]
+    NoCandidate(String, String),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("output")]
+    Output(#[from] format::Error),
+}
@@ -11,10 +11,15 @@ use std::{
 use clap::{arg, value_parser, ArgMatches, Command};
 use moss::{
     client,
-    package::{self, Meta, MissingMetaFieldError},
+    package::{self, meta::Delta, Meta, MissingMetaFieldError},
+    repository::key::{self, SecretKey},
 };
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use sha2::{Digest, Sha256};
+use stone::{
+    header::v1::FileType,
+    payload::{meta, Layout},
+};
 use thiserror::Error;
 use tui::{MultiProgress, ProgressBar, ProgressStyle, Styled};
 
@@ -23,10 +28,35 @@ pub fn command() -> Command {
         .visible_alias("ix")
         .about("Index a collection of packages")
         .arg(arg!(<INDEX_DIR> "directory of index files").value_parser(value_parser!(PathBuf)))
+        .arg(
+            arg!(--sign <SECRET_KEY> "Sign the index with the provided secret key")
+                .long_help(
+                    "Sign the index with the provided secret key, writing a detached \n\
+                     stone.index.sig alongside it. \n\
+                     \n\
+                     Generate a key with `moss repo key generate`",
+                )
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--files "Also write the files of each package")
+                .long_help(
+                    "Also write stone.files alongside the index, listing the files of \n\
+                     each package so clients can search available packages by path \n\
+                     with `moss search-file --available`",
+                )
+                .action(clap::ArgAction::SetTrue),
+        )
 }
 
 pub fn handle(args: &ArgMatches) -> Result<(), Error> {
     let dir = args.get_one::<PathBuf>("INDEX_DIR").unwrap().canonicalize()?;
+    // Load early so a bad key doesn't waste an index run
+    let secret_key = args
+        .get_one::<PathBuf>("sign")
+        .map(|path| SecretKey::load(path))
+        .transpose()?;
+    let with_files = args.get_flag("files");
 
     let stone_files = enumerate_stone_files(&dir)?;
 
@@ -45,27 +75,36 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
 
     let list = stone_files
         .par_iter()
-        .map(|path| get_meta(path, &dir, &multi_progress, &total_progress))
+        .map(|path| get_meta(path, &dir, with_files, &multi_progress, &total_progress))
         .collect::<Result<Vec<_>, _>>()?;
 
     let mut map = BTreeMap::new();
+    let mut deltas = vec![];
 
     // Add each meta to the map, removing
     // dupes by keeping the latest release
-    for meta in list {
+    for indexed in list {
+        let (meta, layouts) = match indexed {
+            Indexed::Package(meta, layouts) => (*meta, layouts),
+            Indexed::Delta(target, delta) => {
+                deltas.push((target, delta));
+                continue;
+            }
+        };
+
         match map.entry(meta.name.clone()) {
             btree_map::Entry::Vacant(entry) => {
-                entry.insert(meta);
+                entry.insert((meta, layouts));
             }
             btree_map::Entry::Occupied(mut entry) => {
-                match (entry.get().source_release, meta.source_release) {
+                match (entry.get().0.source_release, meta.source_release) {
                     // Error if dupe is same version
                     (prev, curr) if prev == curr => {
                         return Err(Error::DuplicateRelease(meta.name.clone(), meta.source_release));
                     }
                     // Update if dupe is newer version
                     (prev, curr) if prev < curr => {
-                        entry.insert(meta);
+                        entry.insert((meta, layouts));
                     }
                     // Otherwise prev is more recent, don't replace
                     _ => {}
@@ -74,16 +113,70 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
         }
     }
 
-    write_index(&dir, map, &total_progress)?;
+    // Only deltas producing an indexed package are published
+    deltas.sort_by(|(_, a), (_, b)| a.from.cmp(&b.from));
+    let mut published = vec![];
+    for (target, delta) in deltas {
+        if map.values().any(|(meta, _)| meta.hash.as_ref() == Some(&target)) {
+            published.push((target, delta));
+        } else {
+            multi_progress.println(format!(
+                "{} {} {}",
+                "Skipped".yellow(),
+                delta.uri.bold(),
+                "(package not in index)".dim()
+            ))?;
+        }
+    }
+
+    write_index(&dir, map.values().map(|(meta, _)| meta), &total_progress)?;
+
+    let files_path = dir.join("stone.files");
+
+    if with_files {
+        write_files(&files_path, map.values())?;
+    } else if files_path.exists() {
+        // Stale file list of a previous index
+        fs::remove_file(&files_path)?;
+    }
+
+    // Deltas are kept out of the index as older clients reject their records
+    let deltas_path = dir.join("stone.deltas");
+
+    if !published.is_empty() {
+        write_deltas(&deltas_path, &published)?;
+    } else if deltas_path.exists() {
+        fs::remove_file(&deltas_path)?;
+    }
+
+    let signature_path = sign(&dir.join("stone.index"), secret_key.as_ref())?;
+    sign(&files_path, secret_key.as_ref())?;
+    sign(&deltas_path, secret_key.as_ref())?;
 
     multi_progress.clear()?;
 
     println!("\nIndex file written to {:?}", dir.join("stone.index").display());
 
+    if with_files {
+        println!("File list written to {:?}", files_path.display());
+    }
+
+    if !published.is_empty() {
+        println!("{} deltas written to {:?}", published.len(), deltas_path.display());
+    }
+
+    if secret_key.is_some() {
+        println!("Index signature written to {:?}", signature_path.display());
+    }
+
     Ok(())
 }
 
-fn write_index(dir: &Path, map: BTreeMap<package::Name, Meta>, total_progress: &ProgressBar) -> Result<(), Error> {
+fn write_index<'a>(
+    dir: &Path,
+    metas: impl Iterator<Item = &'a Meta>,
+    total_progress: &ProgressBar,
+) -> Result<(), Error> {
     total_progress.set_message("Writing index file");
     total_progress.set_style(
         ProgressStyle::with_template("\n {spinner} {wide_msg}")
@@ -96,9 +189,26 @@ fn write_index(dir: &Path, map: BTreeMap<package::Name, Meta>, total_progress: &
 
     let mut writer = stone::Writer::new(&mut file, stone::header::v1::FileType::Repository)?;
 
-    for (_, meta) in map {
-        let payload = meta.to_stone_payload();
+    for meta in metas {
+        let payload = meta.clone().to_stone_payload();
+        writer.add_payload(payload.as_slice())?;
+    }
+
+    writer.finalize()?;
+
+    Ok(())
+}
+
+/// Write the file list of every package, as a meta payload followed by its layout payload
+fn write_files<'a>(path: &Path, packages: impl Iterator<Item = &'a (Meta, Vec<Layout>)>) -> Result<(), Error> {
+    let mut file = fs::File::create(path)?;
+
+    let mut writer = stone::Writer::new(&mut file, stone::header::v1::FileType::Repository)?;
+
+    for (meta, layouts) in packages {
+        let payload = meta.clone().to_stone_payload();
         writer.add_payload(payload.as_slice())?;
+        writer.add_payload(layouts.as_slice())?;
     }
 
     writer.finalize()?;
@@ -106,12 +216,57 @@ fn write_index(dir: &Path, map: BTreeMap<package::Name, Meta>, total_progress: &
     Ok(())
 }
 
+/// Write the deltas to each package, as a meta payload per delta
+fn write_deltas(path: &Path, deltas: &[(String, Delta)]) -> Result<(), Error> {
+    let mut file = fs::File::create(path)?;
+
+    let mut writer = stone::Writer::new(&mut file, stone::header::v1::FileType::Repository)?;
+
+    for (target, delta) in deltas {
+        let payload = delta.clone().to_stone_payload(target.clone());
+        writer.add_payload(payload.as_slice())?;
+    }
+
+    writer.finalize()?;
+
+    Ok(())
+}
+
+/// Write the detached signature of the file at `path` with `secret_key`, or remove
+/// a stale one if there's no key or file, returning the path of the signature
+fn sign(path: &Path, secret_key: Option<&SecretKey>) -> Result<PathBuf, Error> {
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(format!(".{}", key::SIGNATURE_EXTENSION));
+    let signature_path = PathBuf::from(signature_path);
+
+    match secret_key {
+        Some(secret_key) if path.exists() => {
+            let signature = secret_key.sign(&fs::read(path)?);
+            fs::write(&signature_path, key::encode_signature(&signature))?;
+        }
+        _ if signature_path.exists() => fs::remove_file(&signature_path)?,
+        _ => {}
+    }
+
+    Ok(signature_path)
+}
+
+/// A stone file found in the index directory
+enum Indexed {
+    /// A package, along with its layouts if requested
+    Package(Box<Meta>, Vec<Layout>),
+    /// A delta to the package with the given hash
+    Delta(String, Delta),
+}
+
+/// Read the meta of a package, along with its layouts if `with_files` is set
 fn get_meta(
     path: &Path,
     dir: &Path,
+    with_files: bool,
     multi_progress: &MultiProgress,
     total_progress: &ProgressBar,
-) -> Result<Meta, Error> {
+) -> Result<Indexed, Error> {
     let relative_path = format!("{}", path.strip_prefix(dir)?.display());
 
     let progress = multi_progress.insert_before(total_progress, ProgressBar::new_spinner());
@@ -128,6 +283,7 @@ fn get_meta(
 
     let mut file = fs::File::open(path)?;
     let mut reader = stone::read(&mut file)?;
+    let file_type = reader.header.file_type();
     let payloads = reader.payloads()?.collect::<Result<Vec<_>, _>>()?;
 
     let payload = payloads
@@ -135,17 +291,55 @@ fn get_meta(
         .find_map(|payload| payload.meta())
         .ok_or(Error::MissingMetaPayload)?;
 
+    let finish = |kind: &str| -> Result<(), Error> {
+        progress.finish();
+        multi_progress.remove(&progress);
+        multi_progress.println(format!("{} {}", kind.green(), relative_path.clone().bold()))?;
+        total_progress.inc(1);
+        Ok(())
+    };
+
+    if file_type == FileType::Delta {
+        let find = |tag| {
+            payload.body.iter().find_map(|record| match &record.kind {
+                meta::Kind::String(value) if record.tag == tag => Some(value.clone()),
+                _ => None,
+            })
+        };
+        let from = find(meta::Tag::DeltaFrom).ok_or(MissingMetaFieldError(meta::Tag::DeltaFrom))?;
+        let target = find(meta::Tag::PackageHash).ok_or(MissingMetaFieldError(meta::Tag::PackageHash))?;
+
+        finish("Indexed delta")?;
+
+        return Ok(Indexed::Delta(
+            target,
+            Delta {
+                from,
+                uri: relative_path,
+                hash,
+                size,
+            },
+        ));
+    }
+
     let mut meta = Meta::from_stone_payload(&payload.body)?;
     meta.hash = Some(hash);
     meta.download_size = Some(size);
     meta.uri = Some(relative_path.clone());
 
-    progress.finish();
-    multi_progress.remove(&progress);
-    multi_progress.println(format!("{} {}", "Indexed".green(), relative_path.bold()))?;
-    total_progress.inc(1);
+    let layouts = if with_files {
+        payloads
+            .iter()
+            .find_map(|payload| payload.layout())
+            .map(|payload| payload.body.clone())
+            .unwrap_or_default()
+    } else {
+        vec![]
+    };
+
+    finish("Indexed")?;
 
-    Ok(meta)
+    Ok(Indexed::Package(Box::new(meta), layouts))
 }
 
 fn stat_file(path: &Path, relative_path: &str, progress: &ProgressBar) -> Result<(u64, String), Error> {
@@ -211,4 +405,7 @@ pub enum Error {
 
     #[error("client")]
     Client(#[from] client::Error),
+
+    #[error("signing key")]
+    Key(#[from] key::Error),
 }
@@ -10,11 +10,14 @@ use moss::{
     package::Flags,
     Installation, Package, Provider,
 };
+use serde::Serialize;
 use stone::payload::layout;
 use thiserror::Error;
 use tui::Styled;
 use vfs::tree::BlitFile;
 
+use super::format::{self, Format};
+
 const COLUMN_WIDTH: usize = 20;
 
 pub fn command() -> Command {
@@ -35,8 +38,12 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         .collect::<Vec<_>>();
     let show_files = args.get_flag("files");
 
+    let format = Format::get(args);
+
     let client = Client::new(environment::NAME, installation)?;
 
+    let mut output = vec![];
+
     for pkg in pkgs {
         let lookup = Provider::from_name(&pkg).unwrap();
         let resolved = client
@@ -47,19 +54,55 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
             return Err(Error::NotFound(pkg));
         }
         for candidate in resolved {
-            print_package(&candidate);
+            let files = if candidate.flags.installed && show_files {
+                Some(files(client.vfs([&candidate.id])?))
+            } else {
+                None
+            };
+
+            if format.is_some() {
+                output.push(Info {
+                    package: candidate,
+                    files,
+                });
+                continue;
+            }
 
-            if candidate.flags.installed && show_files {
-                let vfs = client.vfs([&candidate.id])?;
-                print_files(vfs);
+            print_package(&candidate);
+            if let Some(files) = files {
+                print_files(files);
             }
             println!();
         }
     }
 
+    if let Some(format) = format {
+        format.print(&output)?;
+    }
+
     Ok(())
 }
 
+/// A queried package, as printed with `--format`
+#[derive(Debug, Serialize)]
+struct Info {
+    #[serde(flatten)]
+    package: Package,
+    /// Only present when `--files` is requested for an installed package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<File>>,
+}
+
+/// A file provided by an installed package
+#[derive(Debug, Serialize)]
+struct File {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+}
+
 /// Print the title for each metadata section
 fn print_titled(title: &'static str) {
     let display_width = COLUMN_WIDTH - title.len();
@@ -101,33 +144,43 @@ fn print_package(pkg: &Package) {
     }
 }
 
-fn print_files(vfs: vfs::Tree<client::PendingFile>) {
-    let files = vfs
-        .iter()
+/// All non-directory files of the package vfs
+fn files(vfs: vfs::Tree<client::PendingFile>) -> Vec<File> {
+    vfs.iter()
         .filter_map(|file| {
             if matches!(file.kind(), vfs::tree::Kind::Directory) {
                 return None;
             }
 
-            let path = file.path();
-            let meta = match file.layout.entry {
-                layout::Entry::Regular(hash, _) => Some(format!(" ({hash:2x})")),
-                layout::Entry::Symlink(source, _) => Some(format!(" -> {source}")),
-                _ => None,
+            let (hash, target) = match &file.layout.entry {
+                layout::Entry::Regular(hash, _) => (Some(format!("{hash:2x}")), None),
+                layout::Entry::Symlink(source, _) => (None, Some(source.clone())),
+                _ => (None, None),
             };
 
-            Some((path, meta))
+            Some(File {
+                path: file.path(),
+                hash,
+                target,
+            })
         })
-        .collect::<Vec<_>>();
+        .collect()
+}
 
+fn print_files(files: Vec<File>) {
     if files.is_empty() {
         return;
     }
 
     print_titled("Files");
     println!();
-    for (path, meta) in files {
-        println!("  {path}{}", meta.unwrap_or_default().dim());
+    for file in files {
+        let meta = match (file.hash, file.target) {
+            (Some(hash), _) => format!(" ({hash})"),
+            (_, Some(target)) => format!(" -> {target}"),
+            _ => String::new(),
+        };
+        println!("  {}{}", file.path, meta.dim());
     }
 }
 
@@ -137,4 +190,6 @@ pub enum Error {
     NotFound(String),
     #[error("client")]
     Client(#[from] client::Error),
+    #[error("format")]
+    Format(#[from] format::Error),
 }
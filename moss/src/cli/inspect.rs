@@ -3,6 +3,8 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use clap::{arg, ArgMatches, Command};
+use nix::sys::stat::{major, minor};
+use serde::Serialize;
 use std::fs::File;
 use std::path::PathBuf;
 use stone::payload::layout;
@@ -10,6 +12,8 @@ use stone::payload::meta;
 use stone::read::PayloadKind;
 use thiserror::Error;
 
+use super::format::{self, Format};
+
 const COLUMN_WIDTH: usize = 20;
 
 pub fn command() -> Command {
@@ -30,6 +34,12 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
         .cloned()
         .collect::<Vec<_>>();
 
+    if let Some(format) = Format::get(args) {
+        let stones = paths.into_iter().map(Stone::read).collect::<Result<Vec<_>, _>>()?;
+        format.print(&stones)?;
+        return Ok(());
+    }
+
     // Process each input path in order.
     for path in paths {
         let mut file = File::open(&path)?;
@@ -115,7 +125,18 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
                         layout::Entry::Symlink(source, target) => {
                             println!("    - /usr/{} -> {} [Symlink]", target, source)
                         }
-                        _ => unreachable!(),
+                        layout::Entry::CharacterDevice(device, target) => {
+                            println!("    - /usr/{} {} [Character device]", target, device_number(device))
+                        }
+                        layout::Entry::BlockDevice(device, target) => {
+                            println!("    - /usr/{} {} [Block device]", target, device_number(device))
+                        }
+                        layout::Entry::Fifo(target) => {
+                            println!("    - /usr/{} [Fifo]", target)
+                        }
+                        layout::Entry::Socket(target) => {
+                            println!("    - /usr/{} [Socket]", target)
+                        }
                     };
                 }
             }
@@ -125,6 +146,126 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
+/// A stone file, as printed with `--format`
+#[derive(Debug, Serialize)]
+struct Stone {
+    path: PathBuf,
+    version: String,
+    meta: Vec<MetaRecord>,
+    layout: Vec<LayoutRecord>,
+}
+
+#[derive(Debug, Serialize)]
+struct MetaRecord {
+    tag: String,
+    value: MetaValue,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum MetaValue {
+    Int(i64),
+    Uint(u64),
+    String(String),
+}
+
+#[derive(Debug, Serialize)]
+struct LayoutRecord {
+    path: String,
+    kind: &'static str,
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+}
+
+impl Stone {
+    fn read(path: PathBuf) -> Result<Self, Error> {
+        let mut file = File::open(&path)?;
+        let mut reader = stone::read(&mut file)?;
+
+        let version = format!("{:?}", reader.header.version());
+        let mut meta = vec![];
+        let mut layout = vec![];
+
+        for payload in reader.payloads()? {
+            match payload? {
+                PayloadKind::Meta(payload) => meta.extend(payload.body.into_iter().map(MetaRecord::from)),
+                PayloadKind::Layout(payload) => layout.extend(payload.body.into_iter().map(LayoutRecord::from)),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            path,
+            version,
+            meta,
+            layout,
+        })
+    }
+}
+
+impl From<meta::Meta> for MetaRecord {
+    fn from(record: meta::Meta) -> Self {
+        let value = match record.kind {
+            meta::Kind::Int8(i) => MetaValue::Int(i.into()),
+            meta::Kind::Int16(i) => MetaValue::Int(i.into()),
+            meta::Kind::Int32(i) => MetaValue::Int(i.into()),
+            meta::Kind::Int64(i) => MetaValue::Int(i),
+            meta::Kind::Uint8(i) => MetaValue::Uint(i.into()),
+            meta::Kind::Uint16(i) => MetaValue::Uint(i.into()),
+            meta::Kind::Uint32(i) => MetaValue::Uint(i.into()),
+            meta::Kind::Uint64(i) => MetaValue::Uint(i),
+            meta::Kind::String(s) => MetaValue::String(s),
+            meta::Kind::Dependency(k, d) | meta::Kind::Provider(k, d) => MetaValue::String(format!("{k}({d})")),
+        };
+
+        Self {
+            tag: format!("{:?}", record.tag),
+            value,
+        }
+    }
+}
+
+impl From<layout::Layout> for LayoutRecord {
+    fn from(record: layout::Layout) -> Self {
+        let (kind, target, hash, source, device) = match record.entry {
+            layout::Entry::Regular(hash, target) => ("regular", target, Some(format!("{hash:032x}")), None, None),
+            layout::Entry::Symlink(source, target) => ("symlink", target, None, Some(source), None),
+            layout::Entry::Directory(target) => ("directory", target, None, None, None),
+            layout::Entry::CharacterDevice(device, target) => {
+                ("character-device", target, None, None, Some(device_number(device)))
+            }
+            layout::Entry::BlockDevice(device, target) => {
+                ("block-device", target, None, None, Some(device_number(device)))
+            }
+            layout::Entry::Fifo(target) => ("fifo", target, None, None, None),
+            layout::Entry::Socket(target) => ("socket", target, None, None, None),
+        };
+
+        Self {
+            path: format!("/usr/{target}"),
+            kind,
+            uid: record.uid,
+            gid: record.gid,
+            mode: record.mode,
+            hash,
+            source,
+            device,
+        }
+    }
+}
+
+/// Format a device number as `major:minor`
+fn device_number(device: u64) -> String {
+    format!("{}:{}", major(device), minor(device))
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("io")]
@@ -132,4 +273,7 @@ pub enum Error {
 
     #[error("stone format")]
     Format(#[from] stone::read::Error),
+
+    #[error("output format")]
+    Output(#[from] format::Error),
 }
@@ -5,16 +5,24 @@
 use std::path::PathBuf;
 
 use clap::{arg, value_parser, ArgMatches, Command};
-use moss::{client::Client, environment, Installation};
+use moss::{
+    client::{self, install, Client},
+    environment, Installation,
+};
+use thiserror::Error;
 
-pub use moss::client::install::Error;
+use super::format::{self, Format};
 
 pub fn command() -> Command {
     Command::new("install")
         .visible_alias("it")
         .about("Install packages")
-        .long_about("Install the requested software to the local system")
-        .arg(arg!(<NAME> ... "packages to install").value_parser(value_parser!(String)))
+        .long_about(
+            "Install the requested software to the local system\n\n\
+             Local `.stone` files may be given by path and are installed in place of any \
+             package with the same name, with dependencies resolved from the configured repositories",
+        )
+        .arg(arg!(<NAME> ... "packages or local .stone files to install").value_parser(value_parser!(String)))
         .arg(
             arg!(--to <blit_target> "Blit this install to the provided directory instead of the root")
                 .long_help(
@@ -24,6 +32,10 @@ pub fn command() -> Command {
                 )
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(
+            arg!(--"dry-run" "Print the plan without fetching or installing anything")
+                .long_help("Print the plan without fetching or installing anything, implied by --format"),
+        )
 }
 
 /// Handle execution of `moss install`
@@ -34,17 +46,40 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         .flatten()
         .map(String::as_str)
         .collect::<Vec<_>>();
-    let yes = *args.get_one::<bool>("yes").unwrap();
+    let format = Format::get(args);
+    let options = install::Options {
+        yes: *args.get_one::<bool>("yes").unwrap(),
+        dry_run: *args.get_one::<bool>("dry-run").unwrap() || format.is_some(),
+    };
 
-    // Grab a client for the root
-    let mut client = Client::new(environment::NAME, installation)?;
+    // Grab a client for the root, a dry run never modifies it
+    let mut client = Client::new(environment::NAME, installation.with_exclusive_lock(!options.dry_run))?;
 
     // Make ephemeral if a blit target was provided
     if let Some(blit_target) = args.get_one::<PathBuf>("to").cloned() {
         client = client.ephemeral(blit_target)?;
     }
 
-    client.install(&pkgs, yes)?;
+    let outcome = client.install(&pkgs, options)?;
+
+    if options.dry_run {
+        match format {
+            Some(format) => format.print(&outcome.plan)?,
+            None => outcome.plan.print(),
+        }
+    }
 
     Ok(())
 }
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error(transparent)]
+    Install(#[from] install::Error),
+
+    #[error("format")]
+    Format(#[from] format::Error),
+}
@@ -4,16 +4,19 @@
 
 use clap::{arg, ArgMatches, Command};
 use itertools::Itertools;
+use serde::Serialize;
 use thiserror::Error;
 
 use moss::{
     client::{self, Client},
     environment,
     package::Flags,
-    Installation,
+    Installation, Package,
 };
 use tui::Styled;
 
+use super::format::{self, Format};
+
 pub fn command() -> Command {
     Command::new("list")
         .about("List packages")
@@ -71,24 +74,30 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     let client = Client::new(environment::NAME, installation)?;
     let pkgs = client.registry.list(filter_flags).collect::<Vec<_>>();
 
-    let sync_available = if sync.is_some() {
-        client.registry.list(Flags::new().with_available()).collect::<Vec<_>>()
+    let (sync_available, restrictions) = if sync.is_some() {
+        (
+            client.registry.list(Flags::new().with_available()).collect::<Vec<_>>(),
+            client.restrictions()?,
+        )
     } else {
-        vec![]
+        (vec![], Default::default())
     };
 
-    if pkgs.is_empty() {
-        return Err(Error::NoneFound);
-    }
+    let none_found = pkgs.is_empty();
 
-    // map to renderable state
-    let mut set = pkgs
+    // pair each package with its sync candidate
+    let mut entries = pkgs
         .into_iter()
         .map(|p| {
             let sync = sync_available
                 .iter()
-                // Get first (priority based)
-                .find(|u| u.meta.name == p.meta.name)
+                // Get first (priority based) not excluded by a hold
+                .find(|u| {
+                    u.meta.name == p.meta.name
+                        && restrictions
+                            .get(&u.meta.name)
+                            .is_none_or(|allowed| allowed.contains(&u.id))
+                })
                 // Ensure it's an upgrade (if `upgrades-only`)
                 // otherwise check if it's a change
                 .filter(|u| {
@@ -98,36 +107,51 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
                         u.meta.source_release != p.meta.source_release
                     }
                 })
-                .map(|u| Revision {
-                    version: u.meta.version_identifier.clone(),
-                    release: u.meta.source_release.to_string(),
-                });
-
-            Format {
-                name: p.meta.name.to_string(),
-                revision: Revision {
-                    version: p.meta.version_identifier,
-                    release: p.meta.source_release.to_string(),
-                },
-                summary: p.meta.summary,
-                explicit: if filter_flags == Flags::new().with_installed() {
-                    p.flags.explicit
-                } else {
-                    true
-                },
-                sync,
-            }
+                .cloned();
+
+            Entry { package: p, sync }
         })
-        .filter(|item| if sync.is_some() { item.sync.is_some() } else { true })
+        .filter(|entry| if sync.is_some() { entry.sync.is_some() } else { true })
         .collect_vec();
 
     // Thanks to priorities, first in list is the winning candidate in list available.
     // Therefore sort by name and dedupe is safe as we mask the lower priority items out.
-    set.sort_by_key(|s| s.name.clone());
-    set.dedup_by_key(|s| s.name.clone());
+    entries.sort_by_key(|e| e.package.meta.name.clone());
+    entries.dedup_by_key(|e| e.package.meta.name.clone());
+
+    if let Some(format) = Format::get(args) {
+        format.print(&entries)?;
+        return Ok(());
+    }
+
+    if none_found {
+        return Err(Error::NoneFound);
+    }
+
+    // map to renderable state
+    let set = entries
+        .into_iter()
+        .map(|Entry { package: p, sync }| Item {
+            name: p.meta.name.to_string(),
+            revision: Revision {
+                version: p.meta.version_identifier,
+                release: p.meta.source_release.to_string(),
+            },
+            summary: p.meta.summary,
+            explicit: if filter_flags == Flags::new().with_installed() {
+                p.flags.explicit
+            } else {
+                true
+            },
+            sync: sync.map(|u| Revision {
+                version: u.meta.version_identifier,
+                release: u.meta.source_release.to_string(),
+            }),
+        })
+        .collect_vec();
 
     // Grab maximum length
-    let max_length = set.iter().map(Format::size).max().unwrap_or_default();
+    let max_length = set.iter().map(Item::size).max().unwrap_or_default();
 
     // render
     for item in set {
@@ -163,8 +187,17 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     Ok(())
 }
 
+/// A listed package & its sync candidate, if any
+#[derive(Debug, Serialize)]
+struct Entry {
+    #[serde(flatten)]
+    package: Package,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sync: Option<Package>,
+}
+
 #[derive(Debug)]
-struct Format {
+struct Item {
     name: String,
     summary: String,
     revision: Revision,
@@ -172,7 +205,7 @@ struct Format {
     sync: Option<Revision>,
 }
 
-impl Format {
+impl Item {
     fn size(&self) -> usize {
         self.name.len() + self.revision.size() + self.sync.as_ref().map(Revision::size).unwrap_or_default()
     }
@@ -196,4 +229,6 @@ pub enum Error {
     NoneFound,
     #[error("client")]
     Client(#[from] client::Error),
+    #[error("format")]
+    Format(#[from] format::Error),
 }
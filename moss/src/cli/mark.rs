@@ -0,0 +1,105 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgMatches, Command};
+use moss::{
+    client::{self, Client},
+    environment,
+    package::Flags,
+    state, Installation, Provider,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("mark")
+        .about("Mark installed packages as explicit or transitive")
+        .long_about(
+            "Mark installed packages as explicitly installed, or as transitive dependencies \
+             which can be removed with `moss remove --orphans` once nothing depends on them",
+        )
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("explicit")
+                .about("Mark packages as explicitly installed")
+                .arg(arg!(<NAME> ... "packages to mark").value_parser(clap::value_parser!(String))),
+        )
+        .subcommand(
+            Command::new("transitive")
+                .about("Mark packages as transitive dependencies")
+                .arg(arg!(<NAME> ... "packages to mark").value_parser(clap::value_parser!(String))),
+        )
+}
+
+/// Handle execution of `moss mark`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let (explicit, args) = match args.subcommand() {
+        Some(("explicit", args)) => (true, args),
+        Some(("transitive", args)) => (false, args),
+        _ => unreachable!(),
+    };
+    let pkgs = args
+        .get_many::<String>("NAME")
+        .into_iter()
+        .flatten()
+        .map(|name| Provider::from_name(name).map_err(|_| Error::NotInstalled(name.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut client = Client::new(environment::NAME, installation.with_exclusive_lock(true))?;
+    client.prepare_mutation()?;
+
+    let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
+
+    let marked = pkgs
+        .iter()
+        .map(|provider| {
+            installed
+                .iter()
+                .find(|i| i.meta.providers.contains(provider))
+                .ok_or_else(|| Error::NotInstalled(provider.name.clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let Some(active) = client.installation.active_state else {
+        return Err(Error::NoActiveState);
+    };
+    let mut selections = client.state_db.get(active)?.selections;
+
+    let ids = marked.iter().map(|package| package.id.clone()).collect::<Vec<_>>();
+    let changed = state::mark(&mut selections, &ids, explicit);
+    let kind = if explicit { "explicit" } else { "transitive" };
+
+    // Nothing to do if every package is already marked as requested
+    if changed.is_empty() {
+        println!("No packages to mark as {kind}");
+        return Ok(());
+    }
+
+    client.new_state(&selections, format!("Mark {kind}"))?;
+
+    for package in marked.iter().filter(|package| changed.contains(&package.id)) {
+        println!(
+            "{} {} as {kind}",
+            "Marked".green(),
+            package.meta.name.to_string().bold()
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("package not installed: {0}")]
+    NotInstalled(String),
+
+    #[error("no active state")]
+    NoActiveState,
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+}
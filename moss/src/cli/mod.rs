@@ -5,21 +5,32 @@
 use std::{env, path::PathBuf};
 
 use clap::{Arg, ArgAction, Command};
-use moss::{installation, runtime, Installation};
+use moss::{environment, installation, runtime, Installation};
 use thiserror::Error;
 
+mod delta;
 mod extract;
+mod format;
+mod hold;
 mod index;
 mod info;
 mod inspect;
 mod install;
 mod list;
+mod mark;
+mod rdeps;
 mod remove;
+mod repair;
 mod repo;
 mod search;
+mod search_file;
 mod state;
 mod sync;
+mod trigger;
+mod unhold;
+mod verify;
 mod version;
+mod why;
 
 /// Generate the CLI command structure
 fn command() -> Command {
@@ -57,19 +68,59 @@ fn command() -> Command {
                 .help("Assume yes for all questions")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("wait")
+                .long("wait")
+                .global(true)
+                .help("Wait for other moss processes to release the installation instead of failing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("fail-on-trigger-error")
+                .long("fail-on-trigger-error")
+                .global(true)
+                .help("Abort the transaction when a trigger fails, instead of recording a warning")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .global(true)
+                .help("Print query results, or the plan of a transaction without applying it, in a machine-readable format")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(format::Format)),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .global(true)
+                .help("Number of times to retry a failed download")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(u32).range(..=environment::MAX_DOWNLOAD_RETRIES as i64)),
+        )
         .arg_required_else_help(true)
+        .subcommand(delta::command())
         .subcommand(extract::command())
+        .subcommand(hold::command())
         .subcommand(index::command())
         .subcommand(info::command())
         .subcommand(inspect::command())
         .subcommand(install::command())
         .subcommand(list::command())
+        .subcommand(mark::command())
+        .subcommand(rdeps::command())
         .subcommand(remove::command())
+        .subcommand(repair::command())
         .subcommand(repo::command())
         .subcommand(search::command())
+        .subcommand(search_file::command())
         .subcommand(state::command())
         .subcommand(sync::command())
+        .subcommand(trigger::command())
+        .subcommand(unhold::command())
+        .subcommand(verify::command())
         .subcommand(version::command())
+        .subcommand(why::command())
 }
 
 /// Process all CLI arguments
@@ -88,23 +139,41 @@ pub fn process() -> Result<(), Error> {
     // Make async runtime available to all of moss
     let _guard = runtime::init();
 
-    let mut installation = Installation::open(root)?;
+    let mut installation = Installation::open(root)?
+        .with_lock_wait(matches.get_flag("wait"))
+        .with_fail_on_trigger_error(matches.get_flag("fail-on-trigger-error"))
+        .with_download_retries(
+            matches
+                .get_one::<u32>("retries")
+                .copied()
+                .unwrap_or(environment::DOWNLOAD_RETRIES),
+        );
     if let Some(dir) = cache {
         installation = installation.with_cache_dir(dir)?;
     }
 
     match matches.subcommand() {
+        Some(("delta", args)) => delta::handle(args).map_err(Error::Delta),
         Some(("extract", args)) => extract::handle(args).map_err(Error::Extract),
+        Some(("hold", args)) => hold::handle(args, installation).map_err(Error::Hold),
         Some(("index", args)) => index::handle(args).map_err(Error::Index),
         Some(("info", args)) => info::handle(args, installation).map_err(Error::Info),
         Some(("inspect", args)) => inspect::handle(args).map_err(Error::Inspect),
         Some(("install", args)) => install::handle(args, installation).map_err(Error::Install),
         Some(("list", args)) => list::handle(args, installation).map_err(Error::List),
+        Some(("mark", args)) => mark::handle(args, installation).map_err(Error::Mark),
+        Some(("rdeps", args)) => rdeps::handle(args, installation).map_err(Error::Rdeps),
         Some(("remove", args)) => remove::handle(args, installation).map_err(Error::Remove),
+        Some(("repair", args)) => repair::handle(args, installation).map_err(Error::Repair),
         Some(("repo", args)) => repo::handle(args, installation).map_err(Error::Repo),
         Some(("search", args)) => search::handle(args, installation).map_err(Error::Search),
+        Some(("search-file", args)) => search_file::handle(args, installation).map_err(Error::SearchFile),
         Some(("state", args)) => state::handle(args, installation).map_err(Error::State),
         Some(("sync", args)) => sync::handle(args, installation).map_err(Error::Sync),
+        Some(("trigger", args)) => trigger::handle(args, installation).map_err(Error::Trigger),
+        Some(("unhold", args)) => unhold::handle(args, installation).map_err(Error::Unhold),
+        Some(("verify", args)) => verify::handle(args, installation).map_err(Error::Verify),
+        Some(("why", args)) => why::handle(args, installation).map_err(Error::Why),
         Some(("version", _)) => {
             version::print();
             Ok(())
@@ -130,6 +199,8 @@ fn replace_aliases(args: env::Args) -> Vec<String> {
         ("ix", &["index"]),
         ("it", &["install"]),
         ("rm", &["remove"]),
+        ("autoremove", &["remove", "--orphans"]),
+        ("apply", &["state", "import"]),
         ("up", &["sync"]),
     ];
 
@@ -150,6 +221,12 @@ fn replace_aliases(args: env::Args) -> Vec<String> {
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("delta")]
+    Delta(#[from] delta::Error),
+
+    #[error("hold")]
+    Hold(#[from] hold::Error),
+
     #[error("index")]
     Index(#[from] index::Error),
 
@@ -162,27 +239,51 @@ pub enum Error {
     #[error("list")]
     List(#[from] list::Error),
 
+    #[error("mark")]
+    Mark(#[from] mark::Error),
+
     #[error("inspect")]
     Inspect(#[from] inspect::Error),
 
     #[error("extract")]
     Extract(#[from] extract::Error),
 
+    #[error("rdeps")]
+    Rdeps(#[from] rdeps::Error),
+
     #[error("remove")]
     Remove(#[from] remove::Error),
 
+    #[error("repair")]
+    Repair(#[from] repair::Error),
+
     #[error("repo")]
     Repo(#[from] repo::Error),
 
     #[error("search")]
     Search(#[from] search::Error),
 
+    #[error("search-file")]
+    SearchFile(#[from] search_file::Error),
+
     #[error("state")]
     State(#[from] state::Error),
 
     #[error("sync")]
     Sync(#[from] sync::Error),
 
+    #[error("trigger")]
+    Trigger(#[from] trigger::Error),
+
+    #[error("unhold")]
+    Unhold(#[from] unhold::Error),
+
+    #[error("verify")]
+    Verify(#[from] verify::Error),
+
+    #[error("why")]
+    Why(#[from] why::Error),
+
     #[error("installation")]
     Installation(#[from] installation::Error),
 }
@@ -0,0 +1,99 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::BTreeSet;
+
+use clap::{arg, ArgMatches, Command};
+use moss::{
+    client::{self, Client},
+    dependency, environment,
+    package::Flags,
+    Installation, Provider,
+};
+use thiserror::Error;
+use tui::pretty::autoprint_columns;
+
+use super::format::{self, Format};
+
+pub fn command() -> Command {
+    Command::new("rdeps")
+        .about("List reverse dependencies")
+        .long_about(
+            "List packages depending on a package or provider, such as \n\
+             `nano`, `soname(libc.so.6(x86_64))` or `pkgconfig(zlib)` \n\
+             \n\
+             For a package name, dependencies on any of its providers are included",
+        )
+        .arg(arg!(<NAME> "package or provider").value_parser(clap::value_parser!(String)))
+        .arg(arg!(-a --available "Search available packages instead of installed ones"))
+        .arg(arg!(--dot "Print the reverse dependencies as a DOT graph"))
+}
+
+/// Handle execution of `moss rdeps`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let name = args.get_one::<String>("NAME").unwrap();
+    let available = *args.get_one::<bool>("available").unwrap();
+    let dot = *args.get_one::<bool>("dot").unwrap();
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let flags = if available {
+        Flags::new().with_available()
+    } else {
+        Flags::new().with_installed()
+    };
+    let mut packages = client.registry.list(flags).collect::<Vec<_>>();
+    packages.sort_by(|a, b| a.meta.name.cmp(&b.meta.name));
+    packages.dedup_by(|a, b| a.id == b.id);
+
+    let provider = Provider::from_name(name).map_err(|_| Error::InvalidProvider(name.clone()))?;
+
+    // A package name also stands for everything that package provides
+    let mut providers = BTreeSet::from([provider.clone()]);
+    if provider.kind == dependency::Kind::PackageName {
+        providers.extend(
+            packages
+                .iter()
+                .filter(|p| p.meta.name.as_ref() == &provider.name)
+                .flat_map(|p| p.meta.providers.iter().cloned()),
+        );
+    }
+
+    let dependents = packages
+        .into_iter()
+        .filter(|p| !p.meta.providers.contains(&provider))
+        .filter(|p| {
+            p.meta
+                .dependencies
+                .iter()
+                .any(|d| providers.iter().any(|p| p.kind == d.kind && p.name == d.name))
+        })
+        .collect::<Vec<_>>();
+
+    if dot {
+        format::print_dot(
+            dependents
+                .iter()
+                .map(|p| (p.meta.name.as_ref().as_str(), name.as_str())),
+        );
+    } else if let Some(format) = Format::get(args) {
+        format.print(&dependents)?;
+    } else if !dependents.is_empty() {
+        autoprint_columns(&dependents);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid provider: {0}")]
+    InvalidProvider(String),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("output")]
+    Output(#[from] format::Error),
+}
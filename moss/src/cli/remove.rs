@@ -2,12 +2,14 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::HashSet;
-
 use clap::{arg, ArgMatches, Command};
 use itertools::{Either, Itertools};
 use moss::{
-    client::{self, Client},
+    client::{
+        self,
+        remove::{self, Removal},
+        Client,
+    },
     environment,
     package::Flags,
     registry::transaction,
@@ -21,12 +23,23 @@ use tui::{
     Styled,
 };
 
+use super::format::{self, Format};
+
 pub fn command() -> Command {
     Command::new("remove")
         .visible_alias("rm")
         .about("Remove packages")
-        .long_about("Remove packages by name")
-        .arg(arg!(<NAME> ... "packages to install").value_parser(clap::value_parser!(String)))
+        .long_about("Remove packages by name, along with packages depending on them")
+        .arg(
+            arg!([NAME] ... "packages to remove")
+                .value_parser(clap::value_parser!(String))
+                .required_unless_present("orphans"),
+        )
+        .arg(arg!(--orphans "Also remove transitive packages no explicit package depends on"))
+        .arg(
+            arg!(--"dry-run" "Print the plan without removing anything")
+                .long_help("Print the plan without removing anything, implied by --format"),
+        )
 }
 
 /// Handle execution of `moss remove`
@@ -38,12 +51,18 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         .map(|name| Provider::from_name(name).unwrap())
         .collect::<Vec<_>>();
     let yes = *args.get_one::<bool>("yes").unwrap();
+    let format = Format::get(args);
+    let dry_run = *args.get_one::<bool>("dry-run").unwrap() || format.is_some();
+    let orphans = *args.get_one::<bool>("orphans").unwrap();
 
     // Grab a client for the target, enumerate packages
-    let client = Client::new(environment::NAME, installation)?;
+    // A dry run never modifies the installation
+    let mut client = Client::new(environment::NAME, installation.with_exclusive_lock(!dry_run))?;
+    if !dry_run {
+        client.prepare_mutation()?;
+    }
 
     let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
-    let installed_ids = installed.iter().map(|p| p.id.clone()).collect::<HashSet<_>>();
 
     // Separate packages between installed / not installed (or invalid)
     let (for_removal, not_installed): (Vec<_>, Vec<_>) = pkgs.iter().partition_map(|provider| {
@@ -61,24 +80,29 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         return Err(Error::NotImplemented);
     }
 
-    // Add all installed packages to transaction
-    let mut transaction = client
-        .registry
-        .transaction_with_installed(installed_ids.clone().into_iter().collect())?;
-
-    // Remove all pkgs for removal
-    transaction.remove(for_removal);
-
-    // Finalized tx has all reverse deps removed
-    let finalized = transaction.finalize().cloned().collect::<HashSet<_>>();
-
-    // Resolve all removed packages, where removed is (installed - finalized)
-    let removed = client.resolve_packages(installed_ids.difference(&finalized))?;
+    let Removal {
+        remaining: finalized,
+        removed,
+        plan,
+    } = remove::plan(&client.registry, &installed, &for_removal, orphans)?;
+
+    if let Some(format) = format {
+        format.print(&plan)?;
+    } else if removed.is_empty() {
+        println!("No packages to remove");
+    } else if dry_run {
+        plan.print();
+    } else {
+        println!("The following package(s) will be removed:");
+        println!();
+        autoprint_columns(&removed);
+        println!();
+    }
 
-    println!("The following package(s) will be removed:");
-    println!();
-    autoprint_columns(&removed);
-    println!();
+    // Nothing to do, or only the plan was requested
+    if removed.is_empty() || dry_run {
+        return Ok(());
+    }
 
     let result = if yes {
         true
@@ -153,6 +177,9 @@ pub enum Error {
     #[error("io")]
     Io(#[from] std::io::Error),
 
+    #[error("format")]
+    Format(#[from] format::Error),
+
     #[error("string processing")]
     Dialog(#[from] tui::dialoguer::Error),
 }
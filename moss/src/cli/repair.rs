@@ -0,0 +1,50 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{ArgMatches, Command};
+use moss::{
+    client::{self, Client},
+    environment, Installation,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("repair")
+        .about("Recover from an interrupted transaction")
+        .long_about(
+            "Recover from an interrupted transaction \n\
+             \n\
+             If the new state was already promoted the transaction is completed, \n\
+             otherwise it's rolled back to the previously active state",
+        )
+}
+
+/// Handle execution of `moss repair`
+pub fn handle(_args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let mut client = Client::new(environment::NAME, installation.with_exclusive_lock(true))?;
+
+    match client.repair()? {
+        Some(recovery) => println!("Repaired: {recovery}"),
+        None => println!("No interrupted transaction found"),
+    }
+
+    // Sanity check the live tree against our records
+    if let Some(id) = client.installation.active_state {
+        if client.state_db.get(id).is_err() {
+            println!(
+                "{} active state {id} isn't recorded in the state database",
+                "Warning:".yellow()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+}
@@ -2,27 +2,46 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::process;
+use std::{
+    path::{Path, PathBuf},
+    process,
+};
 
 use clap::{arg, Arg, ArgAction, ArgMatches, Command};
 use itertools::Itertools;
 use moss::{
-    repository::{self, Priority},
+    installation,
+    repository::{
+        self,
+        key::{PublicKey, SecretKey},
+        Priority,
+    },
     runtime, Installation, Repository,
 };
+use serde::Serialize;
 use thiserror::Error;
 use url::Url;
 
+use super::format::{self, Format};
+
 /// Control flow for the subcommands
 enum Action {
-    // Root
-    List,
-    // Root, Id, Url, Comment
-    Add(String, Url, String, Priority),
+    // Root, Format
+    List(Option<Format>),
+    // Root, Id, Repository, Keys
+    Add(String, Repository, Vec<PublicKey>),
     // Root, Id
     Remove(String),
     // Root, Id
     Update(Option<String>),
+    // Root, Id, Key
+    AddKey(String, PublicKey),
+    // Root, Id
+    ListKeys(Option<String>),
+    // Root, Id, Key
+    RemoveKey(String, PublicKey),
+    // Path
+    GenerateKey(PathBuf),
 }
 
 /// Return a command for handling `repo` subcommands
@@ -36,6 +55,22 @@ pub fn command() -> Command {
                 .visible_alias("ar")
                 .arg(arg!(<NAME> "repo name").value_parser(clap::value_parser!(String)))
                 .arg(arg!(<URI> "repo uri").value_parser(clap::value_parser!(Url)))
+                .arg(
+                    Arg::new("mirror")
+                        .short('m')
+                        .long("mirror")
+                        .action(ArgAction::Append)
+                        .help("Fallback uri for the repository, can be passed multiple times")
+                        .value_parser(clap::value_parser!(Url)),
+                )
+                .arg(
+                    Arg::new("key")
+                        .short('k')
+                        .long("key")
+                        .action(ArgAction::Append)
+                        .help("Public key trusted to sign the repository index, can be passed multiple times")
+                        .value_parser(clap::value_parser!(PublicKey)),
+                )
                 .arg(
                     Arg::new("comment")
                         .short('c')
@@ -72,6 +107,42 @@ pub fn command() -> Command {
                 .long_about("If no repository is named, update them all")
                 .arg(arg!([NAME] "repo name").value_parser(clap::value_parser!(String))),
         )
+        .subcommand(
+            Command::new("key")
+                .about("Manage trusted repository keys")
+                .long_about(
+                    "Manage the public keys trusted to sign repository indexes. \n\
+                     \n\
+                     Repositories with trusted keys refuse to refresh from an unsigned or badly signed index",
+                )
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Trust a key for a repository")
+                        .arg(arg!(<NAME> "repo name").value_parser(clap::value_parser!(String)))
+                        .arg(arg!(<KEY> "hex encoded public key").value_parser(clap::value_parser!(PublicKey))),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List trusted keys")
+                        .arg(arg!([NAME] "repo name").value_parser(clap::value_parser!(String))),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Stop trusting a key for a repository")
+                        .arg(arg!(<NAME> "repo name").value_parser(clap::value_parser!(String)))
+                        .arg(arg!(<KEY> "hex encoded public key").value_parser(clap::value_parser!(PublicKey))),
+                )
+                .subcommand(
+                    Command::new("generate")
+                        .about("Generate a new signing key")
+                        .long_about(
+                            "Generate a new secret key for signing indexes with `moss index --sign` \n\
+                             and print its public key",
+                        )
+                        .arg(arg!(<PATH> "secret key output file").value_parser(clap::value_parser!(PathBuf))),
+                ),
+        )
 }
 
 /// Handle subcommands to `repo`
@@ -81,22 +152,58 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     let handler = match args.subcommand() {
         Some(("add", cmd_args)) => Action::Add(
             cmd_args.get_one::<String>("NAME").cloned().unwrap(),
-            cmd_args.get_one::<Url>("URI").cloned().unwrap(),
-            cmd_args.get_one::<String>("comment").cloned().unwrap(),
-            Priority::new(*cmd_args.get_one::<u64>("priority").unwrap()),
+            Repository {
+                description: cmd_args.get_one::<String>("comment").cloned().unwrap(),
+                uri: cmd_args.get_one::<Url>("URI").cloned().unwrap(),
+                mirrors: cmd_args
+                    .get_many::<Url>("mirror")
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect(),
+                priority: Priority::new(*cmd_args.get_one::<u64>("priority").unwrap()),
+            },
+            cmd_args
+                .get_many::<PublicKey>("key")
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect(),
         ),
-        Some(("list", _)) => Action::List,
+        Some(("list", cmd_args)) => Action::List(Format::get(cmd_args)),
         Some(("remove", cmd_args)) => Action::Remove(cmd_args.get_one::<String>("NAME").cloned().unwrap()),
         Some(("update", cmd_args)) => Action::Update(cmd_args.get_one::<String>("NAME").cloned()),
+        Some(("key", key_args)) => match key_args.subcommand() {
+            Some(("add", cmd_args)) => Action::AddKey(
+                cmd_args.get_one::<String>("NAME").cloned().unwrap(),
+                *cmd_args.get_one::<PublicKey>("KEY").unwrap(),
+            ),
+            Some(("list", cmd_args)) => Action::ListKeys(cmd_args.get_one::<String>("NAME").cloned()),
+            Some(("remove", cmd_args)) => Action::RemoveKey(
+                cmd_args.get_one::<String>("NAME").cloned().unwrap(),
+                *cmd_args.get_one::<PublicKey>("KEY").unwrap(),
+            ),
+            Some(("generate", cmd_args)) => Action::GenerateKey(cmd_args.get_one::<PathBuf>("PATH").cloned().unwrap()),
+            _ => unreachable!(),
+        },
         _ => unreachable!(),
     };
 
+    // Read-only actions share the installation, everything else needs it to ourselves
+    let read_only = matches!(handler, Action::List(_) | Action::ListKeys(_) | Action::GenerateKey(_));
+    let installation = installation.with_exclusive_lock(!read_only);
+    let _lock = installation.lock()?;
+
     // dispatch to runtime handler function
     match handler {
-        Action::List => list(installation, config),
-        Action::Add(name, uri, comment, priority) => add(installation, config, name, uri, comment, priority),
+        Action::List(format) => list(installation, config, format),
+        Action::Add(name, repository, keys) => add(installation, config, name, repository, keys),
         Action::Remove(name) => remove(installation, config, name),
         Action::Update(name) => update(installation, config, name),
+        Action::AddKey(name, key) => add_key(installation, config, name, key),
+        Action::ListKeys(name) => list_keys(installation, config, name),
+        Action::RemoveKey(name, key) => remove_key(installation, config, name, key),
+        Action::GenerateKey(path) => generate_key(&path),
     }
 }
 
@@ -105,22 +212,23 @@ fn add(
     installation: Installation,
     config: config::Manager,
     name: String,
-    uri: Url,
-    comment: String,
-    priority: Priority,
+    repository: Repository,
+    keys: Vec<PublicKey>,
 ) -> Result<(), Error> {
     let mut manager = repository::Manager::system(config, installation)?;
 
     let id = repository::Id::new(name);
 
-    manager.add_repository(
-        id.clone(),
-        Repository {
-            description: comment,
-            uri,
-            priority,
-        },
-    )?;
+    manager.add_repository(id.clone(), repository)?;
+
+    // Trust keys before the first refresh so it's verified, an unverifiable
+    // repository is removed again rather than left behind
+    for key in keys {
+        if let Err(error) = manager.add_key(id.clone(), key) {
+            manager.remove(id)?;
+            return Err(error.into());
+        }
+    }
 
     runtime::block_on(manager.refresh(&id))?;
 
@@ -130,10 +238,25 @@ fn add(
 }
 
 /// List the repositories and pretty print them
-fn list(installation: Installation, config: config::Manager) -> Result<(), Error> {
+fn list(installation: Installation, config: config::Manager, format: Option<Format>) -> Result<(), Error> {
     let manager = repository::Manager::system(config, installation)?;
 
     let configured_repos = manager.list();
+
+    if let Some(format) = format {
+        let entries = configured_repos
+            .sorted_by(|(_, a), (_, b)| a.priority.cmp(&b.priority).reverse())
+            .map(|(id, repository)| Entry {
+                id,
+                repository,
+                keys: manager.keys().get(id),
+                last_refreshed_from: manager.last_mirror(id),
+            })
+            .collect::<Vec<_>>();
+        format.print(&entries)?;
+        return Ok(());
+    }
+
     if configured_repos.len() == 0 {
         println!("No repositories have been configured yet");
         return Ok(());
@@ -141,11 +264,31 @@ fn list(installation: Installation, config: config::Manager) -> Result<(), Error
 
     for (id, repo) in configured_repos.sorted_by(|(_, a), (_, b)| a.priority.cmp(&b.priority).reverse()) {
         println!(" - {} = {} [{}]", id, repo.uri, repo.priority);
+
+        for mirror in &repo.mirrors {
+            println!("     mirror {mirror}");
+        }
+
+        if let Some(served) = manager.last_mirror(id) {
+            println!("     last refreshed from {served}");
+        }
     }
 
     Ok(())
 }
 
+/// A configured repository, as printed with `--format`
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    id: &'a repository::Id,
+    #[serde(flatten)]
+    repository: &'a Repository,
+    /// Keys trusted to sign the index
+    keys: &'a [PublicKey],
+    /// URI that served the last index refresh
+    last_refreshed_from: Option<Url>,
+}
+
 /// Update specific repos or all
 fn update(installation: Installation, config: config::Manager, which: Option<String>) -> Result<(), Error> {
     let mut manager = repository::Manager::system(config, installation)?;
@@ -185,8 +328,86 @@ fn remove(installation: Installation, config: config::Manager, repo: String) ->
     Ok(())
 }
 
+/// Trust a key for the repo
+fn add_key(installation: Installation, config: config::Manager, repo: String, key: PublicKey) -> Result<(), Error> {
+    let id = repository::Id::new(repo);
+
+    let mut manager = repository::Manager::system(config, installation)?;
+
+    manager.add_key(id.clone(), key)?;
+
+    println!("{key} trusted for {id}");
+
+    Ok(())
+}
+
+/// List trusted keys of a specific repo or all
+fn list_keys(installation: Installation, config: config::Manager, which: Option<String>) -> Result<(), Error> {
+    let manager = repository::Manager::system(config, installation)?;
+
+    let which = which.map(repository::Id::new);
+    let keys = manager
+        .keys()
+        .iter()
+        .filter(|(id, keys)| !keys.is_empty() && which.as_ref().is_none_or(|which| which == *id))
+        .sorted_by_key(|(id, _)| id.to_string())
+        .collect::<Vec<_>>();
+
+    if keys.is_empty() {
+        println!("No repository keys have been trusted yet");
+        return Ok(());
+    }
+
+    for (id, keys) in keys {
+        println!("{id}:");
+
+        for key in keys {
+            println!(" - {key}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Stop trusting a key for the repo
+fn remove_key(installation: Installation, config: config::Manager, repo: String, key: PublicKey) -> Result<(), Error> {
+    let id = repository::Id::new(repo);
+
+    let mut manager = repository::Manager::system(config, installation)?;
+
+    if !manager.remove_key(&id, &key)? {
+        println!("{key} is not trusted for {id}");
+        process::exit(1);
+    }
+
+    println!("{key} no longer trusted for {id}");
+
+    Ok(())
+}
+
+/// Generate a new signing key
+fn generate_key(path: &Path) -> Result<(), Error> {
+    let key = SecretKey::generate();
+
+    key.save(path)?;
+
+    println!("Secret key written to {path:?}");
+    println!("Public key: {}", key.public());
+
+    Ok(())
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("repo manager")]
     RepositoryManager(#[from] repository::manager::Error),
+
+    #[error("key")]
+    Key(#[from] repository::key::Error),
+
+    #[error("installation")]
+    Installation(#[from] installation::Error),
+
+    #[error("format")]
+    Format(#[from] format::Error),
 }
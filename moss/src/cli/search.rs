@@ -11,6 +11,8 @@ use moss::{environment, Client, Installation};
 use tui::pretty::{print_columns, ColumnDisplay};
 use tui::Styled;
 
+use super::format::{self, Format};
+
 const ARG_KEYWORD: &str = "KEYWORD";
 const FLAG_INSTALLED: &str = "installed";
 
@@ -46,9 +48,15 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         package::Flags::new().with_available()
     };
 
-    let output: Vec<Output> = client
-        .registry
-        .by_keyword(keyword, flags)
+    let packages = client.registry.by_keyword(keyword, flags).collect::<Vec<_>>();
+
+    if let Some(format) = Format::get(args) {
+        format.print(&packages)?;
+        return Ok(());
+    }
+
+    let output: Vec<Output> = packages
+        .into_iter()
         .map(|pkg| Output {
             name: pkg.meta.name,
             summary: pkg.meta.summary,
@@ -68,6 +76,9 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
 pub enum Error {
     #[error("client")]
     Client(#[from] client::Error),
+
+    #[error("format")]
+    Format(#[from] format::Error),
 }
 
 const COLUMN_SPACING: usize = 4;
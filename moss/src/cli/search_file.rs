@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgMatches, Command};
+use moss::{
+    client::{self, search},
+    environment, Client, Installation,
+};
+use thiserror::Error;
+use tui::Styled;
+
+use super::format::{self, Format};
+
+pub fn command() -> Command {
+    Command::new("search-file")
+        .visible_alias("sf")
+        .about("Search packages by file path")
+        .long_about(
+            "Find the installed packages owning files matching a path or glob \n\
+             \n\
+             Absolute patterns such as `/usr/bin/bash` match whole paths, while \n\
+             relative ones such as `bash` or `bin/*sh` match the end of a path. \n\
+             \n\
+             With --available, packages in repositories are searched instead. This \n\
+             requires the repository to publish a file list, see `moss index --files`",
+        )
+        .arg(arg!(<PATTERN> "path or glob to search for").value_parser(clap::value_parser!(String)))
+        .arg(
+            arg!(-a --available "Search available packages instead of installed ones").action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Handle execution of `moss search-file`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let input = args.get_one::<String>("PATTERN").unwrap();
+    let pattern = input
+        .parse::<search::Pattern>()
+        .map_err(|error| Error::InvalidPattern(input.clone(), error))?;
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let found = if args.get_flag("available") {
+        let (found, searched) = client.search_available_files(&pattern)?;
+        if searched == 0 {
+            return Err(Error::NoFileLists);
+        }
+        found
+    } else {
+        client.search_installed_files(&pattern)?
+    };
+
+    if let Some(format) = Format::get(args) {
+        format.print(&found)?;
+        return Ok(());
+    }
+
+    for file in &found {
+        let repository = file
+            .repository
+            .as_ref()
+            .map(|repository| format!(" ({repository})"))
+            .unwrap_or_default();
+
+        println!("{} {}{}", file.name.clone().bold(), file.path, repository.dim());
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid pattern {0}")]
+    InvalidPattern(String, #[source] fnmatch::Error),
+
+    #[error("no repository publishes a file list, see `moss index --files`")]
+    NoFileLists,
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("search")]
+    Search(#[from] search::Error),
+
+    #[error("output")]
+    Output(#[from] format::Error),
+}
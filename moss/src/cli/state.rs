@@ -2,13 +2,29 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use clap::{arg, ArgAction, ArgMatches, Command};
+use std::{fs::File, io, path::PathBuf};
+
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command};
 use moss::{
-    client::{self, prune, Client},
-    environment, state, Installation,
+    client::{
+        self,
+        diff::{self, Diff},
+        manifest::{self, Manifest},
+        plan::{self, Plan, Reason, Version},
+        prune, Client,
+    },
+    environment, package, runtime,
+    state::{self, Selection},
+    Installation,
 };
+use serde::Serialize;
 use thiserror::Error;
-use tui::Styled;
+use tui::{
+    dialoguer::{theme::ColorfulTheme, Confirm},
+    Styled,
+};
+
+use super::format::{self, Format};
 
 pub fn command() -> Command {
     Command::new("state")
@@ -17,6 +33,65 @@ pub fn command() -> Command {
         .subcommand_required(true)
         .subcommand(Command::new("active").about("List the active state"))
         .subcommand(Command::new("list").about("List all states"))
+        .subcommand(
+            Command::new("show").about("Show the packages of a state").arg(
+                arg!(<ID> "State id to be shown")
+                    .action(ArgAction::Set)
+                    .value_parser(clap::value_parser!(u64)),
+            ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show the changes between two states")
+                .arg(
+                    arg!(<FROM> "State id to compare from")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    arg!([TO] "State id to compare to, defaults to the active state")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(arg!(--files "Include the files added, removed or modified")),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export a state as a portable manifest")
+                .long_about(
+                    "Export the packages of a state, along with the configured repositories, \n\
+                     as a YAML manifest which `moss state import` can reproduce elsewhere",
+                )
+                .arg(
+                    arg!([ID] "State id to be exported, defaults to the active state")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Reproduce a state from an exported manifest")
+                .long_about(
+                    "Install exactly the packages of an exported manifest from its repositories, \n\
+                     replacing all currently installed packages. Package hashes are verified \n\
+                     against the manifest where known",
+                )
+                .arg(arg!(<FILE> "Manifest to import").value_parser(value_parser!(PathBuf)))
+                .arg(
+                    arg!(--to <blit_target> "Blit this import to the provided directory instead of the root")
+                        .long_help(
+                            "Blit this import to the provided directory instead of the root. \n\
+                             \n\
+                             This operation won't be captured as a new state",
+                        )
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(arg!(-u --update "Update the manifest repositories before importing"))
+                .arg(
+                    arg!(--"dry-run" "Print the plan without fetching or installing anything")
+                        .long_help("Print the plan without fetching or installing anything, implied by --format"),
+                ),
+        )
         .subcommand(
             Command::new("activate").about("Activate a state").arg(
                 arg!(<ID> "State id to be activated")
@@ -49,8 +124,12 @@ pub fn command() -> Command {
 
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     match args.subcommand() {
-        Some(("active", _)) => active(installation),
-        Some(("list", _)) => list(installation),
+        Some(("active", args)) => active(args, installation),
+        Some(("list", args)) => list(args, installation),
+        Some(("show", args)) => show(args, installation),
+        Some(("diff", args)) => diff(args, installation),
+        Some(("export", args)) => export(args, installation),
+        Some(("import", args)) => import(args, installation),
         Some(("activate", args)) => activate(args, installation),
         Some(("prune", args)) => prune(args, installation),
         Some(("remove", args)) => remove(args, installation),
@@ -59,12 +138,18 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
 }
 
 /// List the active state
-pub fn active(installation: Installation) -> Result<(), Error> {
-    if let Some(id) = installation.active_state {
-        let client = Client::new(environment::NAME, installation)?;
-
-        let state = client.state_db.get(id)?;
+pub fn active(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let state = match installation.active_state {
+        Some(id) => {
+            let client = Client::new(environment::NAME, installation)?;
+            Some(client.state_db.get(id)?)
+        }
+        None => None,
+    };
 
+    if let Some(format) = Format::get(args) {
+        format.print(&state)?;
+    } else if let Some(state) = state {
         print_state(state);
     }
 
@@ -72,7 +157,7 @@ pub fn active(installation: Installation) -> Result<(), Error> {
 }
 
 /// List all known states, newest first
-pub fn list(installation: Installation) -> Result<(), Error> {
+pub fn list(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let client = Client::new(environment::NAME, installation)?;
 
     let state_ids = client.state_db.list_ids()?;
@@ -83,14 +168,271 @@ pub fn list(installation: Installation) -> Result<(), Error> {
         .collect::<Result<Vec<_>, _>>()?;
 
     states.reverse();
-    states.into_iter().for_each(print_state);
+
+    if let Some(format) = Format::get(args) {
+        format.print(&states)?;
+    } else {
+        states.into_iter().for_each(print_state);
+    }
+
+    Ok(())
+}
+
+/// A package selected in a state
+#[derive(Debug, Serialize)]
+struct Item {
+    name: String,
+    id: String,
+    #[serde(flatten)]
+    version: Version,
+    explicit: bool,
+    reason: Option<String>,
+}
+
+/// A state along with details of its selected packages
+#[derive(Debug, Serialize)]
+struct Shown {
+    #[serde(flatten)]
+    state: state::State,
+    packages: Vec<Item>,
+}
+
+/// Show a state and its selected packages
+pub fn show(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let id = *args.get_one::<u64>("ID").unwrap() as i32;
+
+    let client = Client::new(environment::NAME, installation)?;
+    let state = client.state_db.get(id.into())?;
+
+    let mut packages = state
+        .selections
+        .iter()
+        .map(|selection| {
+            let meta = client.install_db.get(&selection.package)?;
+            Ok(Item {
+                name: meta.name.to_string(),
+                id: selection.package.to_string(),
+                version: Version {
+                    version: meta.version_identifier,
+                    release: meta.source_release,
+                    build_release: meta.build_release,
+                },
+                explicit: selection.explicit,
+                reason: selection.reason.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let shown = Shown { state, packages };
+
+    if let Some(format) = Format::get(args) {
+        format.print(&shown)?;
+        return Ok(());
+    }
+
+    print_state(shown.state);
+    for item in &shown.packages {
+        let marker = if item.explicit { "explicit" } else { "transitive" };
+        let reason = item.reason.as_ref().map(|r| format!(", {r}")).unwrap_or_default();
+
+        println!(
+            "  {} {}  {}",
+            item.name.clone().bold(),
+            item.version,
+            format!("({marker}{reason})").dim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Show the changes between two states
+pub fn diff(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let from = *args.get_one::<u64>("FROM").unwrap() as i32;
+    let to = match args.get_one::<u64>("TO") {
+        Some(to) => *to as i32,
+        None => i32::from(installation.active_state.ok_or(Error::NoActiveState)?),
+    };
+    let files = args.get_flag("files");
+
+    let client = Client::new(environment::NAME, installation)?;
+    let diff = client.diff_states(from.into(), to.into(), files)?;
+
+    if let Some(format) = Format::get(args) {
+        format.print(&diff)?;
+    } else {
+        print_diff(&diff);
+    }
+
+    Ok(())
+}
+
+/// Export a state as a manifest, in YAML unless another format is requested
+pub fn export(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let id = match args.get_one::<u64>("ID") {
+        Some(id) => *id as i32,
+        None => i32::from(installation.active_state.ok_or(Error::NoActiveState)?),
+    };
+
+    let client = Client::new(environment::NAME, installation)?;
+    let manifest = client.export_state(id.into())?;
+
+    Format::get(args).unwrap_or(Format::Yaml).print(&manifest)?;
+
+    Ok(())
+}
+
+/// Reproduce the state of a manifest
+pub fn import(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let path = args.get_one::<PathBuf>("FILE").unwrap();
+    let update = args.get_flag("update");
+    let format = Format::get(args);
+    let dry_run = args.get_flag("dry-run") || format.is_some();
+    let yes = args.get_flag("yes");
+
+    let manifest: Manifest =
+        serde_yaml::from_reader(File::open(path).map_err(Error::ReadManifest)?).map_err(Error::ParseManifest)?;
+
+    // Only the manifest repositories are used, sharing their cache with the system
+    let mut client = Client::with_explicit_repositories(
+        environment::NAME,
+        installation.with_exclusive_lock(true),
+        manifest.repositories.clone(),
+    )?;
+
+    // Make ephemeral if a blit target was provided
+    if let Some(blit_target) = args.get_one::<PathBuf>("to").cloned() {
+        client = client.ephemeral(blit_target)?;
+    }
+
+    // A dry run never modifies the installed state
+    if !dry_run {
+        client.prepare_mutation()?;
+    }
+
+    if update {
+        runtime::block_on(client.refresh_repositories())?;
+    } else {
+        runtime::block_on(client.ensure_repos_initialized())?;
+    }
+
+    // The cached indexes may predate the manifest
+    let packages = match client.resolve_manifest(&manifest) {
+        Err(manifest::Error::MissingPackage(..)) if !update => {
+            runtime::block_on(client.refresh_repositories())?;
+            client.resolve_manifest(&manifest)?
+        }
+        result => result?,
+    };
+
+    // Ephemeral imports start from scratch
+    let installed = if client.is_ephemeral() {
+        vec![]
+    } else {
+        client
+            .registry
+            .list_installed(package::Flags::default())
+            .collect::<Vec<_>>()
+    };
+
+    let missing = packages
+        .iter()
+        .filter(|p| !installed.iter().any(|i| i.id == p.id))
+        .collect::<Vec<_>>();
+    let removed = installed
+        .iter()
+        .filter(|i| !packages.iter().any(|p| p.meta.name == i.meta.name))
+        .collect::<Vec<_>>();
+
+    let selections = manifest
+        .packages
+        .iter()
+        .map(|entry| Selection {
+            package: entry.id.clone(),
+            explicit: entry.explicit,
+            reason: entry.reason.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    let plan = {
+        let mut plan = Plan::default();
+
+        for package in &missing {
+            let reason = if manifest.packages.iter().any(|e| e.id == package.id && e.explicit) {
+                Reason::Requested
+            } else {
+                Reason::Dependency {
+                    required_by: plan::required_by(package, &packages),
+                }
+            };
+
+            match installed.iter().find(|i| i.meta.name == package.meta.name) {
+                Some(from) => plan.upgrade(from, package, reason),
+                None => plan.add(package, reason),
+            }
+        }
+        for package in &removed {
+            plan.remove(package, Reason::Unlisted);
+        }
+
+        plan
+    };
+
+    // The same packages may still differ in which are explicit
+    let unchanged = !client.is_ephemeral()
+        && plan.is_empty()
+        && match client.installation.active_state {
+            Some(id) => {
+                let mut active = client.state_db.get(id)?.selections;
+                let mut selections = selections.clone();
+                active.sort_by(|a, b| a.package.cmp(&b.package));
+                selections.sort_by(|a, b| a.package.cmp(&b.package));
+                active == selections
+            }
+            None => selections.is_empty(),
+        };
+
+    if let Some(format) = format {
+        format.print(&plan)?;
+        return Ok(());
+    }
+
+    if unchanged {
+        println!("Installation already matches the manifest");
+        return Ok(());
+    }
+
+    plan.print();
+
+    if dry_run {
+        return Ok(());
+    }
+
+    println!();
+    let result = if yes {
+        true
+    } else {
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(" Do you wish to continue? ")
+            .default(false)
+            .interact()?
+    };
+    if !result {
+        return Err(Error::Cancelled);
+    }
+
+    runtime::block_on(client.cache_packages(&missing))?;
+
+    client.new_state(&selections, format!("Import state {}", manifest.state))?;
+
     Ok(())
 }
 
 pub fn activate(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let new_id = *args.get_one::<u64>("ID").unwrap() as i32;
 
-    let client = Client::new(environment::NAME, installation)?;
+    let mut client = Client::new(environment::NAME, installation.with_exclusive_lock(true))?;
     let old_id = client.activate_state(new_id.into())?;
 
     println!(
@@ -107,7 +449,7 @@ pub fn prune(args: &ArgMatches, installation: Installation) -> Result<(), Error>
     let include_newer = args.get_flag("include-newer");
     let yes = args.get_flag("yes");
 
-    let client = Client::new(environment::NAME, installation)?;
+    let mut client = Client::new(environment::NAME, installation.with_exclusive_lock(true))?;
     client.prune(prune::Strategy::KeepRecent { keep, include_newer }, yes)?;
 
     Ok(())
@@ -117,7 +459,7 @@ pub fn remove(args: &ArgMatches, installation: Installation) -> Result<(), Error
     let id = *args.get_one::<u64>("ID").unwrap() as i32;
     let yes = args.get_flag("yes");
 
-    let client = Client::new(environment::NAME, installation)?;
+    let mut client = Client::new(environment::NAME, installation.with_exclusive_lock(true))?;
     client.prune(prune::Strategy::Remove(id.into()), yes)?;
 
     Ok(())
@@ -139,14 +481,96 @@ fn print_state(state: state::State) {
     // TODO: List packages?
     // TODO: Start with normal list, compute diff, reverse to print ?
     println!("{} {}", "Packages:".bold(), state.selections.len());
+    for warning in &state.warnings {
+        println!("{} {warning}", "Warning:".yellow());
+    }
     println!();
 }
 
+/// Emit the changes between two states for the TUI
+fn print_diff(diff: &Diff) {
+    println!(
+        "State #{} → #{}",
+        diff.from.to_string().bold(),
+        diff.to.to_string().bold()
+    );
+    println!();
+
+    if diff.is_empty() {
+        println!("No package changes");
+    }
+    if !diff.added.is_empty() {
+        println!("{}", "Added:".green());
+        for entry in &diff.added {
+            println!("  {} {}", entry.name.clone().bold(), entry.version);
+        }
+    }
+    if !diff.upgraded.is_empty() {
+        println!("{}", "Upgraded:".blue());
+        for update in &diff.upgraded {
+            println!("  {} {} → {}", update.name.clone().bold(), update.from, update.to);
+        }
+    }
+    if !diff.downgraded.is_empty() {
+        println!("{}", "Downgraded:".yellow());
+        for update in &diff.downgraded {
+            println!("  {} {} → {}", update.name.clone().bold(), update.from, update.to);
+        }
+    }
+    if !diff.removed.is_empty() {
+        println!("{}", "Removed:".red());
+        for entry in &diff.removed {
+            println!("  {} {}", entry.name.clone().bold(), entry.version);
+        }
+    }
+
+    if let Some(files) = &diff.files {
+        println!();
+        if files.is_empty() {
+            println!("No file changes");
+        } else {
+            println!("{}", "Files:".bold());
+            for file in files {
+                let change = match file.change {
+                    diff::Change::Added => file.change.to_string().green(),
+                    diff::Change::Removed => file.change.to_string().red(),
+                    diff::Change::Modified => file.change.to_string().yellow(),
+                };
+                println!("  {change} {}", file.path);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("cancelled")]
+    Cancelled,
+
+    #[error("no active state")]
+    NoActiveState,
+
+    #[error("read manifest")]
+    ReadManifest(#[source] io::Error),
+
+    #[error("parse manifest")]
+    ParseManifest(#[source] serde_yaml::Error),
+
+    #[error("manifest")]
+    Manifest(#[from] manifest::Error),
+
+    #[error("string processing")]
+    Dialog(#[from] tui::dialoguer::Error),
+
     #[error("client")]
     Client(#[from] client::Error),
 
+    #[error("diff")]
+    Diff(#[from] diff::Error),
+
     #[error("db")]
     DB(#[from] moss::db::Error),
+
+    #[error("format")]
+    Format(#[from] format::Error),
 }
@@ -3,14 +3,18 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::borrow::Cow;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
 
 use clap::{arg, value_parser, ArgMatches, Command};
 use moss::registry::transaction;
 use moss::state::Selection;
 use moss::{
-    client::{self, Client},
+    client::{
+        self,
+        plan::{self, Plan, Reason},
+        Client,
+    },
     package::{self},
     Package,
 };
@@ -21,12 +25,22 @@ use tui::dialoguer::theme::ColorfulTheme;
 use tui::dialoguer::Confirm;
 use tui::pretty::autoprint_columns;
 
+use super::format::{self, Format};
+
 pub fn command() -> Command {
     Command::new("sync")
         .visible_alias("up")
         .about("Sync packages")
         .long_about("Sync package selections with candidates from the highest priority repository")
-        .arg(arg!(-u --"update" "Update repositories before syncing"))
+        .arg(
+            arg!(-u --"update" "Update repositories before syncing")
+                .long_help(
+                    "Update repositories before syncing. \n\
+                     \n\
+                     Not available with --dry-run or --format, which never modify the installation",
+                )
+                .conflicts_with("dry-run"),
+        )
         .arg(arg!(--"upgrade-only" "Only sync packages that have a version upgrade"))
         .arg(
             arg!(--to <blit_target> "Blit this sync to the provided directory instead of the root")
@@ -37,20 +51,36 @@ pub fn command() -> Command {
                 )
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(
+            arg!(--"dry-run" "Print the plan without fetching or syncing anything")
+                .long_help("Print the plan without fetching or syncing anything, implied by --format"),
+        )
 }
 
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let yes_all = *args.get_one::<bool>("yes").unwrap();
     let update = *args.get_one::<bool>("update").unwrap();
     let upgrade_only = *args.get_one::<bool>("upgrade-only").unwrap();
+    let format = Format::get(args);
+    let dry_run = *args.get_one::<bool>("dry-run").unwrap() || format.is_some();
 
-    let mut client = Client::new(environment::NAME, installation)?;
+    // `--format` is global, so clap can't reject it alongside `--update`
+    if update && dry_run {
+        return Err(Error::UpdateDryRun);
+    }
+
+    // A dry run never modifies the installation
+    let mut client = Client::new(environment::NAME, installation.with_exclusive_lock(!dry_run))?;
 
     // Make ephemeral if a blit target was provided
     if let Some(blit_target) = args.get_one::<PathBuf>("to").cloned() {
         client = client.ephemeral(blit_target)?;
     }
 
+    if !dry_run {
+        client.prepare_mutation()?;
+    }
+
     // Update repos if requested
     if update {
         runtime::block_on(client.refresh_repositories())?;
@@ -73,8 +103,9 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     //
     // By resolving only explicit first, this ensures any "orphaned" transitive deps
     // are naturally dropped from the final state.
-    let first_pass = resolve_with_sync(&client, Resolution::Explicit, upgrade_only, &installed)?;
-    let finalized = resolve_with_sync(&client, Resolution::All, upgrade_only, &first_pass)?;
+    let restrictions = client.restrictions()?;
+    let first_pass = resolve_with_sync(&client, Resolution::Explicit, upgrade_only, &restrictions, &installed)?;
+    let finalized = resolve_with_sync(&client, Resolution::All, upgrade_only, &restrictions, &first_pass)?;
 
     // Synced are packages are:
     //
@@ -89,23 +120,77 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         .filter(|p| !finalized.iter().any(|f| f.meta.name == p.meta.name))
         .cloned()
         .collect::<Vec<_>>();
+    // Packages a hold kept from syncing to their highest priority candidate
+    let held = finalized
+        .iter()
+        .filter(|p| restrictions.contains_key(&p.meta.name))
+        .filter_map(|p| {
+            let candidate = client
+                .registry
+                .by_name(&p.meta.name, package::Flags::new().with_available())
+                .next()?;
+            let upgrade_check = !upgrade_only || candidate.meta.source_release > p.meta.source_release;
 
-    if synced.is_empty() && removed.is_empty() {
+            (candidate.id != p.id && upgrade_check).then_some((p, candidate))
+        })
+        .collect::<Vec<_>>();
+
+    let plan = {
+        let mut plan = Plan::default();
+
+        for package in &synced {
+            let previous = installed.iter().find(|i| i.meta.name == package.meta.name);
+            let reason = if previous.is_some_and(|i| i.flags.explicit) {
+                Reason::Requested
+            } else {
+                Reason::Dependency {
+                    required_by: plan::required_by(package, &finalized),
+                }
+            };
+
+            match previous {
+                Some(from) if from.id != package.id => plan.upgrade(from, package, reason),
+                _ => plan.add(package, reason),
+            }
+        }
+        for package in &removed {
+            plan.remove(package, Reason::Orphaned);
+        }
+        for (package, candidate) in &held {
+            plan.hold(package, candidate);
+        }
+
+        plan
+    };
+
+    let no_changes = synced.is_empty() && removed.is_empty();
+
+    if let Some(format) = format {
+        format.print(&plan)?;
+    } else if no_changes {
         println!("No packages to sync");
-        return Ok(());
+        plan.print_held();
+    } else if dry_run {
+        plan.print();
+    } else {
+        if !synced.is_empty() {
+            println!("The following packages will be sync'd: ");
+            println!();
+            autoprint_columns(synced.as_slice());
+            println!();
+        }
+        if !removed.is_empty() {
+            println!("The following orphaned packages will be removed: ");
+            println!();
+            autoprint_columns(removed.as_slice());
+            println!();
+        }
+        plan.print_held();
     }
 
-    if !synced.is_empty() {
-        println!("The following packages will be sync'd: ");
-        println!();
-        autoprint_columns(synced.as_slice());
-        println!();
-    }
-    if !removed.is_empty() {
-        println!("The following orphaned packages will be removed: ");
-        println!();
-        autoprint_columns(removed.as_slice());
-        println!();
+    // Nothing to do, or only the plan was requested
+    if no_changes || dry_run {
+        return Ok(());
     }
 
     // Must we prompt?
@@ -176,6 +261,7 @@ fn resolve_with_sync(
     client: &Client,
     resolution: Resolution,
     upgrade_only: bool,
+    restrictions: &BTreeMap<package::Name, Vec<package::Id>>,
     packages: &[Package],
 ) -> Result<Vec<Package>, Error> {
     let all_ids = packages.iter().map(|p| &p.id).collect::<BTreeSet<_>>();
@@ -189,11 +275,13 @@ fn resolve_with_sync(
             Resolution::All => true,
         })
         .map(|p| {
-            // Get first available = use highest priority
+            let allowed = restrictions.get(&p.meta.name);
+
+            // Get first available = use highest priority, unless held
             if let Some(lookup) = client
                 .registry
                 .by_name(&p.meta.name, package::Flags::new().with_available())
-                .next()
+                .find(|c| allowed.is_none_or(|allowed| allowed.contains(&c.id)))
             {
                 let upgrade_check = if upgrade_only {
                     lookup.meta.source_release > p.meta.source_release
@@ -206,6 +294,9 @@ fn resolve_with_sync(
                 } else {
                     Ok(Cow::Borrowed(p))
                 }
+            } else if allowed.is_some() {
+                // Held at a package that's no longer available
+                Ok(Cow::Borrowed(p))
             } else {
                 Err(Error::NameNotFound(p.meta.name.clone()))
             }
@@ -213,7 +304,7 @@ fn resolve_with_sync(
         .collect::<Result<Vec<_>, _>>()?;
 
     // Build a new tx from this sync'd package set
-    let mut tx = client.registry.transaction()?;
+    let mut tx = client.transaction()?;
     tx.add(with_sync.iter().map(|p| p.id.clone()).collect())?;
 
     // Resolve the tx
@@ -231,6 +322,9 @@ pub enum Error {
     #[error("no installation")]
     NoInstall,
 
+    #[error("--update can't be used with --dry-run or --format, which never modify the installation")]
+    UpdateDryRun,
+
     #[error("client")]
     Client(#[from] client::Error),
 
@@ -245,4 +339,7 @@ pub enum Error {
 
     #[error("io")]
     Io(#[from] std::io::Error),
+
+    #[error("format")]
+    Format(#[from] format::Error),
 }
@@ -0,0 +1,191 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgAction, ArgMatches, Command};
+use moss::{
+    client::{self, trigger},
+    environment, Client, Installation,
+};
+use thiserror::Error;
+use tui::Styled;
+
+use super::format::{self, Format};
+
+pub fn command() -> Command {
+    Command::new("trigger")
+        .about("Inspect and run triggers")
+        .long_about(
+            "Inspect the transaction and system triggers installed under \n\
+             `/usr/share/moss/triggers`, or run them against the active state",
+        )
+        .subcommand_required(true)
+        .subcommand(Command::new("list").about("List installed triggers"))
+        .subcommand(
+            Command::new("show")
+                .about("Show the definition of a trigger")
+                .arg(arg!(<NAME> "Trigger to show").value_parser(clap::value_parser!(String))),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Run triggers against the active state")
+                .long_about(
+                    "Run the handlers of triggers matching any path of the active state, \n\
+                     in the order they'd run during a transaction",
+                )
+                .arg(
+                    arg!([NAME] ... "Only run these triggers")
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(arg!(--"dry-run" "Print the handlers in order without running them")),
+        )
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    // Only running triggers mutates the installation
+    let exclusive = matches!(args.subcommand(), Some(("run", args)) if !args.get_flag("dry-run"));
+    let client = Client::new(environment::NAME, installation.with_exclusive_lock(exclusive))?;
+
+    match args.subcommand() {
+        Some(("list", args)) => list(args, &client),
+        Some(("show", args)) => show(args, &client),
+        Some(("run", args)) => run(args, &client),
+        _ => unreachable!(),
+    }
+}
+
+/// List installed triggers
+fn list(args: &ArgMatches, client: &Client) -> Result<(), Error> {
+    let installed = client.installed_triggers();
+
+    if let Some(format) = Format::get(args) {
+        format.print(&installed)?;
+        return Ok(());
+    }
+
+    let width = installed
+        .iter()
+        .map(|installed| installed.trigger.name.len())
+        .max()
+        .unwrap_or_default();
+
+    for installed in &installed {
+        let inhibited = installed
+            .inhibited
+            .as_ref()
+            .map(|reason| format!(" (inhibited, {reason})"))
+            .unwrap_or_default();
+
+        println!(
+            "{}  {}  {}{}",
+            format!("{:width$}", installed.trigger.name).bold(),
+            format!("{:11}", installed.kind.to_string()).dim(),
+            installed.trigger.description.lines().next().unwrap_or_default(),
+            inhibited.yellow(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Show the definition of a trigger
+fn show(args: &ArgMatches, client: &Client) -> Result<(), Error> {
+    let name = args.get_one::<String>("NAME").unwrap();
+
+    let installed = client
+        .installed_triggers()
+        .into_iter()
+        .find(|installed| installed.trigger.name == *name)
+        .ok_or_else(|| Error::UnknownTrigger(name.clone()))?;
+
+    if let Some(format) = Format::get(args) {
+        format.print(&installed)?;
+        return Ok(());
+    }
+
+    let trigger = &installed.trigger;
+
+    println!("{} ({})", trigger.name.clone().bold(), installed.kind);
+    println!("{}", trigger.description.trim_end());
+    println!();
+
+    for (label, value) in [
+        ("Before:", &trigger.before),
+        ("After:", &trigger.after),
+        ("Needs:", &trigger.needs),
+        ("Inhibited:", &installed.inhibited),
+    ] {
+        if let Some(value) = value {
+            println!("{} {value}", label.bold());
+        }
+    }
+    if trigger.always {
+        println!("{} always", "Runs:".bold());
+    }
+
+    println!("{}", "Paths:".bold());
+    for (pattern, definition) in &trigger.paths {
+        let kind = definition
+            .kind
+            .map(|kind| format!(" ({kind:?})").to_lowercase())
+            .unwrap_or_default();
+        println!("  {pattern}{} → {}", kind.dim(), definition.handlers.join(", "));
+    }
+
+    println!("{}", "Handlers:".bold());
+    for (name, handler) in &trigger.handlers {
+        println!("  {} {handler}", format!("{name}:").dim());
+    }
+
+    Ok(())
+}
+
+/// Run, or print, the handlers of triggers against the active state
+fn run(args: &ArgMatches, client: &Client) -> Result<(), Error> {
+    let names = args
+        .get_many::<String>("NAME")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if !args.get_flag("dry-run") {
+        let count = client.run_triggers(&names)?;
+        println!("Ran {count} trigger handler(s)");
+        return Ok(());
+    }
+
+    let compiled = client.compile_triggers(&names)?;
+
+    if let Some(format) = Format::get(args) {
+        format.print(&compiled)?;
+        return Ok(());
+    }
+
+    for compiled in &compiled {
+        println!(
+            "{} {} {}",
+            format!("[{}]", compiled.kind).dim(),
+            format!("{}:", compiled.trigger).bold(),
+            compiled.handler
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown trigger: {0}")]
+    UnknownTrigger(String),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error(transparent)]
+    Trigger(#[from] trigger::Error),
+
+    #[error("output")]
+    Output(#[from] format::Error),
+}
@@ -0,0 +1,57 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgMatches, Command};
+use moss::{
+    client::{self, Client},
+    environment, package, Installation,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("unhold")
+        .about("Release held packages")
+        .long_about("Release the hold or pin on packages, so `install` and `sync` may change them again")
+        .arg(arg!(<NAME> ... "packages to release").value_parser(clap::value_parser!(String)))
+}
+
+/// Handle execution of `moss unhold`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let names = args
+        .get_many::<String>("NAME")
+        .into_iter()
+        .flatten()
+        .map(|name| package::Name::from(name.clone()))
+        .collect::<Vec<_>>();
+
+    let client = Client::new(environment::NAME, installation)?;
+    let holds = client.holds();
+
+    if let Some(name) = names.iter().find(|name| holds.get(name.as_ref()).is_none()) {
+        return Err(Error::NotHeld(name.to_string()));
+    }
+
+    for name in &names {
+        if !client.unhold(name) {
+            return Err(Error::ConfiguredElsewhere(name.to_string()));
+        }
+
+        println!("{} {}", "Released".green(), name.to_string().bold());
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("package isn't held: {0}")]
+    NotHeld(String),
+
+    #[error("hold on {0} is configured elsewhere and can't be released")]
+    ConfiguredElsewhere(String),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+}
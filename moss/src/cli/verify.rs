@@ -0,0 +1,143 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgMatches, Command};
+use moss::{
+    client::{self, verify, Client},
+    environment,
+    package::Flags,
+    Installation, Provider,
+};
+use thiserror::Error;
+use tui::Styled;
+
+use super::format::{self, Format};
+
+pub fn command() -> Command {
+    Command::new("verify")
+        .about("Verify installed files")
+        .long_about(
+            "Verify the files of installed packages against their recorded layout \n\
+             \n\
+             Reports missing files, type, mode or owner changes, modified content \n\
+             and files no longer linked to the asset store",
+        )
+        .arg(arg!([NAME] ... "packages to verify, defaults to all").value_parser(clap::value_parser!(String)))
+        .arg(arg!(--repair "Restore damaged files, fetching packages again if needed"))
+}
+
+/// Handle execution of `moss verify`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let names = args
+        .get_many::<String>("NAME")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let repair = *args.get_one::<bool>("repair").unwrap();
+
+    let mut client = Client::new(environment::NAME, installation.with_exclusive_lock(repair))?;
+    if repair {
+        client.prepare_mutation()?;
+    }
+
+    let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
+
+    let packages = if names.is_empty() {
+        installed.iter().map(|p| p.id.clone()).collect::<Vec<_>>()
+    } else {
+        names
+            .iter()
+            .map(|name| {
+                let provider = Provider::from_name(name).map_err(|_| Error::NotInstalled(name.to_string()))?;
+                installed
+                    .iter()
+                    .find(|i| i.meta.providers.contains(&provider))
+                    .map(|i| i.id.clone())
+                    .ok_or_else(|| Error::NotInstalled(name.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let problems = client.verify(&packages)?;
+
+    if let Some(format) = Format::get(args) {
+        format.print(&problems)?;
+    } else if problems.is_empty() {
+        println!("All files verified");
+    } else {
+        for problem in &problems {
+            let name = installed
+                .iter()
+                .find(|p| p.id == problem.package)
+                .map(|p| p.meta.name.to_string())
+                .unwrap_or_default();
+
+            println!(
+                "{} {} {}",
+                problem.path.clone().bold(),
+                problem.issue,
+                format!("({name})").dim()
+            );
+        }
+        println!();
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    if !repair {
+        return Err(Error::Damaged(problems.len()));
+    }
+
+    let repair = client.repair_files(&problems)?;
+    println!("{} {} file(s)", "Repaired".green(), repair.repaired);
+
+    if repair.failed.is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    for (package, error) in &repair.failed {
+        let name = installed
+            .iter()
+            .find(|p| p.id == *package)
+            .map(|p| p.meta.name.to_string())
+            .unwrap_or_else(|| package.to_string());
+
+        println!(
+            "{} couldn't fetch {}: {}",
+            "Warning:".yellow(),
+            name.bold(),
+            error.clone().dim()
+        );
+    }
+    println!();
+
+    Err(Error::Unrepaired {
+        files: repair.unrepaired,
+        packages: repair.failed.len(),
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("package not installed: {0}")]
+    NotInstalled(String),
+
+    #[error("{0} problem(s) found, run with --repair to restore the damaged files")]
+    Damaged(usize),
+
+    #[error("{files} file(s) left damaged, as {packages} package(s) couldn't be fetched")]
+    Unrepaired { files: usize, packages: usize },
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("verify")]
+    Verify(#[from] verify::Error),
+
+    #[error("output")]
+    Output(#[from] format::Error),
+}
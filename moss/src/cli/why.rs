@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::BTreeSet;
+
+use clap::{arg, ArgMatches, Command};
+use moss::{
+    client::{self, Client},
+    environment,
+    package::{self, Flags},
+    registry::transaction,
+    Installation, Package, Provider,
+};
+use serde::Serialize;
+use thiserror::Error;
+use tui::Styled;
+
+use super::format::{self, Format};
+
+pub fn command() -> Command {
+    Command::new("why")
+        .about("Show why a package is installed")
+        .long_about("Show the dependency chains from explicitly installed packages down to the given package")
+        .arg(arg!(<NAME> "installed package to explain").value_parser(clap::value_parser!(String)))
+        .arg(arg!(--dot "Print the dependency chains as a DOT graph"))
+}
+
+/// Why a package is installed
+#[derive(Debug, Serialize)]
+struct Why {
+    package: String,
+    explicit: bool,
+    /// Package names from an explicit package down to this one
+    chains: Vec<Vec<String>>,
+}
+
+/// Handle execution of `moss why`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let name = args.get_one::<String>("NAME").unwrap();
+    let dot = *args.get_one::<bool>("dot").unwrap();
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
+    let provider = Provider::from_name(name).map_err(|_| Error::NotInstalled(name.clone()))?;
+    let target = installed
+        .iter()
+        .find(|p| p.meta.providers.contains(&provider))
+        .ok_or_else(|| Error::NotInstalled(name.clone()))?;
+
+    let tx = client
+        .registry
+        .transaction_with_installed(installed.iter().map(|p| p.id.clone()).collect())?;
+    let graph = tx.graph();
+
+    let name_of = |id: &package::Id| {
+        installed
+            .iter()
+            .find(|p| p.id == *id)
+            .map(|p| p.meta.name.to_string())
+            .unwrap_or_else(|| id.to_string())
+    };
+
+    // Shortest chain from each explicit package that leads to the target
+    let chains = installed
+        .iter()
+        .filter(|p| p.flags.explicit && p.id != target.id)
+        .filter_map(|p| graph.shortest_path(&p.id, &target.id))
+        .map(|path| path.into_iter().map(name_of).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let why = Why {
+        package: target.meta.name.to_string(),
+        explicit: target.flags.explicit,
+        chains,
+    };
+
+    if dot {
+        let edges = why
+            .chains
+            .iter()
+            .flat_map(|chain| chain.windows(2).map(|pair| (pair[0].as_str(), pair[1].as_str())))
+            .collect::<BTreeSet<_>>();
+        format::print_dot(edges);
+    } else if let Some(format) = Format::get(args) {
+        format.print(&why)?;
+    } else {
+        print_why(target, &why);
+    }
+
+    Ok(())
+}
+
+fn print_why(target: &Package, why: &Why) {
+    let name = target.meta.name.to_string();
+
+    if why.explicit {
+        println!("{} is explicitly installed", name.clone().bold());
+    }
+
+    if why.chains.is_empty() {
+        if !why.explicit {
+            println!("{} isn't required by any explicitly installed package", name.bold());
+        }
+        return;
+    }
+
+    if why.explicit {
+        println!();
+    }
+    println!("{} is required by:", name.bold());
+    println!();
+    for chain in &why.chains {
+        println!("  {}", chain.join(&" → ".dim().to_string()));
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("package not installed: {0}")]
+    NotInstalled(String),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
+
+    #[error("output")]
+    Output(#[from] format::Error),
+}
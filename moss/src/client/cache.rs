@@ -6,21 +6,22 @@
 
 use std::{
     collections::HashSet,
-    io,
-    path::PathBuf,
+    io, iter,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
-use futures::StreamExt;
-use stone::{payload, read::PayloadKind};
+use log::warn;
+use sha2::{Digest, Sha256};
+use stone::{header::v1::FileType, payload, read::PayloadKind};
 use thiserror::Error;
 use tokio::{
     fs::{self, File},
-    io::AsyncWriteExt,
+    io::AsyncReadExt,
 };
 use url::Url;
 
-use crate::{package, request, Installation};
+use crate::{db, package, request, Installation};
 
 /// Synchronized set of assets that are currently being
 /// unpacked. Used to prevent unpacking the same asset
@@ -59,55 +60,208 @@ impl Progress {
 }
 
 /// Fetch a package with the provided [`package::Meta`] and [`Installation`] and return a [`Download`] on success.
+///
+/// A delta is preferred when all assets of the release it applies to are already in the
+/// asset store, falling back to the full package if the delta can't be fetched.
+///
+/// Each of `mirrors` of a url is tried in order if the url is unavailable.
 pub async fn fetch(
     meta: &package::Meta,
     installation: &Installation,
+    layout_db: &db::layout::Database,
+    mirrors: impl Fn(&Url) -> Vec<Url>,
     on_progress: impl Fn(Progress),
 ) -> Result<Download, Error> {
     let url = meta.uri.as_ref().ok_or(Error::MissingUri)?.parse::<Url>()?;
     let hash = meta.hash.as_ref().ok_or(Error::MissingHash)?;
 
-    let download_path = download_path(installation, hash)?;
+    let package_path = download_path(installation, hash)?;
+
+    // Nothing to gain from a delta if we already have the full package
+    if !is_intact(&package_path, hash).await? {
+        if let Some(delta) = usable_delta(meta, installation, layout_db)? {
+            let delta_path = download_path(installation, &delta.hash)?;
+            let delta_url = delta.uri.parse::<Url>()?;
+
+            match fetch_file(
+                &delta_url,
+                &mirrors(&delta_url),
+                &delta_path,
+                &delta.hash,
+                delta.size,
+                installation.download_retries,
+                &on_progress,
+            )
+            .await
+            {
+                Ok(was_cached) => {
+                    return Ok(Download {
+                        id: meta.id().into(),
+                        path: delta_path,
+                        installation: installation.clone(),
+                        was_cached,
+                        was_delta: true,
+                    })
+                }
+                Err(error) => warn!("Delta of {} unavailable, fetching full package: {error}", meta.name),
+            }
+        }
+    }
+
+    let was_cached = fetch_file(
+        &url,
+        &mirrors(&url),
+        &package_path,
+        hash,
+        meta.download_size.unwrap_or_default(),
+        installation.download_retries,
+        &on_progress,
+    )
+    .await?;
+
+    Ok(Download {
+        id: meta.id().into(),
+        path: package_path,
+        installation: installation.clone(),
+        was_cached,
+        was_delta: false,
+    })
+}
 
-    if let Some(parent) = download_path.parent() {
+/// Download `url` to `path` unless an intact copy is already there, returning true if it was.
+///
+/// Each of `mirrors` is tried in order if `url` is unavailable.
+async fn fetch_file(
+    url: &Url,
+    mirrors: &[Url],
+    path: &Path,
+    hash: &str,
+    size: u64,
+    retries: u32,
+    on_progress: &impl Fn(Progress),
+) -> Result<bool, Error> {
+    if let Some(parent) = path.parent() {
         fs::create_dir_all(parent).await?;
     }
 
-    if fs::try_exists(&download_path).await? {
-        return Ok(Download {
-            id: meta.id().into(),
-            path: download_path,
-            installation: installation.clone(),
-            was_cached: true,
+    if is_intact(path, hash).await? {
+        return Ok(true);
+    }
+
+    let mut urls = iter::once(url).chain(mirrors).peekable();
+    let mut hasher;
+
+    loop {
+        let url = urls.next().expect("at least one url");
+        let mut total = 0;
+        hasher = Sha256::new();
+
+        let result = request::download(url.clone(), path, retries, |bytes| {
+            let delta = bytes.len() as u64;
+            total += delta;
+            hasher.update(bytes);
+
+            (on_progress)(Progress {
+                delta,
+                completed: total,
+                total: size.max(total),
+            });
+        })
+        .await;
+
+        match result {
+            Ok(()) => break,
+            Err(error) if error.is_transient() && urls.peek().is_some() => {
+                warn!("Download of {url} failed, trying next mirror: {error}");
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    let computed = hex::encode(hasher.finalize());
+
+    if computed != hash {
+        fs::remove_file(path).await?;
+
+        return Err(Error::HashMismatch {
+            expected: hash.to_string(),
+            computed,
         });
     }
 
-    let mut bytes = request::get(url).await?;
-    let mut out = File::create(&download_path).await?;
+    Ok(false)
+}
+
+/// Returns true if the file at `path` exists with the expected `hash`, removing it if it's damaged
+async fn is_intact(path: &Path, hash: &str) -> Result<bool, Error> {
+    if !fs::try_exists(path).await? {
+        return Ok(false);
+    }
+
+    // Only trust a cached file if it's still intact, otherwise refetch it
+    if file_hash(path).await? == hash {
+        return Ok(true);
+    }
 
-    let mut total = 0;
+    fs::remove_file(path).await?;
 
-    while let Some(chunk) = bytes.next().await {
-        let bytes = chunk?;
-        let delta = bytes.len() as u64;
-        total += delta;
-        out.write_all(&bytes).await?;
+    Ok(false)
+}
+
+/// Returns the first delta of `meta` applying to a release whose assets are all in the asset store
+fn usable_delta<'a>(
+    meta: &'a package::Meta,
+    installation: &Installation,
+    layout_db: &db::layout::Database,
+) -> Result<Option<&'a package::meta::Delta>, Error> {
+    for delta in &meta.deltas {
+        let layouts = layout_db.query([&package::Id::from(delta.from.clone())])?;
+
+        // We know nothing of releases that were never installed
+        if layouts.is_empty() {
+            continue;
+        }
 
-        (on_progress)(Progress {
-            delta,
-            completed: total,
-            total: meta.download_size.unwrap_or(total),
+        let complete = layouts.iter().all(|(_, layout)| match &layout.entry {
+            payload::layout::Entry::Regular(hash, _) => asset_path(installation, &format!("{hash:02x}")).exists(),
+            _ => true,
         });
+
+        if complete {
+            return Ok(Some(delta));
+        }
     }
 
-    out.flush().await?;
+    Ok(None)
+}
 
-    Ok(Download {
+/// Compute the hex encoded sha256 of the file at `path`
+async fn file_hash(path: &Path) -> Result<String, Error> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Use the local `.stone` file at `path` for the provided [`package::Meta`] and return a [`Download`]
+/// without fetching it into the download cache.
+pub fn local(meta: &package::Meta, path: impl Into<PathBuf>, installation: &Installation) -> Download {
+    Download {
         id: meta.id().into(),
-        path: download_path,
+        path: path.into(),
         installation: installation.clone(),
         was_cached: false,
-    })
+        was_delta: false,
+    }
 }
 
 /// A package that has been downloaded to the installation
@@ -116,6 +270,8 @@ pub struct Download {
     path: PathBuf,
     installation: Installation,
     pub was_cached: bool,
+    /// A delta was fetched instead of the full package
+    pub was_delta: bool,
 }
 
 /// Upon fetch completion we have this unpacked asset bound with
@@ -187,6 +343,27 @@ impl Download {
             .flat_map(|p| &p.body)
             .collect::<Vec<_>>();
 
+        // A delta only carries the assets missing from the release it applies
+        // to, so the rest must already be in the asset store
+        if reader.header.file_type() == FileType::Delta {
+            let provided = indices.iter().map(|index| index.digest).collect::<HashSet<_>>();
+            let missing = payloads
+                .iter()
+                .filter_map(PayloadKind::layout)
+                .flat_map(|p| &p.body)
+                .filter(|layout| match &layout.entry {
+                    payload::layout::Entry::Regular(hash, _) => {
+                        !provided.contains(hash) && !asset_path(&self.installation, &format!("{hash:02x}")).exists()
+                    }
+                    _ => false,
+                })
+                .count();
+
+            if missing > 0 {
+                return Err(Error::IncompleteDelta(missing));
+            }
+        }
+
         // If we don't have any files to unpack OR download was cached
         // & all assets exist, we can skip unpacking
         if indices.is_empty() || (self.was_cached && check_assets_exist(&indices, &self.installation)) {
@@ -300,8 +477,12 @@ pub enum Error {
     MissingUri,
     #[error("Missing content payload")]
     MissingContent,
+    #[error("Delta is missing {0} assets of the release it applies to")]
+    IncompleteDelta(usize),
     #[error("Malformed download hash: {0}")]
     MalformedHash(String),
+    #[error("Download hash mismatch, expected {expected} got {computed}")]
+    HashMismatch { expected: String, computed: String },
     #[error("stone format")]
     Format(#[from] stone::read::Error),
     #[error("invalid url")]
@@ -310,4 +491,120 @@ pub enum Error {
     Request(#[from] request::Error),
     #[error("io")]
     Io(#[from] io::Error),
+    #[error("layout db")]
+    DB(#[from] db::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STONE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../test/bash-completion-2.11-1-1-x86_64.stone"
+    );
+
+    fn test_meta(hash: &str) -> package::Meta {
+        package::Meta {
+            uri: Some(Url::from_file_path(STONE).unwrap().to_string()),
+            hash: Some(hash.to_string()),
+            ..package::Meta::test("bash-completion", "2.11")
+        }
+    }
+
+    fn no_mirrors(_: &Url) -> Vec<Url> {
+        vec![]
+    }
+
+    #[tokio::test]
+    async fn verify_download_hash() {
+        let root = std::env::temp_dir().join(format!("moss-cache-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let installation = Installation::open(&root).unwrap();
+        let layout_db = db::layout::Database::new(":memory:").unwrap();
+
+        let hash = file_hash(Path::new(STONE)).await.unwrap();
+
+        // Corrupt download is rejected and removed
+        let bad_hash = "0".repeat(64);
+        let result = fetch(&test_meta(&bad_hash), &installation, &layout_db, no_mirrors, |_| {}).await;
+        assert!(matches!(result, Err(Error::HashMismatch { .. })));
+        assert!(!download_path(&installation, &bad_hash).unwrap().exists());
+
+        // Valid download is kept
+        let meta = test_meta(&hash);
+        let download = fetch(&meta, &installation, &layout_db, no_mirrors, |_| {})
+            .await
+            .unwrap();
+        assert!(!download.was_cached);
+        let download = fetch(&meta, &installation, &layout_db, no_mirrors, |_| {})
+            .await
+            .unwrap();
+        assert!(download.was_cached);
+
+        // Stale cached file is fetched again
+        let path = download_path(&installation, &hash).unwrap();
+        std::fs::write(&path, b"truncated").unwrap();
+        let download = fetch(&meta, &installation, &layout_db, no_mirrors, |_| {})
+            .await
+            .unwrap();
+        assert!(!download.was_cached);
+        assert_eq!(file_hash(&path).await.unwrap(), hash);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn prefer_delta() {
+        let root = std::env::temp_dir().join(format!("moss-delta-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let installation = Installation::open(&root).unwrap();
+        let layout_db = db::layout::Database::new(":memory:").unwrap();
+
+        // The full package is unavailable, so only the delta can be fetched
+        let mut meta = test_meta(&"f".repeat(64));
+        meta.uri = Some(Url::from_file_path(root.join("missing.stone")).unwrap().to_string());
+        meta.deltas = vec![package::meta::Delta {
+            from: "previous".into(),
+            uri: Url::from_file_path(STONE).unwrap().to_string(),
+            hash: file_hash(Path::new(STONE)).await.unwrap(),
+            size: std::fs::metadata(STONE).unwrap().len(),
+        }];
+
+        // Previous release was never installed
+        let result = fetch(&meta, &installation, &layout_db, no_mirrors, |_| {}).await;
+        assert!(result.is_err());
+
+        // Previous release is installed, but its assets are gone
+        let asset = 0xdeadbeef_u128;
+        layout_db
+            .add(
+                package::Id::from("previous".to_string()),
+                payload::Layout {
+                    uid: 0,
+                    gid: 0,
+                    mode: 0o100644,
+                    tag: 0,
+                    entry: payload::layout::Entry::Regular(asset, "share/file".into()),
+                },
+            )
+            .unwrap();
+        let result = fetch(&meta, &installation, &layout_db, no_mirrors, |_| {}).await;
+        assert!(result.is_err());
+
+        let asset_file = asset_path(&installation, &format!("{asset:02x}"));
+        std::fs::create_dir_all(asset_file.parent().unwrap()).unwrap();
+        std::fs::write(&asset_file, b"asset").unwrap();
+
+        let download = fetch(&meta, &installation, &layout_db, no_mirrors, |_| {})
+            .await
+            .unwrap();
+        assert!(download.was_delta);
+        assert_eq!(
+            download.path,
+            download_path(&installation, &meta.deltas[0].hash).unwrap()
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,303 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Compare the packages, and optionally the files, of two states
+//!
+//! Packages are matched by name, so a package present in both states with a
+//! different id was either upgraded or downgraded between them.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use serde::Serialize;
+use thiserror::Error;
+use vfs::tree::BlitFile;
+
+use super::{plan::Version, Client, PendingFile};
+use crate::{db, dependency, package, state, State};
+
+/// Changes from one state to another
+#[derive(Debug, Clone, Serialize)]
+pub struct Diff {
+    pub from: state::Id,
+    pub to: state::Id,
+    /// Packages only in the newer state
+    pub added: Vec<Entry>,
+    /// Packages only in the older state
+    pub removed: Vec<Entry>,
+    /// Packages with a newer version in the newer state
+    pub upgraded: Vec<Update>,
+    /// Packages with an older version in the newer state
+    pub downgraded: Vec<Update>,
+    /// File-level changes, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<FileChange>>,
+}
+
+/// A package present in only one of the states
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub id: String,
+    #[serde(flatten)]
+    pub version: Version,
+}
+
+/// A package with a different version in each state
+#[derive(Debug, Clone, Serialize)]
+pub struct Update {
+    pub name: String,
+    pub from_id: String,
+    pub to_id: String,
+    pub from: Version,
+    pub to: Version,
+}
+
+/// A file that differs between the states
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    /// Absolute path within the installation, i.e. `/usr/bin/bash`
+    pub path: String,
+    pub change: Change,
+}
+
+/// How a file differs between the states
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Change {
+    Added,
+    Removed,
+    /// Content, type or metadata changed
+    Modified,
+}
+
+impl Diff {
+    /// Returns true if both states have the same packages
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.upgraded.is_empty() && self.downgraded.is_empty()
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added => write!(f, "+"),
+            Change::Removed => write!(f, "-"),
+            Change::Modified => write!(f, "~"),
+        }
+    }
+}
+
+/// Compare state `from` to state `to`, including their files if `files` is set
+pub fn diff(client: &Client, from: state::Id, to: state::Id, files: bool) -> Result<Diff, Error> {
+    let from_state = client.state_db.get(from)?;
+    let to_state = client.state_db.get(to)?;
+
+    let old = packages(client, &from_state)?;
+    let new = packages(client, &to_state)?;
+
+    let entry = |id: &package::Id, meta: &package::Meta| Entry {
+        name: meta.name.to_string(),
+        id: id.to_string(),
+        version: Version::new(meta),
+    };
+
+    let mut diff = Diff {
+        from,
+        to,
+        added: new
+            .iter()
+            .filter(|(name, _)| !old.contains_key(*name))
+            .map(|(_, (id, meta))| entry(id, meta))
+            .collect(),
+        removed: old
+            .iter()
+            .filter(|(name, _)| !new.contains_key(*name))
+            .map(|(_, (id, meta))| entry(id, meta))
+            .collect(),
+        upgraded: vec![],
+        downgraded: vec![],
+        files: None,
+    };
+
+    for (name, (from_id, from_meta)) in &old {
+        let Some((to_id, to_meta)) = new.get(name).filter(|(id, _)| id != from_id) else {
+            continue;
+        };
+
+        let update = Update {
+            name: name.to_string(),
+            from_id: from_id.to_string(),
+            to_id: to_id.to_string(),
+            from: Version::new(from_meta),
+            to: Version::new(to_meta),
+        };
+
+        // A rebuild of the same version counts as an upgrade
+        if compare(to_meta, from_meta).is_lt() {
+            diff.downgraded.push(update);
+        } else {
+            diff.upgraded.push(update);
+        }
+    }
+
+    if files {
+        let old = files_by_path(&client.vfs(from_state.selections.iter().map(|s| &s.package))?);
+        let new = files_by_path(&client.vfs(to_state.selections.iter().map(|s| &s.package))?);
+
+        diff.files = Some(
+            changes(&old, &new)
+                .map(|(path, change)| FileChange {
+                    path: path.clone(),
+                    change,
+                })
+                .collect(),
+        );
+    }
+
+    Ok(diff)
+}
+
+/// The packages of `state` by name
+fn packages(client: &Client, state: &State) -> Result<BTreeMap<package::Name, (package::Id, package::Meta)>, Error> {
+    state
+        .selections
+        .iter()
+        .map(|selection| {
+            let meta = client.install_db.get(&selection.package)?;
+            Ok((meta.name.clone(), (selection.package.clone(), meta)))
+        })
+        .collect()
+}
+
+/// Files added, modified or removed going from tree `old` to `new`, as found in `new` unless removed
+pub(super) fn changed_files(old: &vfs::Tree<PendingFile>, new: &vfs::Tree<PendingFile>) -> Vec<PendingFile> {
+    let old = files_by_path(old);
+    let new = files_by_path(new);
+
+    changes(&old, &new)
+        .filter_map(|(path, _)| new.get(path).or_else(|| old.get(path)).cloned())
+        .collect()
+}
+
+/// The files of `tree` by path
+fn files_by_path(tree: &vfs::Tree<PendingFile>) -> BTreeMap<String, PendingFile> {
+    tree.iter()
+        .map(|file| (file.path(), file))
+        // The root is implied by every tree
+        .filter(|(path, _)| path != "/")
+        .collect()
+}
+
+/// Paths added, removed or modified going from `old` to `new`
+fn changes<'a>(
+    old: &'a BTreeMap<String, PendingFile>,
+    new: &'a BTreeMap<String, PendingFile>,
+) -> impl Iterator<Item = (&'a String, Change)> {
+    old.keys()
+        .chain(new.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|path| {
+            let change = match (old.get(path), new.get(path)) {
+                (None, Some(_)) => Change::Added,
+                (Some(_), None) => Change::Removed,
+                (Some(a), Some(b)) if a.layout != b.layout => Change::Modified,
+                _ => return None,
+            };
+
+            Some((path, change))
+        })
+}
+
+/// Order two versions of a package by release, then version, then build
+fn compare(a: &package::Meta, b: &package::Meta) -> Ordering {
+    a.source_release
+        .cmp(&b.source_release)
+        .then_with(|| dependency::compare_versions(&a.version_identifier, &b.version_identifier))
+        .then_with(|| a.build_release.cmp(&b.build_release))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] super::Error),
+
+    #[error("db")]
+    DB(#[from] db::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use stone::payload::layout;
+
+    use super::*;
+
+    fn meta(version: &str, release: u64, build_release: u64) -> package::Meta {
+        package::Meta {
+            source_release: release,
+            build_release,
+            ..package::Meta::test("test", version)
+        }
+    }
+
+    #[test]
+    fn ordering() {
+        // Release takes precedence, as versions may be reset by upstream
+        assert!(compare(&meta("1.0", 2, 1), &meta("2.0", 1, 1)).is_gt());
+        assert!(compare(&meta("1.10", 1, 1), &meta("1.9", 1, 1)).is_gt());
+        assert!(compare(&meta("1.0", 1, 1), &meta("1.0", 1, 2)).is_lt());
+        assert!(compare(&meta("1.0", 1, 1), &meta("1.0", 1, 1)).is_eq());
+    }
+
+    fn tree(files: &[(&str, u128)]) -> vfs::Tree<PendingFile> {
+        let mut builder = vfs::tree::builder::TreeBuilder::new();
+        for (path, hash) in files {
+            builder.push(PendingFile {
+                id: package::Id::from("test".to_string()),
+                layout: layout::Layout {
+                    uid: 0,
+                    gid: 0,
+                    mode: 0o644,
+                    tag: 0,
+                    entry: layout::Entry::Regular(*hash, path.to_string()),
+                },
+            });
+        }
+        builder.bake();
+        builder.tree().unwrap()
+    }
+
+    #[test]
+    fn changed_files_between_trees() {
+        let old = tree(&[("bin/same", 1), ("bin/modified", 2), ("bin/removed", 3)]);
+        let new = tree(&[("bin/same", 1), ("bin/modified", 4), ("bin/added", 5)]);
+
+        let changed = changed_files(&old, &new)
+            .into_iter()
+            .map(|file| (file.path(), file.layout.entry))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            changed,
+            [
+                (
+                    "/usr/bin/added".to_string(),
+                    layout::Entry::Regular(5, "bin/added".into())
+                ),
+                (
+                    "/usr/bin/modified".to_string(),
+                    layout::Entry::Regular(4, "bin/modified".into())
+                ),
+                (
+                    "/usr/bin/removed".to_string(),
+                    layout::Entry::Regular(3, "bin/removed".into())
+                ),
+            ]
+        );
+    }
+}
@@ -4,16 +4,25 @@
 
 //! Installation-specific code for several core moss operations
 
-use std::time::{Duration, Instant};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use thiserror::Error;
 use tui::{
     dialoguer::{theme::ColorfulTheme, Confirm},
     pretty::autoprint_columns,
+    Styled,
 };
 
 use crate::{
-    client::{self, Client},
+    client::{
+        self,
+        plan::{self, Plan, Reason},
+        Client,
+    },
     package::{self, Flags},
     registry::transaction,
     runtime,
@@ -21,18 +30,43 @@ use crate::{
     Package, Provider,
 };
 
+/// Options controlling how an install is presented & applied
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Don't prompt before applying the install
+    pub yes: bool,
+    /// Only build the plan, stopping before anything is fetched or blitted
+    pub dry_run: bool,
+}
+
 /// Install a set of packages
 /// If this call is successful a new State is recorded into the [`super::db::state::Database`].
 /// Upon completion the `/usr` tree is "hot swapped" with the staging tree through `renameat2` call.
-pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing, Error> {
+///
+/// A dry run returns the plan without printing it, leaving its presentation to the caller.
+pub fn install(client: &mut Client, pkgs: &[&str], options: Options) -> Result<Outcome, Error> {
     let mut timing = Timing::default();
     let mut instant = Instant::now();
 
+    // A dry run never modifies the installation
+    if !options.dry_run {
+        client.prepare_mutation()?;
+    }
+
+    // Load local stones, these are always installed over existing packages
+    let (local_paths, names) = pkgs.iter().partition::<Vec<&str>, _>(|p| is_local_stone(p));
+    if let Some(missing) = local_paths.iter().find(|path| !Path::new(path).is_file()) {
+        return Err(Error::FileNotFound(missing.to_string()));
+    }
+    let local = client.add_local_packages(local_paths.iter().map(Path::new))?;
+
     // Resolve input packages
-    let input = resolve_input(pkgs, client)?;
+    let restrictions = client.restrictions()?;
+    let (resolved_input, held) = resolve_input(&names, client, &restrictions)?;
+    let input = local.iter().cloned().chain(resolved_input).collect::<Vec<_>>();
 
     // Add all inputs
-    let mut tx = client.registry.transaction()?;
+    let mut tx = client.transaction()?;
 
     tx.add(input.clone())?;
 
@@ -41,7 +75,14 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
 
     // Get installed packages to check against
     let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
-    let is_installed = |p: &Package| installed.iter().any(|i| i.meta.name == p.meta.name);
+    // Input packages count as installed by name, dependencies must be the exact
+    // installed package as another version was only picked to satisfy a constraint
+    let is_installed = |p: &Package| {
+        !local.contains(&p.id)
+            && installed
+                .iter()
+                .any(|i| i.id == p.id || (input.contains(&p.id) && i.meta.name == p.meta.name))
+    };
 
     // Get missing packages that are:
     //
@@ -52,11 +93,67 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
         .filter(|p| client.is_ephemeral() || !is_installed(p))
         .collect::<Vec<_>>();
 
+    // Installed packages replaced by another package of the same name,
+    // i.e. a local stone or a version satisfying a dependency constraint
+    let replaced = installed
+        .iter()
+        .filter(|i| missing.iter().any(|p| p.id != i.id && p.meta.name == i.meta.name))
+        .collect::<Vec<_>>();
+
+    // Installed packages conflicting with a package being installed, which are
+    // replaced by it. Ephemeral installs don't carry over the installed set.
+    let conflicting = if client.is_ephemeral() {
+        vec![]
+    } else {
+        installed
+            .iter()
+            .filter(|i| !replaced.iter().any(|r| r.id == i.id))
+            .filter_map(|i| missing.iter().find_map(|p| Some((i, *p, p.conflict_with(i)?))))
+            .collect::<Vec<_>>()
+    };
+    check_conflict_dependents(&installed, &replaced, &conflicting, &missing)?;
+
+    let plan = {
+        let mut plan = Plan::default();
+
+        for package in &missing {
+            let reason = if input.contains(&package.id) {
+                Reason::Requested
+            } else {
+                Reason::Dependency {
+                    required_by: plan::required_by(package, &resolved),
+                }
+            };
+
+            match replaced.iter().find(|i| i.meta.name == package.meta.name) {
+                Some(from) => plan.upgrade(from, package, reason),
+                None => plan.add(package, reason),
+            }
+        }
+        for (installed, package, _) in &conflicting {
+            plan.remove(
+                installed,
+                Reason::Conflict {
+                    conflicts_with: package.meta.name.to_string(),
+                },
+            );
+        }
+        for (package, candidate) in &held {
+            plan.hold(package, candidate);
+        }
+
+        plan
+    };
+
     timing.resolve = instant.elapsed();
 
-    // If no new packages exist, exit and print
-    // packages already installed
+    // Only the plan was requested
+    if options.dry_run {
+        return Ok(Outcome { plan, timing });
+    }
+
     if missing.is_empty() {
+        // If no new packages exist, print packages already installed
         let installed = resolved
             .iter()
             .filter(|p| is_installed(p) && input.contains(&p.id))
@@ -67,17 +164,35 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
             println!();
             autoprint_columns(&installed);
         }
+        print_held(&held);
+    } else {
+        println!("The following package(s) will be installed:");
+        println!();
+        autoprint_columns(&missing);
+        println!();
 
-        return Ok(timing);
+        if !conflicting.is_empty() {
+            println!("The following conflicting package(s) will be replaced:");
+            println!();
+            for (installed, package, provider) in &conflicting {
+                println!(
+                    "{} {}",
+                    installed.meta.name.to_string().bold(),
+                    format!("(conflicts with {} on {provider})", package.meta.name).dim()
+                );
+            }
+            println!();
+        }
+        print_held(&held);
     }
 
-    println!("The following package(s) will be installed:");
-    println!();
-    autoprint_columns(&missing);
-    println!();
+    // Nothing to do
+    if missing.is_empty() {
+        return Ok(Outcome { plan, timing });
+    }
 
     // Must we prompt?
-    let result = if yes {
+    let result = if options.yes {
         true
     } else {
         Confirm::with_theme(&ColorfulTheme::default())
@@ -104,11 +219,15 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
             Some(id) if !client.is_ephemeral() => client.state_db.get(id)?.selections,
             _ => vec![],
         };
+        // Drop installed packages replaced by another of the same name or a conflicting package
+        let previous_selections = previous_selections.into_iter().filter(|s| {
+            !replaced.iter().any(|i| i.id == s.package) && !conflicting.iter().any(|(i, _, _)| i.id == s.package)
+        });
         let missing_selections = missing.iter().map(|p| Selection {
             package: p.id.clone(),
             // Package is explicit if it was one of the input
             // packages provided by the user
-            explicit: input.iter().any(|id| *id == p.id),
+            explicit: input.contains(&p.id),
             reason: None,
         });
 
@@ -120,40 +239,148 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
 
     timing.blit = instant.elapsed();
 
-    Ok(timing)
+    Ok(Outcome { plan, timing })
 }
 
-/// Resolves the package arguments as valid input packages. Returns an error
-/// if any args are invalid.
-fn resolve_input(pkgs: &[&str], client: &Client) -> Result<Vec<package::Id>, Error> {
-    // Parse pkg args into valid / invalid sets
-    let queried = pkgs.iter().map(|p| find_packages(p, client));
+/// A package kept back by a hold, along with its preferred candidate
+type Held = (Package, Package);
 
+/// Resolves the package arguments as valid input packages, along with any package
+/// a hold kept back from its preferred candidate. Returns an error if any args are invalid.
+fn resolve_input(
+    pkgs: &[&str],
+    client: &Client,
+    restrictions: &BTreeMap<package::Name, Vec<package::Id>>,
+) -> Result<(Vec<package::Id>, Vec<Held>), Error> {
     let mut results = vec![];
+    let mut held = vec![];
 
-    for (id, pkg) in queried {
-        if let Some(pkg) = pkg {
-            results.push(pkg.id)
-        } else {
+    for pkg in pkgs {
+        let (id, candidates) = find_packages(pkg, client);
+        let Some(first) = candidates.first() else {
             return Err(Error::NoPackage(id));
+        };
+        // A hold may keep an installed package no longer available
+        let installed = installed_packages(pkg, client);
+
+        let allowed = candidates.iter().chain(&installed).find(|p| {
+            restrictions
+                .get(&p.meta.name)
+                .is_none_or(|allowed| allowed.contains(&p.id))
+        });
+
+        match allowed {
+            Some(package) if package.id != first.id => {
+                held.push((package.clone(), first.clone()));
+                results.push(package.id.clone());
+            }
+            // Nothing allowed is left for the transaction to report
+            _ => results.push(first.id.clone()),
         }
     }
 
-    Ok(results)
+    Ok((results, held))
 }
 
-/// Resolve a package name to the first package
-fn find_packages(id: &str, client: &Client) -> (String, Option<Package>) {
+/// Print the packages kept back by a hold, if any
+fn print_held(held: &[Held]) {
+    if held.is_empty() {
+        return;
+    }
+
+    println!("The following package(s) are held back:");
+    println!();
+    for (package, candidate) in held {
+        println!(
+            "{} {}",
+            package.meta.name.to_string().bold(),
+            format!(
+                "({}-{}, {}-{} available)",
+                package.meta.version_identifier,
+                package.meta.source_release,
+                candidate.meta.version_identifier,
+                candidate.meta.source_release
+            )
+            .dim()
+        );
+    }
+    println!();
+}
+
+/// Ensure replacing the `conflicting` installed packages doesn't leave
+/// any remaining installed package without a provider it depends on
+fn check_conflict_dependents(
+    installed: &[Package],
+    replaced: &[&Package],
+    conflicting: &[(&Package, &Package, &Provider)],
+    missing: &[&Package],
+) -> Result<(), Error> {
+    let kept = installed
+        .iter()
+        .filter(|i| !replaced.iter().any(|r| r.id == i.id) && !conflicting.iter().any(|(c, _, _)| c.id == i.id))
+        .collect::<Vec<_>>();
+
+    for dependent in &kept {
+        for dependency in &dependent.meta.dependencies {
+            let provider = Provider {
+                kind: dependency.kind,
+                name: dependency.name.clone(),
+            };
+            let Some((installed, package, conflict)) = conflicting
+                .iter()
+                .find(|(installed, _, _)| installed.meta.providers.contains(&provider))
+            else {
+                continue;
+            };
+            let still_provided = kept.iter().chain(missing).any(|p| p.meta.providers.contains(&provider));
+
+            if !still_provided {
+                return Err(Error::ConflictRequired {
+                    package: package.meta.name.to_string(),
+                    installed: installed.meta.name.to_string(),
+                    provider: conflict.to_string(),
+                    dependent: dependent.meta.name.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns true if the argument refers to a local `.stone` file, rather than a package name
+fn is_local_stone(arg: &str) -> bool {
+    arg.ends_with(".stone")
+}
+
+/// Resolve a package name to all available packages, in order of preference
+fn find_packages(id: &str, client: &Client) -> (String, Vec<Package>) {
     let provider = Provider::from_name(id).unwrap();
     let result = client
         .registry
         .by_provider(&provider, Flags::new().with_available())
-        .next();
+        .collect();
 
-    // First only, pre-sorted
+    // Pre-sorted
     (id.into(), result)
 }
 
+/// Resolve a package name to the installed packages providing it
+fn installed_packages(id: &str, client: &Client) -> Vec<Package> {
+    let provider = Provider::from_name(id).unwrap();
+    client
+        .registry
+        .by_provider(&provider, Flags::new().with_installed())
+        .collect()
+}
+
+/// The result of an install
+pub struct Outcome {
+    /// Changes made to the installation, or those a dry run would make
+    pub plan: Plan,
+    pub timing: Timing,
+}
+
 /// Simple timing information for Install
 #[derive(Default)]
 pub struct Timing {
@@ -177,6 +404,19 @@ pub enum Error {
     #[error("no package found: {0}")]
     NoPackage(String),
 
+    /// A local `.stone` file doesn't exist
+    #[error("file not found: {0}")]
+    FileNotFound(String),
+
+    /// A conflicting installed package can't be replaced as others depend on it
+    #[error("{package} conflicts with installed {installed} ({provider}), which {dependent} depends on")]
+    ConflictRequired {
+        package: String,
+        installed: String,
+        provider: String,
+        dependent: String,
+    },
+
     /// A transaction specific error occurred
     #[error("transaction")]
     Transaction(#[from] transaction::Error),
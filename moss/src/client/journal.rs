@@ -0,0 +1,203 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Journal of in-progress state transitions
+//!
+//! Before each step of a transaction that alters the installation (staging,
+//! promotion, archival & system triggers) the step is recorded to the journal. If
+//! moss is interrupted the journal is left behind, allowing the transaction to be
+//! rolled back, or forward from the step it was interrupted at.
+
+use std::{fmt, fs, io};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{state, Installation};
+
+/// The kind of transaction being journaled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    /// A new state is being blitted & activated
+    NewState,
+    /// An archived state is being activated
+    Activate,
+}
+
+/// The step the transaction is about to perform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Step {
+    /// Populating the staging tree
+    Stage,
+    /// Swapping the staging tree with `/usr`
+    Promote,
+    /// Archiving the previously active `/usr`
+    Archive,
+    /// Running the system triggers of the new state
+    Triggers,
+}
+
+/// An in-progress transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Journal {
+    pub operation: Operation,
+    pub step: Step,
+    /// State that was active when the transaction began
+    pub old: Option<state::Id>,
+    /// State being activated, once it's known
+    pub new: Option<state::Id>,
+}
+
+impl Journal {
+    /// Begin journaling a transaction
+    pub fn begin(
+        installation: &Installation,
+        operation: Operation,
+        old: Option<state::Id>,
+        new: Option<state::Id>,
+    ) -> Result<Self, Error> {
+        let journal = Self {
+            operation,
+            step: Step::Stage,
+            old,
+            new,
+        };
+        journal.write(installation)?;
+        Ok(journal)
+    }
+
+    /// Record the state being activated
+    pub fn set_new(&mut self, installation: &Installation, new: state::Id) -> Result<(), Error> {
+        self.new = Some(new);
+        self.write(installation)
+    }
+
+    /// Record the next step is about to be performed
+    pub fn step(&mut self, installation: &Installation, step: Step) -> Result<(), Error> {
+        self.step = step;
+        self.write(installation)
+    }
+
+    /// How to recover the interrupted transaction, given whether its new state was
+    /// `promoted` to `/usr`
+    pub fn resume(&self, promoted: bool) -> Resume {
+        let Some(new) = self.new else {
+            return Resume::RollBack;
+        };
+
+        match self.step {
+            Step::Stage => Resume::RollBack,
+            // Interrupted either side of the swap
+            Step::Promote if !promoted => Resume::RollBack,
+            Step::Promote | Step::Archive => Resume::RollForward {
+                new,
+                from: Step::Archive,
+            },
+            Step::Triggers => Resume::RollForward {
+                new,
+                from: Step::Triggers,
+            },
+        }
+    }
+
+    /// Mark the transaction as complete, removing the journal
+    pub fn complete(self, installation: &Installation) -> Result<(), Error> {
+        clear(installation)
+    }
+
+    /// Durably replace the journal file, so it's never seen half written
+    fn write(&self, installation: &Installation) -> Result<(), Error> {
+        let path = installation.journal_path();
+        let temp = path.with_extension("new");
+
+        let file = fs::File::create(&temp)?;
+        serde_yaml::to_writer(&file, self)?;
+        file.sync_all()?;
+        fs::rename(temp, path)?;
+
+        Ok(())
+    }
+}
+
+/// Load the journal of an interrupted transaction, if any
+pub fn load(installation: &Installation) -> Result<Option<Journal>, Error> {
+    match fs::File::open(installation.journal_path()) {
+        Ok(file) => Ok(Some(serde_yaml::from_reader(file)?)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Remove the journal
+pub fn clear(installation: &Installation) -> Result<(), Error> {
+    match fs::remove_file(installation.journal_path()) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// How an interrupted transaction is recovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Undo the transaction, restoring the previously active state
+    RollBack,
+    /// Complete the transaction activating `new`, starting with the `from` step
+    RollForward { new: state::Id, from: Step },
+}
+
+/// Outcome of recovering an interrupted transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// The new state was already promoted, so the transaction was completed
+    RolledForward(state::Id),
+    /// The new state was never promoted, so the transaction was undone
+    RolledBack(Option<state::Id>),
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recovery::RolledForward(id) => write!(f, "completed interrupted transaction, state {id} is active"),
+            Recovery::RolledBack(Some(id)) => write!(f, "rolled back interrupted transaction, state {id} is active"),
+            Recovery::RolledBack(None) => write!(f, "rolled back interrupted transaction"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+    #[error("serialize")]
+    Serde(#[from] serde_yaml::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resume_steps() {
+        let journal = |step, new| Journal {
+            operation: Operation::NewState,
+            step,
+            old: Some(state::Id::from(1)),
+            new,
+        };
+        let new = state::Id::from(2);
+        let forward = |from| Resume::RollForward { new, from };
+
+        // Nothing to activate without a new state
+        assert_eq!(journal(Step::Stage, None).resume(false), Resume::RollBack);
+
+        assert_eq!(journal(Step::Stage, Some(new)).resume(false), Resume::RollBack);
+        assert_eq!(journal(Step::Promote, Some(new)).resume(false), Resume::RollBack);
+        assert_eq!(journal(Step::Promote, Some(new)).resume(true), forward(Step::Archive));
+        assert_eq!(journal(Step::Archive, Some(new)).resume(true), forward(Step::Archive));
+        assert_eq!(journal(Step::Triggers, Some(new)).resume(true), forward(Step::Triggers));
+    }
+}
@@ -0,0 +1,163 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Portable state manifests
+//!
+//! A manifest records the exact packages of a state along with the repositories
+//! they came from, so the state can be reproduced on another installation. No
+//! resolution happens on import, every package is looked up by its id and its
+//! hash checked against the one recorded at export.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::Client;
+use crate::{db, package, repository, state, Package};
+
+/// An exported state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The state this manifest was exported from
+    pub state: state::Id,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Repositories providing the packages
+    pub repositories: repository::Map,
+    /// Every package of the state, ordered by name
+    pub packages: Vec<Entry>,
+}
+
+/// A package selected in a [`Manifest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    pub id: package::Id,
+    pub version: String,
+    pub release: u64,
+    /// Hash of the `.stone`, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Explicitly selected, rather than a transitive dependency
+    pub explicit: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Export state `id` along with the configured repositories
+pub fn export(client: &Client, id: state::Id) -> Result<Manifest, Error> {
+    let state = client.state_db.get(id)?;
+
+    let mut packages = state
+        .selections
+        .iter()
+        .map(|selection| {
+            let meta = client.install_db.get(&selection.package)?;
+
+            Ok(Entry {
+                name: meta.name.to_string(),
+                id: selection.package.clone(),
+                version: meta.version_identifier,
+                release: meta.source_release,
+                hash: meta.hash,
+                explicit: selection.explicit,
+                reason: selection.reason.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Manifest {
+        state: state.id,
+        summary: state.summary,
+        repositories: repository::Map::with(
+            client
+                .repositories
+                .list()
+                .map(|(id, repository)| (id.clone(), repository.clone())),
+        ),
+        packages,
+    })
+}
+
+/// Look up every package of `manifest`, ensuring each matches its recorded hash
+pub fn resolve(client: &Client, manifest: &Manifest) -> Result<Vec<Package>, Error> {
+    manifest
+        .packages
+        .iter()
+        .map(|entry| {
+            let package = client
+                .registry
+                .by_id(&entry.id)
+                .next()
+                .ok_or_else(|| Error::MissingPackage(entry.name.clone(), entry.id.clone()))?;
+
+            if let (Some(expected), Some(found)) = (&entry.hash, &package.meta.hash) {
+                if expected != found {
+                    return Err(Error::HashMismatch {
+                        name: entry.name.clone(),
+                        expected: expected.clone(),
+                        found: found.clone(),
+                    });
+                }
+            }
+
+            Ok(package)
+        })
+        .collect()
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0} ({1}) isn't available from any repository")]
+    MissingPackage(String, package::Id),
+
+    #[error("{name} has hash {found}, but the manifest expects {expected}")]
+    HashMismatch {
+        name: String,
+        expected: String,
+        found: String,
+    },
+
+    #[error("db")]
+    DB(#[from] db::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_manifest() {
+        let manifest: Manifest = serde_yaml::from_str(
+            "
+state: 4
+repositories:
+  volatile:
+    description: Volatile
+    uri: https://example.com/stone.index
+    priority: 0
+packages:
+- name: nano
+  id: abc123
+  version: '8.0'
+  release: 3
+  explicit: true
+",
+        )
+        .unwrap();
+
+        assert_eq!(manifest.state, state::Id::from(4));
+        assert!(manifest.summary.is_none());
+        assert!(manifest
+            .repositories
+            .get(&repository::Id::new("volatile".into()))
+            .is_some());
+        assert_eq!(manifest.packages[0].id, package::Id::from("abc123".to_string()));
+        assert!(manifest.packages[0].hash.is_none());
+
+        let exported = serde_yaml::to_string(&manifest).unwrap();
+        assert!(!exported.contains("hash"));
+        assert!(!exported.contains("reason"));
+    }
+}
@@ -9,6 +9,8 @@
 //! operations
 
 use std::{
+    collections::BTreeMap,
+    fmt,
     fs::{self, create_dir_all},
     io,
     os::{fd::RawFd, unix::fs::symlink},
@@ -22,8 +24,8 @@ use nix::{
     errno::Errno,
     fcntl::{self, OFlag},
     libc::{syscall, SYS_renameat2, AT_FDCWD, RENAME_EXCHANGE},
-    sys::stat::{fchmodat, mkdirat, Mode},
-    unistd::{close, linkat, mkdir, symlinkat},
+    sys::stat::{fchmodat, mkdirat, mknodat, FchmodatFlags, Mode, SFlag},
+    unistd::{close, fchownat, linkat, mkdir, mkfifoat, symlinkat, FchownatFlags, Gid, Uid},
 };
 use stone::{payload::layout, read::PayloadKind};
 use thiserror::Error;
@@ -31,19 +33,33 @@ use tui::{MultiProgress, ProgressBar, ProgressStyle, Styled};
 use vfs::tree::{builder::TreeBuilder, BlitFile, Element};
 
 use self::install::install;
+use self::journal::Journal;
 use self::prune::prune;
 use crate::{
-    db, environment, installation, package,
-    registry::plugin::{self, Plugin},
+    db, dependency, environment,
+    hold::{self, Hold},
+    installation, package,
+    registry::{
+        plugin::{self, Plugin},
+        transaction, Transaction,
+    },
     repository, runtime,
     state::{self, Selection},
     Installation, Package, Registry, State,
 };
 
 pub mod cache;
+pub mod diff;
 pub mod install;
+pub mod journal;
+pub mod manifest;
+pub mod plan;
 mod postblit;
 pub mod prune;
+pub mod remove;
+pub mod search;
+pub mod trigger;
+pub mod verify;
 
 /// A Client is a connection to the underlying package management systems
 pub struct Client {
@@ -69,6 +85,13 @@ pub struct Client {
     /// All of our configured repositories, to seed the [`crate::registry::Registry`]
     repositories: repository::Manager,
 
+    /// Local `.stone` files loaded for this client, to seed the [`crate::registry::Registry`]
+    cobble: plugin::Cobble,
+
+    /// Advisory lock on the installation, held for the lifetime of the client. Exclusive
+    /// if requested up front via [`Installation::with_exclusive_lock`], otherwise shared
+    lock: installation::Lock,
+
     /// Operational scope (real systems, ephemeral, etc)
     scope: Scope,
 }
@@ -91,10 +114,16 @@ impl Client {
     /// Build a functioning Client for the given [`Installation`] and repositories
     fn build(
         client_name: impl ToString,
-        installation: Installation,
+        mut installation: Installation,
         repositories: Option<repository::Map>,
     ) -> Result<Client, Error> {
         let name = client_name.to_string();
+        // Lock before opening databases, since migrations may write to them
+        let lock = installation.lock()?;
+        if lock.is_exclusive() {
+            // Another process may have changed the active state before we got the lock
+            installation.refresh_active_state();
+        }
         let config = config::Manager::system(&installation.root, "moss");
         let install_db = db::meta::Database::new(installation.db_path("install").to_str().unwrap_or_default())?;
         let state_db = db::state::Database::new(installation.db_path("state").to_str().unwrap_or_default())?;
@@ -106,7 +135,8 @@ impl Client {
             repository::Manager::system(config.clone(), installation.clone())?
         };
 
-        let registry = build_registry(&installation, &repositories, &install_db, &state_db)?;
+        let cobble = plugin::Cobble::default();
+        let registry = build_registry(&installation, &repositories, &cobble, &install_db, &state_db)?;
 
         Ok(Client {
             name,
@@ -117,18 +147,182 @@ impl Client {
             install_db,
             state_db,
             layout_db,
+            cobble,
+            lock,
             scope: Scope::Stateful,
         })
     }
 
+    /// Prepare for a mutating operation by recovering from any interrupted transaction
+    ///
+    /// The installation must have been locked with [`Installation::with_exclusive_lock`]
+    pub fn prepare_mutation(&mut self) -> Result<(), Error> {
+        if let Some(recovery) = self.repair()? {
+            println!("{} {recovery}", "Warning:".yellow());
+        }
+
+        Ok(())
+    }
+
+    /// Recover from any interrupted transaction, rebuilding the registry if
+    /// the active state changed
+    ///
+    /// Returns how the interrupted transaction was recovered, if there was one
+    pub fn repair(&mut self) -> Result<Option<journal::Recovery>, Error> {
+        self.ensure_exclusive()?;
+
+        let previous = self.installation.active_state;
+
+        // Only stateful clients journal their transactions
+        let recovery = match journal::load(&self.installation)? {
+            Some(journal) if !self.is_ephemeral() => {
+                let recovery = self.recover(journal)?;
+                self.installation.refresh_active_state();
+                Some(recovery)
+            }
+            _ => None,
+        };
+
+        if self.installation.active_state != previous {
+            self.registry = build_registry(
+                &self.installation,
+                &self.repositories,
+                &self.cobble,
+                &self.install_db,
+                &self.state_db,
+            )?;
+        }
+
+        Ok(recovery)
+    }
+
+    /// Ensure the installation is locked exclusively, so it can be mutated
+    fn ensure_exclusive(&self) -> Result<(), Error> {
+        if self.lock.is_exclusive() {
+            Ok(())
+        } else {
+            Err(Error::SharedLock)
+        }
+    }
+
+    /// Roll an interrupted transaction forward if its new state was promoted,
+    /// otherwise roll it back to the previously active state
+    fn recover(&self, journal: Journal) -> Result<journal::Recovery, Error> {
+        let staging_usr = self.installation.staging_path("usr");
+        let promoted = journal.new.is_some() && self.installation.read_active_state() == journal.new;
+
+        match journal.resume(promoted) {
+            // Promotion happened, complete the remaining steps
+            journal::Resume::RollForward { new, from } => {
+                let mut journal = journal;
+                let old = journal.old;
+
+                if let (journal::Step::Archive, Some(old)) = (from, old) {
+                    let archived = self.installation.root_path(old.to_string()).join("usr");
+
+                    if staging_usr.exists() && !archived.exists() {
+                        self.archive_state(old)?;
+                    }
+                }
+
+                create_root_links(&self.installation.root)?;
+
+                // System triggers never ran, or were interrupted, for the new state
+                journal.step(&self.installation, journal::Step::Triggers)?;
+                let state = self.state_db.get(new)?;
+                let fstree = self.vfs(state.selections.iter().map(|selection| &selection.package))?;
+                let changed = self.changed_files(old, &fstree)?;
+                let mut executor = postblit::Executor::new(&self.installation, new);
+                self.run_system_triggers(&mut executor, &fstree, changed.as_deref())?;
+
+                journal.complete(&self.installation)?;
+                self.finish_triggers(executor, Some(new))?;
+
+                Ok(journal::Recovery::RolledForward(new))
+            }
+            // Never promoted, undo the transaction
+            journal::Resume::RollBack => {
+                let staging_dir = self.installation.staging_dir();
+
+                match journal.operation {
+                    journal::Operation::NewState => {
+                        if let Some(new) = journal.new {
+                            self.state_db.remove(&new)?;
+                        }
+
+                        // Discard the partially written tree
+                        if staging_dir.exists() {
+                            fs::remove_dir_all(&staging_dir)?;
+                        }
+                    }
+                    journal::Operation::Activate => {
+                        // Return the staged tree to the archive
+                        if let Some(new) = journal.new {
+                            let archived = self.installation.root_path(new.to_string());
+
+                            if staging_usr.exists() && !archived.exists() {
+                                fs::rename(&staging_dir, archived)?;
+                            }
+                        }
+                    }
+                }
+
+                fs::create_dir_all(&staging_dir)?;
+
+                let old = journal.old;
+                journal.complete(&self.installation)?;
+
+                Ok(journal::Recovery::RolledBack(old))
+            }
+        }
+    }
+
+    /// Run the journaled steps of a transaction, completing the journal once they
+    /// succeed
+    ///
+    /// If a step fails the transaction is recovered right away, so the journal is
+    /// only left behind when moss is interrupted. Failing system triggers are fatal
+    /// to the transaction, but the new state remains active.
+    fn journaled<T>(
+        &self,
+        mut journal: Journal,
+        steps: impl FnOnce(&mut Journal) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        match steps(&mut journal) {
+            Ok(value) => {
+                journal.complete(&self.installation)?;
+                Ok(value)
+            }
+            Err(error) => {
+                let recovered = if journal.step == journal::Step::Triggers {
+                    journal.complete(&self.installation).map_err(Error::from)
+                } else {
+                    self.recover(journal).map(drop)
+                };
+
+                if let Err(recover_error) = recovered {
+                    println!(
+                        "{} failed to recover from the failed transaction, run `moss repair`: {recover_error}",
+                        "Warning:".yellow()
+                    );
+                }
+                Err(error)
+            }
+        }
+    }
+
     /// Returns `true` if this is an ephemeral client
     pub fn is_ephemeral(&self) -> bool {
         matches!(self.scope, Scope::Ephemeral { .. })
     }
 
     /// Perform an installation via [`install::install`]
-    pub fn install(&mut self, packages: &[&str], yes: bool) -> Result<install::Timing, install::Error> {
-        install(self, packages, yes)
+    pub fn install(
+        &mut self,
+        packages: &[&str],
+        options: install::Options,
+    ) -> Result<install::Outcome, install::Error> {
+        install(self, packages, options)
     }
 
     /// Transition to an ephemeral client that doesn't record state changes
@@ -155,14 +349,49 @@ impl Client {
     /// Ensures all repositories have been initialized by ensuring their stone indexes
     /// are downloaded and added to the meta db
     pub async fn ensure_repos_initialized(&mut self) -> Result<usize, Error> {
+        self.prepare_mutation()?;
+
         let num_initialized = self.repositories.ensure_all_initialized().await?;
-        self.registry = build_registry(&self.installation, &self.repositories, &self.install_db, &self.state_db)?;
+        self.registry = build_registry(
+            &self.installation,
+            &self.repositories,
+            &self.cobble,
+            &self.install_db,
+            &self.state_db,
+        )?;
         Ok(num_initialized)
     }
 
+    /// Load the local `.stone` files at `paths` into the registry, making them available
+    /// for resolution alongside the configured repositories.
+    ///
+    /// Returns the [`package::Id`] of each loaded file, in order.
+    pub fn add_local_packages<'a>(
+        &mut self,
+        paths: impl IntoIterator<Item = &'a Path>,
+    ) -> Result<Vec<package::Id>, Error> {
+        let ids = paths
+            .into_iter()
+            .map(|path| Ok(self.cobble.add_package(path)?.into()))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // Rebuild registry
+        self.registry = build_registry(
+            &self.installation,
+            &self.repositories,
+            &self.cobble,
+            &self.install_db,
+            &self.state_db,
+        )?;
+
+        Ok(ids)
+    }
+
     /// Reload all configured repositories and refreshes their index file, then update
     /// registry with all active repositories.
     pub async fn refresh_repositories(&mut self) -> Result<(), Error> {
+        self.prepare_mutation()?;
+
         // Reload manager if not explicit to pickup config changes
         // then refresh indexes
         if !self.repositories.is_explicit() {
@@ -171,7 +400,13 @@ impl Client {
         self.repositories.refresh_all().await?;
 
         // Rebuild registry
-        self.registry = build_registry(&self.installation, &self.repositories, &self.install_db, &self.state_db)?;
+        self.registry = build_registry(
+            &self.installation,
+            &self.repositories,
+            &self.cobble,
+            &self.install_db,
+            &self.state_db,
+        )?;
 
         Ok(())
     }
@@ -179,11 +414,13 @@ impl Client {
     /// Prune states with the provided [`prune::Strategy`]
     /// This allows automatic removal of unused states (and their associated assets)
     /// from the disk, acting as a garbage collection facility.
-    pub fn prune(&self, strategy: prune::Strategy, yes: bool) -> Result<(), Error> {
+    pub fn prune(&mut self, strategy: prune::Strategy, yes: bool) -> Result<(), Error> {
         if self.scope.is_ephemeral() {
             return Err(Error::EphemeralProhibitedOperation);
         }
 
+        self.prepare_mutation()?;
+
         prune(
             strategy,
             &self.state_db,
@@ -211,11 +448,99 @@ impl Client {
         Ok(metadata)
     }
 
+    /// All configured package holds
+    pub fn holds(&self) -> hold::Map {
+        self.config
+            .load::<hold::Map>()
+            .into_iter()
+            .reduce(hold::Map::merge)
+            .unwrap_or_default()
+    }
+
+    /// Hold the package `name`, replacing any existing hold on it
+    pub fn hold(&self, name: &package::Name, hold: Hold) -> Result<(), Error> {
+        self.config
+            .save(name, &hold::Map::with([(name.to_string(), hold)]))
+            .map_err(Error::SaveConfig)
+    }
+
+    /// Release the hold on the package `name`. Returns false if it isn't held in its
+    /// own config file, such as holds configured by the vendor.
+    pub fn unhold(&self, name: &package::Name) -> bool {
+        self.config.delete::<hold::Map>(name).is_ok()
+    }
+
+    /// Returns the only candidates `hold` allows for the package `name`, or `None`
+    /// if it doesn't restrict anything, i.e. a hold on a package that isn't installed
+    pub fn held_candidates(&self, name: &package::Name, hold: &Hold) -> Result<Option<Vec<package::Id>>, Error> {
+        let installed = self
+            .registry
+            .by_name(name, package::Flags::new().with_installed())
+            .next();
+
+        if !hold.is_pin() {
+            return Ok(installed.map(|p| vec![p.id]));
+        }
+
+        let constraint = hold
+            .constraint()
+            .map_err(|error| Error::InvalidPin(name.clone(), error))?;
+
+        let mut candidates = installed
+            .into_iter()
+            .chain(self.registry.by_name(name, package::Flags::new().with_available()))
+            .filter(|p| {
+                constraint
+                    .as_ref()
+                    .is_none_or(|c| c.allows(&p.meta.version_identifier, p.meta.source_release))
+            })
+            .filter(|p| {
+                hold.repository
+                    .as_ref()
+                    .is_none_or(|repo| self.registry.in_repository(&p.id, repo))
+            })
+            .map(|p| p.id)
+            .collect::<Vec<_>>();
+        candidates.dedup();
+
+        Ok(Some(candidates))
+    }
+
+    /// Returns the candidates allowed by every configured hold, by package name
+    ///
+    /// Ephemeral clients don't carry over the installed set, so holds don't apply
+    pub fn restrictions(&self) -> Result<BTreeMap<package::Name, Vec<package::Id>>, Error> {
+        if self.is_ephemeral() {
+            return Ok(BTreeMap::new());
+        }
+
+        let mut restrictions = BTreeMap::new();
+        for (name, hold) in self.holds() {
+            let name = package::Name::from(name);
+            if let Some(candidates) = self.held_candidates(&name, &hold)? {
+                restrictions.insert(name, candidates);
+            }
+        }
+
+        Ok(restrictions)
+    }
+
+    /// Return a new transaction which honours the configured package holds
+    pub fn transaction(&self) -> Result<Transaction<'_>, Error> {
+        let mut tx = self.registry.transaction()?;
+        for (name, candidates) in self.restrictions()? {
+            tx.restrict(name, candidates);
+        }
+        Ok(tx)
+    }
+
     /// Activates the provided state and runs system triggers
     /// once applied. The current state gets archived.
     ///
     /// Returns the old state that was archived
-    pub fn activate_state(&self, id: state::Id) -> Result<state::Id, Error> {
+    pub fn activate_state(&mut self, id: state::Id) -> Result<state::Id, Error> {
+        self.prepare_mutation()?;
+
         // Fetch the new state
         let new = self.state_db.get(id).map_err(|_| Error::StateDoesntExist(id))?;
 
@@ -235,25 +560,38 @@ impl Client {
             fs::create_dir(&staging_dir)?;
         }
 
-        // Move new (archived) state to staging
-        fs::rename(self.installation.root_path(new.id.to_string()), &staging_dir)?;
+        let journal = Journal::begin(
+            &self.installation,
+            journal::Operation::Activate,
+            Some(old),
+            Some(new.id),
+        )?;
 
-        // Promote staging
-        self.promote_staging()?;
+        let executor = self.journaled(journal, |journal| {
+            // Move new (archived) state to staging
+            fs::rename(self.installation.root_path(new.id.to_string()), &staging_dir)?;
 
-        // Archive old state
-        self.archive_state(old)?;
+            // Promote staging
+            journal.step(&self.installation, journal::Step::Promote)?;
+            self.promote_staging()?;
 
-        // Build VFS from new state selections
-        // to build triggers from
-        let fstree = self.vfs(new.selections.iter().map(|selection| &selection.package))?;
+            // Archive old state
+            journal.step(&self.installation, journal::Step::Archive)?;
+            self.archive_state(old)?;
 
-        // Run system triggers
-        let sys_triggers =
-            postblit::triggers(postblit::TriggerScope::System(&self.installation, &self.scope), &fstree)?;
-        for trigger in sys_triggers {
-            trigger.execute()?;
-        }
+            // Build VFS from new state selections
+            // to build triggers from
+            journal.step(&self.installation, journal::Step::Triggers)?;
+            let fstree = self.vfs(new.selections.iter().map(|selection| &selection.package))?;
+
+            // Run system triggers
+            let changed = self.changed_files(Some(old), &fstree)?;
+            let mut executor = postblit::Executor::new(&self.installation, new.id);
+            self.run_system_triggers(&mut executor, &fstree, changed.as_deref())?;
+
+            Ok(executor)
+        })?;
+        self.finish_triggers(executor, Some(new.id))?;
 
         Ok(old)
     }
@@ -264,54 +602,93 @@ impl Client {
     ///
     /// Returns `None` if the client is ephemeral
     pub fn new_state(&self, selections: &[Selection], summary: impl ToString) -> Result<Option<State>, Error> {
-        let old_state = self.installation.active_state;
+        self.ensure_exclusive()?;
+
+        // Never blit packages that conflict with each other
+        let packages = self.resolve_packages(selections.iter().map(|s| &s.package))?;
+        if let Some((package, other, provider)) = transaction::find_conflict(&packages) {
+            return Err(Error::Conflict {
+                package: package.meta.name.clone(),
+                other: other.meta.name.clone(),
+                provider: provider.to_string(),
+            });
+        }
 
-        let fstree = self.blit_root(selections.iter().map(|s| &s.package))?;
+        let old_state = self.installation.active_state;
 
         match &self.scope {
             Scope::Stateful => {
-                // Add to db
-                let state = self.state_db.add(selections, Some(&summary.to_string()), None)?;
-
-                // Write state id
-                {
-                    let usr = self.installation.staging_path("usr");
-                    fs::create_dir_all(&usr)?;
-                    let state_path = usr.join(".stateID");
-                    fs::write(state_path, state.id.to_string())?;
-                }
+                let journal = Journal::begin(&self.installation, journal::Operation::NewState, old_state, None)?;
 
-                record_os_release(&self.installation.staging_dir(), Some(state.id))?;
+                let (state, executor) = self.journaled(journal, |journal| {
+                    let fstree = self.blit_root(selections.iter().map(|s| &s.package))?;
 
-                // Run all of the transaction triggers
-                let triggers = postblit::triggers(
-                    postblit::TriggerScope::Transaction(&self.installation, &self.scope),
-                    &fstree,
-                )?;
-                create_root_links(&self.installation.isolation_dir())?;
-                for trigger in triggers {
-                    trigger.execute()?;
-                }
-                // Staging is only used with [`Scope::Stateful`]
-                self.promote_staging()?;
+                    // Add to db
+                    let state = self.state_db.add(selections, Some(&summary.to_string()), None)?;
+                    journal.set_new(&self.installation, state.id)?;
 
-                // Now we got it staged, we need working rootfs
-                create_root_links(&self.installation.root)?;
+                    // Write state id
+                    {
+                        let usr = self.installation.staging_path("usr");
+                        fs::create_dir_all(&usr)?;
+                        let state_path = usr.join(".stateID");
+                        fs::write(state_path, state.id.to_string())?;
+                    }
 
-                if let Some(id) = old_state {
-                    self.archive_state(id)?;
-                }
+                    record_os_release(&self.installation.staging_dir(), Some(state.id))?;
+
+                    // Carry over what the transaction triggers of the old state produced, so
+                    // only those affected by the transaction run again
+                    let changed = match old_state {
+                        Some(old) => {
+                            let state = self.state_db.get(old)?;
+                            let old_tree = self.vfs(state.selections.iter().map(|selection| &selection.package))?;
+                            let changed = diff::changed_files(&old_tree, &fstree);
+                            postblit::carry_outputs(
+                                &self.installation.root,
+                                &self.installation.staging_dir(),
+                                &old_tree,
+                                &changed,
+                            )?;
+                            Some(changed)
+                        }
+                        None => None,
+                    };
+
+                    // Run the transaction triggers
+                    let triggers = postblit::triggers(
+                        postblit::TriggerScope::Transaction(&self.installation, &self.scope),
+                        &fstree,
+                        changed.as_deref(),
+                    )?;
+                    create_root_links(&self.installation.isolation_dir())?;
+                    let mut executor = postblit::Executor::new(&self.installation, state.id);
+                    executor.run(triggers)?;
+                    // Staging is only used with [`Scope::Stateful`]
+                    journal.step(&self.installation, journal::Step::Promote)?;
+                    self.promote_staging()?;
+
+                    // Now we got it staged, we need working rootfs
+                    create_root_links(&self.installation.root)?;
+
+                    journal.step(&self.installation, journal::Step::Archive)?;
+                    if let Some(id) = old_state {
+                        self.archive_state(id)?;
+                    }
 
-                // At this point we're allowed to run system triggers
-                let sys_triggers =
-                    postblit::triggers(postblit::TriggerScope::System(&self.installation, &self.scope), &fstree)?;
-                for trigger in sys_triggers {
-                    trigger.execute()?;
-                }
+                    // At this point we're allowed to run system triggers
+                    journal.step(&self.installation, journal::Step::Triggers)?;
+                    self.run_system_triggers(&mut executor, &fstree, changed.as_deref())?;
+
+                    Ok((state, executor))
+                })?;
+                self.finish_triggers(executor, Some(state.id))?;
 
                 Ok(Some(state))
             }
             Scope::Ephemeral { blit_root } => {
+                let fstree = self.blit_root(selections.iter().map(|s| &s.package))?;
+
                 record_os_release(blit_root, None)?;
                 create_root_links(blit_root)?;
                 create_root_links(&self.installation.isolation_dir())?;
@@ -319,25 +696,78 @@ impl Client {
                 let etc = blit_root.join("etc");
                 create_dir_all(etc)?;
 
+                let mut executor = postblit::Executor::new(
+                    &self.installation,
+                    format_args!("ephemeral-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S")),
+                );
+
                 // ephemeral tx triggers
                 let triggers = postblit::triggers(
                     postblit::TriggerScope::Transaction(&self.installation, &self.scope),
                     &fstree,
+                    None,
                 )?;
-                for trigger in triggers {
-                    trigger.execute()?;
-                }
+                executor.run(triggers)?;
                 // ephemeral system triggers
-                let sys_triggers =
-                    postblit::triggers(postblit::TriggerScope::System(&self.installation, &self.scope), &fstree)?;
-                for trigger in sys_triggers {
-                    trigger.execute()?;
-                }
+                let sys_triggers = postblit::triggers(
+                    postblit::TriggerScope::System(&self.installation, &self.scope),
+                    &fstree,
+                    None,
+                )?;
+                executor.run(sys_triggers)?;
+                self.finish_triggers(executor, None)?;
+
                 Ok(None)
             }
         }
     }
 
+    /// Files changed going from state `old` to the `new` tree, or `None` without a
+    /// previous state, in which case every trigger should run
+    fn changed_files(
+        &self,
+        old: Option<state::Id>,
+        new: &vfs::Tree<PendingFile>,
+    ) -> Result<Option<Vec<PendingFile>>, Error> {
+        let Some(old) = old else {
+            return Ok(None);
+        };
+
+        let state = self.state_db.get(old)?;
+        let tree = self.vfs(state.selections.iter().map(|selection| &selection.package))?;
+
+        Ok(Some(diff::changed_files(&tree, new)))
+    }
+
+    /// Run the system triggers of the active `fstree`, limited to those affected by
+    /// the `changed` files if known
+    fn run_system_triggers(
+        &self,
+        executor: &mut postblit::Executor,
+        fstree: &vfs::Tree<PendingFile>,
+        changed: Option<&[PendingFile]>,
+    ) -> Result<(), Error> {
+        let sys_triggers = postblit::triggers(
+            postblit::TriggerScope::System(&self.installation, &self.scope),
+            fstree,
+            changed,
+        )?;
+        executor.run(sys_triggers)?;
+
+        Ok(())
+    }
+
+    /// Summarise failed triggers, recording them as warnings against `state`
+    fn finish_triggers(&self, executor: postblit::Executor, state: Option<state::Id>) -> Result<(), Error> {
+        let warnings = executor.finish();
+
+        if let Some(state) = state.filter(|_| !warnings.is_empty()) {
+            self.state_db.add_warnings(&state, &warnings)?;
+        }
+
+        Ok(())
+    }
+
     /// "Activate" the staging tree
     /// In practice, this means we perform an atomic swap of the `/usr` directory on the
     /// host filesystem with the `/usr` tree within the transaction tree.
@@ -402,6 +832,60 @@ impl Client {
         Ok(())
     }
 
+    /// Compare the packages of state `from` to state `to`, and their files if `files` is set
+    pub fn diff_states(&self, from: state::Id, to: state::Id, files: bool) -> Result<diff::Diff, diff::Error> {
+        diff::diff(self, from, to, files)
+    }
+
+    /// List the triggers installed in the active state
+    pub fn installed_triggers(&self) -> Vec<trigger::Installed> {
+        trigger::installed(self)
+    }
+
+    /// Compile the trigger handlers of the active state, limited to `names` unless empty
+    pub fn compile_triggers(&self, names: &[String]) -> Result<Vec<trigger::Compiled>, trigger::Error> {
+        trigger::compile(self, names)
+    }
+
+    /// Re-run the trigger handlers of the active state, limited to `names` unless empty
+    pub fn run_triggers(&self, names: &[String]) -> Result<usize, trigger::Error> {
+        trigger::run(self, names)
+    }
+
+    /// Export state `id` as a portable [`manifest::Manifest`]
+    pub fn export_state(&self, id: state::Id) -> Result<manifest::Manifest, manifest::Error> {
+        manifest::export(self, id)
+    }
+
+    /// Look up the packages of an imported [`manifest::Manifest`]
+    pub fn resolve_manifest(&self, manifest: &manifest::Manifest) -> Result<Vec<Package>, manifest::Error> {
+        manifest::resolve(self, manifest)
+    }
+
+    /// Find the installed packages owning files matching `pattern`
+    pub fn search_installed_files(&self, pattern: &search::Pattern) -> Result<Vec<search::Found>, search::Error> {
+        search::installed(self, pattern)
+    }
+
+    /// Find the available packages providing files matching `pattern`, along with
+    /// the number of repositories publishing a file list to search
+    pub fn search_available_files(
+        &self,
+        pattern: &search::Pattern,
+    ) -> Result<(Vec<search::Found>, usize), search::Error> {
+        search::available(self, pattern)
+    }
+
+    /// Verify the files of `packages` in the active state against the layout database
+    pub fn verify(&self, packages: &[package::Id]) -> Result<Vec<verify::Problem>, verify::Error> {
+        verify::verify(self, packages)
+    }
+
+    /// Restore the damaged files found by [`Client::verify`]
+    pub fn repair_files(&self, problems: &[verify::Problem]) -> Result<verify::Repair, verify::Error> {
+        verify::repair(self, problems)
+    }
+
     /// Download & unpack the provided packages. Packages already cached will be validated & skipped.
     pub async fn cache_packages(&self, packages: &[&Package]) -> Result<(), Error> {
         // Setup progress bar
@@ -440,12 +924,26 @@ impl Client {
             );
             progress_bar.enable_steady_tick(Duration::from_millis(150));
 
-            // Download and update progress
-            let download = cache::fetch(&package.meta, &self.installation, |progress| {
-                progress_bar.inc(progress.delta);
-            })
-            .await?;
+            // Download and update progress, local stones are used in place
+            let download = match self.cobble.path(&package.id) {
+                Some(path) => cache::local(&package.meta, path, &self.installation),
+                None => {
+                    cache::fetch(
+                        &package.meta,
+                        &self.installation,
+                        &self.layout_db,
+                        |url| self.repositories.mirror_urls(url),
+                        |progress| {
+                            // Deltas are smaller than the package
+                            progress_bar.set_length(progress.total);
+                            progress_bar.set_position(progress.completed);
+                        },
+                    )
+                    .await?
+                }
+            };
             let is_cached = download.was_cached;
+            let is_delta = download.was_delta;
 
             // Move rest of blocking code to threadpool
 
@@ -501,12 +999,14 @@ impl Client {
                 let cached_tag = is_cached
                     .then_some(format!("{}", " (cached)".dim()))
                     .unwrap_or_default();
+                let delta_tag = is_delta.then_some(format!("{}", " (delta)".dim())).unwrap_or_default();
 
                 // Write installed line
                 multi_progress.println(format!(
-                    "{} {}{}",
+                    "{} {}{}{}",
                     "Installed".green(),
                     package_name.clone().bold(),
+                    delta_tag,
                     cached_tag,
                 ))?;
 
@@ -519,7 +1019,7 @@ impl Client {
         }))
         // Use max network concurrency since we download files here
         .buffer_unordered(environment::MAX_NETWORK_CONCURRENCY)
-        .try_collect()
+        .try_collect::<()>()
         .await?;
 
         // Remove progress
@@ -672,7 +1172,7 @@ impl Client {
                     Some(parent),
                     subpath,
                     Mode::from_bits_truncate(item.layout.mode),
-                    nix::sys::stat::FchmodatFlags::NoFollowSymlink,
+                    FchmodatFlags::NoFollowSymlink,
                 )?;
             }
             layout::Entry::Symlink(source, _) => {
@@ -681,18 +1181,48 @@ impl Client {
             layout::Entry::Directory(_) => {
                 mkdirat(parent, subpath, Mode::from_bits_truncate(item.layout.mode))?;
             }
-
-            // unimplemented
-            layout::Entry::CharacterDevice(_) => todo!(),
-            layout::Entry::BlockDevice(_) => todo!(),
-            layout::Entry::Fifo(_) => todo!(),
-            layout::Entry::Socket(_) => todo!(),
+            layout::Entry::CharacterDevice(..)
+            | layout::Entry::BlockDevice(..)
+            | layout::Entry::Fifo(_)
+            | layout::Entry::Socket(_) => {
+                create_special_file(Some(parent), subpath, &item.layout)?;
+            }
         };
 
         Ok(())
     }
 }
 
+/// Create the device node, FIFO or socket described by `layout` at `path`, relative
+/// to `parent` if set. Unlike other inodes these are owned as recorded, and their
+/// mode is set exactly rather than being subject to the umask.
+fn create_special_file(parent: Option<RawFd>, path: &str, layout: &layout::Layout) -> Result<(), Errno> {
+    let mode = Mode::from_bits_truncate(layout.mode);
+    let node = |kind, device| mknodat(parent.unwrap_or(AT_FDCWD), path, kind, mode, device);
+
+    match layout.entry {
+        layout::Entry::CharacterDevice(device, _) => node(SFlag::S_IFCHR, device)?,
+        layout::Entry::BlockDevice(device, _) => node(SFlag::S_IFBLK, device)?,
+        layout::Entry::Socket(_) => node(SFlag::S_IFSOCK, 0)?,
+        layout::Entry::Fifo(_) => mkfifoat(parent, path, mode)?,
+        layout::Entry::Regular(..) | layout::Entry::Symlink(..) | layout::Entry::Directory(_) => {
+            return Err(Errno::EINVAL)
+        }
+    }
+
+    // Ownership first, as changing it clears any setuid / setgid bits
+    fchownat(
+        parent,
+        path,
+        Some(Uid::from_raw(layout.uid)),
+        Some(Gid::from_raw(layout.gid)),
+        FchownatFlags::NoFollowSymlink,
+    )?;
+    fchmodat(parent, path, mode, FchmodatFlags::NoFollowSymlink)?;
+
+    Ok(())
+}
+
 /// Add root symlinks & os-release file
 fn create_root_links(root: &Path) -> Result<(), io::Error> {
     let links = vec![
@@ -778,7 +1308,11 @@ impl BlitFile for PendingFile {
         match &self.layout.entry {
             layout::Entry::Symlink(source, _) => vfs::tree::Kind::Symlink(source.clone()),
             layout::Entry::Directory(_) => vfs::tree::Kind::Directory,
-            _ => vfs::tree::Kind::Regular,
+            layout::Entry::Regular(..) => vfs::tree::Kind::Regular,
+            layout::Entry::CharacterDevice(..) => vfs::tree::Kind::CharacterDevice,
+            layout::Entry::BlockDevice(..) => vfs::tree::Kind::BlockDevice,
+            layout::Entry::Fifo(_) => vfs::tree::Kind::Fifo,
+            layout::Entry::Socket(_) => vfs::tree::Kind::Socket,
         }
     }
 
@@ -793,8 +1327,8 @@ impl BlitFile for PendingFile {
             layout::Entry::Regular(_, target) => target.clone(),
             layout::Entry::Symlink(_, target) => target.clone(),
             layout::Entry::Directory(target) => target.clone(),
-            layout::Entry::CharacterDevice(target) => target.clone(),
-            layout::Entry::BlockDevice(target) => target.clone(),
+            layout::Entry::CharacterDevice(_, target) => target.clone(),
+            layout::Entry::BlockDevice(_, target) => target.clone(),
             layout::Entry::Fifo(target) => target.clone(),
             layout::Entry::Socket(target) => target.clone(),
         };
@@ -809,8 +1343,8 @@ impl BlitFile for PendingFile {
             layout::Entry::Regular(source, _) => layout::Entry::Regular(*source, path),
             layout::Entry::Symlink(source, _) => layout::Entry::Symlink(source.clone(), path),
             layout::Entry::Directory(_) => layout::Entry::Directory(path),
-            layout::Entry::CharacterDevice(_) => layout::Entry::CharacterDevice(path),
-            layout::Entry::BlockDevice(_) => layout::Entry::BlockDevice(path),
+            layout::Entry::CharacterDevice(device, _) => layout::Entry::CharacterDevice(*device, path),
+            layout::Entry::BlockDevice(device, _) => layout::Entry::BlockDevice(*device, path),
             layout::Entry::Fifo(_) => layout::Entry::Fifo(path),
             layout::Entry::Socket(_) => layout::Entry::Socket(path),
         };
@@ -833,9 +1367,9 @@ impl From<String> for PendingFile {
     }
 }
 
-impl ToString for PendingFile {
-    fn to_string(&self) -> String {
-        self.path()
+impl fmt::Display for PendingFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.path())
     }
 }
 
@@ -845,11 +1379,13 @@ impl ToString for PendingFile {
 ///
 /// * `installation` - Describe our installation target tree
 /// * `repositories` - Configured repositories to laoad [`crate::registry::Plugin::Repository`]
+/// * `cobble`       - Local `.stone` files to load as [`crate::registry::Plugin::Cobble`]
 /// * `installdb`    - Installation database opened in the installation tree
 /// * `statedb`      - State database opened in the installation tree
 fn build_registry(
     installation: &Installation,
     repositories: &repository::Manager,
+    cobble: &plugin::Cobble,
     installdb: &db::meta::Database,
     statedb: &db::state::Database,
 ) -> Result<Registry, Error> {
@@ -860,7 +1396,7 @@ fn build_registry(
 
     let mut registry = Registry::default();
 
-    registry.add_plugin(Plugin::Cobble(plugin::Cobble::default()));
+    registry.add_plugin(Plugin::Cobble(cobble.clone()));
     registry.add_plugin(Plugin::Active(plugin::Active::new(state, installdb.clone())));
 
     for repo in repositories.active() {
@@ -881,20 +1417,38 @@ pub enum Error {
     StateDoesntExist(state::Id),
     #[error("No metadata found for package {0:?}")]
     MissingMetadata(package::Id),
+    #[error("{package} conflicts with {other} ({provider})")]
+    Conflict {
+        package: package::Name,
+        other: package::Name,
+        provider: String,
+    },
+    #[error("invalid version pin for {0}")]
+    InvalidPin(package::Name, #[source] dependency::ParseError),
+    #[error("save config")]
+    SaveConfig(#[source] config::SaveError),
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
     #[error("Ephemeral client not allowed on installation root")]
     EphemeralInstallationRoot,
     #[error("Operation not allowed with ephemeral client")]
     EphemeralProhibitedOperation,
+    #[error("Operation requires an exclusive lock on the installation")]
+    SharedLock,
     #[error("installation")]
     Installation(#[from] installation::Error),
     #[error("cache")]
     Cache(#[from] cache::Error),
+    #[error("local package")]
+    Cobble(#[from] plugin::cobble::Error),
     #[error("repository manager")]
     Repository(#[from] repository::manager::Error),
     #[error("db")]
     Meta(#[from] db::Error),
     #[error("prune")]
     Prune(#[from] prune::Error),
+    #[error("journal")]
+    Journal(#[from] journal::Error),
     #[error("io")]
     Io(#[from] io::Error),
     #[error("filesystem")]
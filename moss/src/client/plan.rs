@@ -0,0 +1,348 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Plans describe the changes a transaction will make to the installation
+//!
+//! A plan is built once a transaction has been resolved, before anything is
+//! fetched or blitted, so it can be reviewed (or diffed by tooling via its
+//! JSON form) ahead of being applied.
+
+use std::fmt;
+
+use serde::Serialize;
+use tui::{HumanBytes, Styled};
+
+use crate::{package, Package};
+
+/// The complete set of changes a transaction will make
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Plan {
+    /// Packages that will be newly installed
+    pub added: Vec<Change>,
+    /// Packages that will be removed
+    pub removed: Vec<Change>,
+    /// Installed packages that will be replaced by another version
+    pub upgraded: Vec<Upgrade>,
+    /// Packages kept back from their preferred candidate by a hold
+    pub held: Vec<Held>,
+    /// Total size of all packages that need to be downloaded, in bytes
+    pub download_size: u64,
+}
+
+/// Version information of a planned package
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Version {
+    pub version: String,
+    pub release: u64,
+    pub build_release: u64,
+}
+
+/// A package being added or removed
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub name: String,
+    pub id: String,
+    #[serde(flatten)]
+    pub version: Version,
+    pub download_size: Option<u64>,
+    pub reason: Reason,
+}
+
+/// An installed package being replaced by another version of itself
+#[derive(Debug, Clone, Serialize)]
+pub struct Upgrade {
+    pub name: String,
+    pub from_id: String,
+    pub to_id: String,
+    pub from: Version,
+    pub to: Version,
+    pub download_size: Option<u64>,
+    pub reason: Reason,
+}
+
+/// A package kept back from its preferred candidate by a hold
+#[derive(Debug, Clone, Serialize)]
+pub struct Held {
+    pub name: String,
+    pub id: String,
+    #[serde(flatten)]
+    pub version: Version,
+    /// The candidate that would be selected without the hold
+    pub candidate: Version,
+}
+
+/// Why a package is part of the plan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Reason {
+    /// Explicitly requested by the user
+    Requested,
+    /// Pulled in to satisfy the dependencies of other packages
+    Dependency { required_by: Vec<String> },
+    /// Removed as it depends on other packages being removed
+    ReverseDependency { depends_on: Vec<String> },
+    /// No longer required by any package
+    Orphaned,
+    /// Replaced by a conflicting package
+    Conflict { conflicts_with: String },
+    /// Not part of an imported state
+    Unlisted,
+}
+
+impl Plan {
+    /// Add a newly installed package to the plan
+    pub fn add(&mut self, package: &Package, reason: Reason) {
+        self.download_size += package.meta.download_size.unwrap_or_default();
+        self.added.push(Change::new(package, reason));
+        self.added.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /// Add a removed package to the plan
+    pub fn remove(&mut self, package: &Package, reason: Reason) {
+        self.removed.push(Change::new(package, reason));
+        self.removed.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /// Add an installed package replaced by `to` to the plan
+    pub fn upgrade(&mut self, from: &Package, to: &Package, reason: Reason) {
+        self.download_size += to.meta.download_size.unwrap_or_default();
+        self.upgraded.push(Upgrade {
+            name: to.meta.name.to_string(),
+            from_id: from.id.to_string(),
+            to_id: to.id.to_string(),
+            from: Version::new(&from.meta),
+            to: Version::new(&to.meta),
+            download_size: to.meta.download_size,
+            reason,
+        });
+        self.upgraded.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /// Add a package kept at `package` instead of `candidate` by a hold to the plan
+    pub fn hold(&mut self, package: &Package, candidate: &Package) {
+        self.held.push(Held {
+            name: package.meta.name.to_string(),
+            id: package.id.to_string(),
+            version: Version::new(&package.meta),
+            candidate: Version::new(&candidate.meta),
+        });
+        self.held.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /// Returns true if the plan makes no changes
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.upgraded.is_empty()
+    }
+
+    /// Print the plan in human readable form
+    pub fn print(&self) {
+        if self.is_empty() {
+            println!("No changes");
+            self.print_held();
+            return;
+        }
+
+        if !self.added.is_empty() {
+            println!("{}", "Added:".green());
+            for change in &self.added {
+                println!(
+                    "  {} {}  ({})",
+                    change.name.clone().bold(),
+                    change.version,
+                    change.reason.to_string().dim()
+                );
+            }
+        }
+        if !self.upgraded.is_empty() {
+            println!("{}", "Upgraded:".blue());
+            for upgrade in &self.upgraded {
+                println!(
+                    "  {} {} → {}  ({})",
+                    upgrade.name.clone().bold(),
+                    upgrade.from,
+                    upgrade.to,
+                    upgrade.reason.to_string().dim()
+                );
+            }
+        }
+        if !self.removed.is_empty() {
+            println!("{}", "Removed:".red());
+            for change in &self.removed {
+                println!(
+                    "  {} {}  ({})",
+                    change.name.clone().bold(),
+                    change.version,
+                    change.reason.to_string().dim()
+                );
+            }
+        }
+
+        self.print_held();
+
+        if !self.added.is_empty() || !self.upgraded.is_empty() {
+            println!();
+            println!("Download size: {}", HumanBytes(self.download_size));
+        }
+    }
+
+    /// Print the held packages, if any
+    pub fn print_held(&self) {
+        if self.held.is_empty() {
+            return;
+        }
+
+        println!("{}", "Held back:".yellow());
+        for held in &self.held {
+            println!(
+                "  {} {}  ({})",
+                held.name.clone().bold(),
+                held.version,
+                format!("{} available", held.candidate).dim()
+            );
+        }
+    }
+}
+
+impl Change {
+    fn new(package: &Package, reason: Reason) -> Self {
+        Self {
+            name: package.meta.name.to_string(),
+            id: package.id.to_string(),
+            version: Version::new(&package.meta),
+            download_size: package.meta.download_size,
+            reason,
+        }
+    }
+}
+
+impl Version {
+    pub(super) fn new(meta: &package::Meta) -> Self {
+        Self {
+            version: meta.version_identifier.clone(),
+            release: meta.source_release,
+            build_release: meta.build_release,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.version, self.release)
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Requested => write!(f, "requested"),
+            Reason::Dependency { required_by } => write!(f, "required by {}", required_by.join(", ")),
+            Reason::ReverseDependency { depends_on } => write!(f, "depends on {}", depends_on.join(", ")),
+            Reason::Orphaned => write!(f, "orphaned"),
+            Reason::Conflict { conflicts_with } => write!(f, "conflicts with {conflicts_with}"),
+            Reason::Unlisted => write!(f, "not in manifest"),
+        }
+    }
+}
+
+/// Names of the `packages` that depend on something `package` provides
+pub fn required_by<'a>(package: &Package, packages: impl IntoIterator<Item = &'a Package>) -> Vec<String> {
+    let mut names = packages
+        .into_iter()
+        .filter(|p| p.id != package.id && depends_on_package(p, package))
+        .map(|p| p.meta.name.to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Names of the `packages` that provide something `package` depends on
+pub fn depends_on<'a>(package: &Package, packages: impl IntoIterator<Item = &'a Package>) -> Vec<String> {
+    let mut names = packages
+        .into_iter()
+        .filter(|p| p.id != package.id && depends_on_package(package, p))
+        .map(|p| p.meta.name.to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Returns true if any dependency of `package` is satisfied by `provider`
+fn depends_on_package(package: &Package, provider: &Package) -> bool {
+    package.meta.dependencies.iter().any(|dependency| {
+        provider
+            .meta
+            .providers
+            .iter()
+            .any(|p| p.kind == dependency.kind && p.name == dependency.name)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{dependency, Provider};
+
+    fn package(name: &str, dependencies: &[&str]) -> Package {
+        let mut package = Package::test(name, "1.0", 1, dependencies);
+        package.meta.providers.insert(Provider {
+            kind: dependency::Kind::SharedLibrary,
+            name: format!("lib{name}.so.1(x86_64)"),
+        });
+        package.meta.download_size = Some(1024);
+        package
+    }
+
+    #[test]
+    fn reasons() {
+        let app = package("app", &["libfoo", "soname(libbar.so.1(x86_64))"]);
+        let foo = package("libfoo", &[]);
+        let bar = package("bar", &[]);
+        let all = [&app, &foo, &bar];
+
+        assert_eq!(required_by(&foo, all), vec!["app"]);
+        assert_eq!(required_by(&bar, all), vec!["app"]);
+        assert!(required_by(&app, all).is_empty());
+        assert_eq!(depends_on(&app, all), vec!["bar", "libfoo"]);
+
+        let mut plan = Plan::default();
+        plan.add(&app, Reason::Requested);
+        plan.add(
+            &foo,
+            Reason::Dependency {
+                required_by: required_by(&foo, all),
+            },
+        );
+        plan.upgrade(&bar, &bar, Reason::Requested);
+
+        assert_eq!(plan.download_size, 3072);
+        assert_eq!(plan.added[0].name, "app");
+
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["added"][1]["reason"]["type"], "dependency");
+        assert_eq!(json["added"][1]["reason"]["required_by"][0], "app");
+        assert_eq!(json["added"][1]["version"], "1.0");
+        assert_eq!(json["upgraded"][0]["to"]["release"], 1);
+    }
+
+    #[test]
+    fn version_field_names() {
+        let app = package("app", &[]);
+
+        // Packages & plans are consumed by the same tooling, so they must agree
+        let meta = serde_json::to_value(&app.meta).unwrap();
+        let version = serde_json::to_value(Version::new(&app.meta)).unwrap();
+
+        for field in ["version", "release", "build_release"] {
+            assert_eq!(meta[field], version[field], "{field}");
+        }
+        assert_eq!(
+            version,
+            serde_json::json!({ "version": "1.0", "release": 1, "build_release": 1 })
+        );
+        assert!(meta.get("version_identifier").is_none());
+        assert!(meta.get("source_release").is_none());
+    }
+}
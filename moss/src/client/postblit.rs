@@ -9,7 +9,11 @@
 //! Note that currently we only load from `/usr/share/moss/triggers/{tx,sys.d}/*.yaml`
 //! and do not yet support local triggers
 use std::{
-    path::{Path, PathBuf},
+    collections::BTreeSet,
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
     process,
 };
 
@@ -18,7 +22,9 @@ use container::Container;
 use itertools::Itertools;
 use serde::Deserialize;
 use thiserror::Error;
-use triggers::format::{CompiledHandler, Handler, Trigger};
+use triggers::format::{CompiledHandler, Handler, PathKind, Trigger};
+use tui::Styled;
+use vfs::tree::{BlitFile, Kind};
 
 use super::PendingFile;
 
@@ -69,6 +75,19 @@ impl<'a> TriggerScope<'a> {
         }
     }
 
+    /// Detect the environment of the system being modified, to evaluate inhibitors against
+    pub(super) fn environment(&self) -> triggers::Environment {
+        triggers::Environment::detect(self.host_path(""))
+    }
+
+    /// Root of the paths triggers of this scope may modify, as seen when they execute
+    fn scope_root(&self) -> &'static Path {
+        match self {
+            TriggerScope::Transaction(..) => Path::new("/usr"),
+            TriggerScope::System(..) => Path::new("/"),
+        }
+    }
+
     /// Join "host" paths, outside the staging filesystem. Ensure no sandbox break for ephemeral
     fn host_path(&self, path: impl AsRef<Path>) -> PathBuf {
         match self {
@@ -105,20 +124,12 @@ pub(super) struct TriggerRunner<'a> {
     trigger: CompiledHandler,
 }
 
-/// Load all triggers matching the given scope and staging filesystem
-///
-/// # Arguments
-///
-/// * `scope`  - Trigger execution scope
-/// * `fstree` - Virtual filesystem tree populated with records of the staging filesystem
-pub(super) fn triggers<'a>(
-    scope: TriggerScope<'a>,
-    fstree: &vfs::tree::Tree<PendingFile>,
-) -> Result<Vec<TriggerRunner<'a>>, Error> {
+/// Load the triggers installed in the root of the given scope
+pub(super) fn load(scope: TriggerScope<'_>) -> Vec<Trigger> {
     let trigger_root = Path::new("usr").join("share").join("moss").join("triggers");
 
     // Load appropriate triggers from their locations and convert back to a vec of Trigger
-    let triggers = match scope {
+    match scope {
         TriggerScope::Transaction(..) => config::Manager::custom(scope.root_dir().join(trigger_root))
             .load::<TransactionTrigger>()
             .into_iter()
@@ -129,11 +140,40 @@ pub(super) fn triggers<'a>(
             .into_iter()
             .map(|t| t.0)
             .collect_vec(),
-    };
+    }
+}
+
+/// Load all triggers matching the given scope and staging filesystem
+///
+/// # Arguments
+///
+/// * `scope`   - Trigger execution scope
+/// * `fstree`  - Virtual filesystem tree populated with records of the staging filesystem
+/// * `changed` - Files changed since the previous state, if any, to only run triggers affected
+///   by the transaction
+pub(super) fn triggers<'a>(
+    scope: TriggerScope<'a>,
+    fstree: &vfs::tree::Tree<PendingFile>,
+    changed: Option<&[PendingFile]>,
+) -> Result<Vec<TriggerRunner<'a>>, Error> {
+    let triggers = load(scope);
+
+    let environment = scope.environment();
 
     // Load trigger collection, process all the paths, convert to scoped TriggerRunner vec
-    let mut collection = triggers::Collection::new(triggers.iter())?;
-    collection.process_paths(fstree.iter().map(|m| m.to_string()));
+    let mut collection = triggers::Collection::new(triggers.iter(), &environment)?;
+    let entry = |m: &PendingFile| {
+        let kind = match m.kind() {
+            Kind::Directory => Some(PathKind::Directory),
+            Kind::Symlink(_) => Some(PathKind::Symlink),
+            Kind::Regular | Kind::CharacterDevice | Kind::BlockDevice | Kind::Fifo | Kind::Socket => None,
+        };
+        (m.to_string(), kind)
+    };
+    match changed {
+        Some(changed) => collection.process_changes(fstree.iter().map(|m| entry(&m)), changed.iter().map(entry)),
+        None => collection.process_paths(fstree.iter().map(|m| entry(&m))),
+    }
     let computed_commands = collection
         .bake()?
         .into_iter()
@@ -142,7 +182,101 @@ pub(super) fn triggers<'a>(
     Ok(computed_commands)
 }
 
+/// Carry the outputs of transaction triggers from the `live` root into the freshly blitted
+/// `staging` root, so only triggers affected by the `changed` files need to run again
+///
+/// Anything under the live `/usr` not packaged by the `old` tree was written by a trigger, and
+/// is copied unless staging already has the path or lost its parent directory. Packaged files
+/// deleted by a trigger are deleted from staging too, unless the transaction changed them.
+pub(super) fn carry_outputs(
+    live: &Path,
+    staging: &Path,
+    old: &vfs::tree::Tree<PendingFile>,
+    changed: &[PendingFile],
+) -> Result<(), Error> {
+    let mut packaged = BTreeSet::new();
+    for file in old.iter() {
+        for ancestor in Path::new(&file.path()).ancestors() {
+            if !packaged.insert(ancestor.to_path_buf()) {
+                break;
+            }
+        }
+    }
+
+    if live.join("usr").is_dir() {
+        carry_dir(live, staging, Path::new("usr"), &packaged)?;
+    }
+
+    let changed = changed.iter().map(|file| file.path()).collect::<BTreeSet<_>>();
+    for file in old.iter() {
+        let path = file.path();
+        if matches!(file.kind(), Kind::Directory) || changed.contains(&path) {
+            continue;
+        }
+
+        let relative = path.trim_start_matches('/');
+        if exists(&live.join(relative))? {
+            continue;
+        }
+        match fs::remove_file(staging.join(relative)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy the unpackaged entries of `dir` from `live` into `staging`
+fn carry_dir(live: &Path, staging: &Path, dir: &Path, packaged: &BTreeSet<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(live.join(dir))? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = dir.join(entry.file_name());
+        let target = staging.join(&path);
+
+        // Outputs may be nested within packaged directories
+        if exists(&target)? {
+            if file_type.is_dir() && target.is_dir() {
+                carry_dir(live, staging, &path, packaged)?;
+            }
+            continue;
+        }
+
+        // Packaged by the old state, but not the new one
+        if packaged.contains(&Path::new("/").join(&path)) {
+            continue;
+        }
+
+        if file_type.is_dir() {
+            fs::create_dir(&target)?;
+            fs::set_permissions(&target, entry.metadata()?.permissions())?;
+            carry_dir(live, staging, &path, packaged)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `path` exists, without following symlinks
+fn exists(path: &Path) -> io::Result<bool> {
+    match fs::symlink_metadata(path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
 impl<'a> TriggerRunner<'a> {
+    /// The compiled handler to execute
+    pub fn handler(&self) -> &CompiledHandler {
+        &self.trigger
+    }
+
     /// Execute a trigger, taking care to account for the transaction scope and client scope
     ///
     /// All transaction triggers are run via sandboxing ([`container::Container`]) to limit their
@@ -150,7 +284,11 @@ impl<'a> TriggerRunner<'a> {
     /// System triggers will execute without any sandboxing when moss is used directly against the
     /// live root filesystem, and will force sandboxing when using a non-`/` root (such as using the
     /// `-D argument with `moss install`)
-    pub fn execute(&self) -> Result<(), Error> {
+    ///
+    /// Output of the trigger is appended to `log`
+    pub fn execute(&self, log: &File) -> Result<(), Error> {
+        let scope_root = self.scope.scope_root();
+
         match self.scope {
             TriggerScope::Transaction(install, _) => {
                 // TODO: Add caching support via /var/
@@ -161,12 +299,12 @@ impl<'a> TriggerRunner<'a> {
                     .bind_rw(self.scope.guest_path("usr"), "/usr")
                     .work_dir("/");
 
-                Ok(isolation.run(|| execute_trigger_directly(&self.trigger))?)
+                Ok(isolation.run(|| execute_trigger_directly(&self.trigger, scope_root, log))?)
             }
             TriggerScope::System(install, _) => {
                 // OK, if the root == `/` then we can run directly, otherwise we need to containerise with RW.
                 if install.root.to_string_lossy() == "/" {
-                    Ok(execute_trigger_directly(&self.trigger)?)
+                    Ok(execute_trigger_directly(&self.trigger, scope_root, log)?)
                 } else {
                     let isolation = Container::new(install.isolation_dir())
                         .networking(false)
@@ -175,7 +313,7 @@ impl<'a> TriggerRunner<'a> {
                         .bind_rw(self.scope.guest_path("usr"), "/usr")
                         .work_dir("/");
 
-                    Ok(isolation.run(|| execute_trigger_directly(&self.trigger))?)
+                    Ok(isolation.run(|| execute_trigger_directly(&self.trigger, scope_root, log))?)
                 }
             }
         }
@@ -183,27 +321,168 @@ impl<'a> TriggerRunner<'a> {
 }
 
 /// Internal executor for triggers.
-fn execute_trigger_directly(trigger: &CompiledHandler) -> Result<(), Error> {
+///
+/// `Delete` handlers may only remove paths within `scope_root`, as seen from within the container
+fn execute_trigger_directly(trigger: &CompiledHandler, scope_root: &Path, mut log: &File) -> Result<(), Error> {
+    writeln!(log, "==> {}: {}", trigger.trigger(), trigger.handler())?;
+
     match trigger.handler() {
         Handler::Run { run, args } => {
-            let cmd = process::Command::new(run).args(args).current_dir("/").output()?;
+            let status = process::Command::new(run)
+                .args(args)
+                .current_dir("/")
+                .stdout(log.try_clone()?)
+                .stderr(log.try_clone()?)
+                .status()
+                .map_err(|error| Error::Spawn(run.clone(), error))?;
 
-            if let Some(code) = cmd.status.code() {
-                if code != 0 {
-                    eprintln!("Trigger exited with non-zero status code: {run} {args:?}");
-                    eprintln!("   Stdout: {}", String::from_utf8(cmd.stdout).unwrap());
-                    eprintln!("   Stderr: {}", String::from_utf8(cmd.stderr).unwrap());
+            if !status.success() {
+                return Err(Error::Exit(status));
+            }
+        }
+        Handler::Delete { delete } => {
+            for path in delete {
+                let path = deletable_path(path, scope_root)?;
+
+                match fs::symlink_metadata(&path) {
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&path)?,
+                    Ok(_) => fs::remove_file(&path)?,
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                    Err(error) => return Err(error.into()),
                 }
-            } else {
-                eprintln!("Failed to execute trigger: {run} {args:?}");
+
+                writeln!(log, "deleted {}", path.display())?;
             }
         }
-        Handler::Delete { .. } => todo!(),
     }
 
     Ok(())
 }
 
+/// Ensure `path` can't escape `scope_root`, either lexically or through symlinks
+fn deletable_path(path: &str, scope_root: &Path) -> Result<PathBuf, Error> {
+    let outside = || Error::DeleteOutsideScope(path.to_string(), scope_root.to_path_buf());
+    let path = Path::new(path);
+
+    if !path.is_absolute()
+        || path.components().any(|component| component == Component::ParentDir)
+        || !path.starts_with(scope_root)
+        || path == scope_root
+    {
+        return Err(outside());
+    }
+
+    // Parents which don't exist can't lead anywhere
+    if let Some(parent) = path.parent().and_then(|parent| parent.canonicalize().ok()) {
+        if !parent.starts_with(scope_root) {
+            return Err(outside());
+        }
+    }
+
+    Ok(path.to_path_buf())
+}
+
+/// A trigger which failed to execute
+#[derive(Debug)]
+struct Failure {
+    trigger: String,
+    error: String,
+}
+
+/// Executes the triggers of a single transaction
+///
+/// Output of every handler is appended to a log under `.moss/log/triggers`. Failures abort
+/// the transaction if [`Installation::fail_on_trigger_error`] is set, otherwise they're
+/// collected to be summarised once the transaction completes.
+pub(super) struct Executor {
+    log: Option<File>,
+    log_path: PathBuf,
+    fatal: bool,
+    failures: Vec<Failure>,
+}
+
+impl Executor {
+    /// Create an executor logging to the trigger log `name` of the installation
+    pub fn new(installation: &Installation, name: impl fmt::Display) -> Self {
+        Self {
+            log: None,
+            log_path: installation.log_path("triggers").join(format!("{name}.log")),
+            fatal: installation.fail_on_trigger_error,
+            failures: vec![],
+        }
+    }
+
+    /// Execute all `triggers` in order
+    pub fn run<'a>(&mut self, triggers: impl IntoIterator<Item = TriggerRunner<'a>>) -> Result<(), Error> {
+        for runner in triggers {
+            let log = match &mut self.log {
+                Some(log) => log,
+                // Only log transactions which actually run triggers
+                None => {
+                    if let Some(dir) = self.log_path.parent() {
+                        fs::create_dir_all(dir)?;
+                    }
+                    let log = File::options().create(true).append(true).open(&self.log_path)?;
+                    self.log.insert(log)
+                }
+            };
+
+            let Err(error) = runner.execute(log) else {
+                continue;
+            };
+            let trigger = runner.trigger.trigger().to_string();
+
+            if self.fatal {
+                return Err(Error::Failed(trigger, Box::new(error)));
+            }
+
+            let error = match error {
+                // Already flattened by the container
+                Error::Container(container::Error::Failure(message)) => message,
+                error => error_chain(&error),
+            };
+            writeln!(log, "failed: {error}")?;
+            self.failures.push(Failure { trigger, error });
+        }
+
+        Ok(())
+    }
+
+    /// Print a summary of failed triggers, returning them as warnings to record against the state
+    pub fn finish(self) -> Vec<String> {
+        if self.failures.is_empty() {
+            return vec![];
+        }
+
+        println!();
+        println!(
+            "{} {} trigger handler(s) failed, see {}",
+            "Warning:".yellow(),
+            self.failures.len(),
+            self.log_path.display()
+        );
+        for failure in &self.failures {
+            println!("  {} {}", failure.trigger.clone().bold(), failure.error.clone().dim());
+        }
+
+        self.failures
+            .into_iter()
+            .map(|failure| format!("trigger {} failed: {}", failure.trigger, failure.error))
+            .collect()
+    }
+}
+
+/// Flatten `error` and its sources into a single line
+pub(super) fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut chain = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        chain.push_str(&format!(": {error}"));
+        source = error.source();
+    }
+    chain
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("container")]
@@ -214,4 +493,104 @@ pub enum Error {
 
     #[error("io")]
     IO(#[from] std::io::Error),
+
+    #[error("failed to run {0}")]
+    Spawn(String, #[source] io::Error),
+
+    #[error("{0}")]
+    Exit(process::ExitStatus),
+
+    #[error("refusing to delete {0}, outside of {1:?}")]
+    DeleteOutsideScope(String, PathBuf),
+
+    #[error("trigger {0} failed")]
+    Failed(String, #[source] Box<Error>),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deletable_paths() {
+        let usr = Path::new("/usr");
+
+        assert!(deletable_path("/usr/lib/some/cache", usr).is_ok());
+        assert!(deletable_path("/usr/lib/does-not-exist/cache", usr).is_ok());
+        assert!(deletable_path("/etc/passwd", usr).is_err());
+        assert!(deletable_path("/usr/../etc/passwd", usr).is_err());
+        assert!(deletable_path("usr/lib/cache", usr).is_err());
+        assert!(deletable_path("/usr", usr).is_err());
+        assert!(deletable_path("/usrlocal/cache", usr).is_err());
+        assert!(deletable_path("/", Path::new("/")).is_err());
+    }
+
+    fn tree(files: &[(&str, u128)]) -> vfs::tree::Tree<PendingFile> {
+        let mut builder = vfs::tree::builder::TreeBuilder::new();
+        for (path, hash) in files {
+            builder.push(PendingFile {
+                id: crate::package::Id::from("test".to_string()),
+                layout: stone::payload::layout::Layout {
+                    uid: 0,
+                    gid: 0,
+                    mode: 0o644,
+                    tag: 0,
+                    entry: stone::payload::layout::Entry::Regular(*hash, path.to_string()),
+                },
+            });
+        }
+        builder.bake();
+        builder.tree().unwrap()
+    }
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn carried_outputs() {
+        let live = tempfile::tempdir().unwrap();
+        let staging = tempfile::tempdir().unwrap();
+        let (live, staging) = (live.path(), staging.path());
+
+        let old = tree(&[
+            ("bin/a", 1),
+            ("lib/deleted", 2),
+            ("lib/modified", 3),
+            ("share/removed/file", 4),
+        ]);
+        let new = tree(&[("bin/a", 1), ("lib/deleted", 2), ("lib/modified", 5)]);
+        let changed = super::super::diff::changed_files(&old, &new);
+
+        // Live root as left by the old state and its triggers, which deleted the
+        // packaged `deleted` and `modified` files
+        for path in ["usr/bin/a", "usr/share/removed/file", "usr/share/removed/cache"] {
+            write(live, path, "old");
+        }
+        write(live, "usr/.stateID", "1");
+        write(live, "usr/lib/cache", "output");
+        write(live, "usr/lib/generated/output", "output");
+        std::os::unix::fs::symlink("cache", live.join("usr/lib/link")).unwrap();
+
+        // Freshly blitted staging root of the new state
+        for file in new.iter().filter(|file| matches!(file.kind(), Kind::Regular)) {
+            write(staging, file.path().trim_start_matches('/'), "new");
+        }
+        write(staging, "usr/.stateID", "2");
+
+        carry_outputs(live, staging, &old, &changed).unwrap();
+
+        let read = |path: &str| fs::read_to_string(staging.join(path)).ok();
+        assert_eq!(read("usr/.stateID").as_deref(), Some("2"));
+        assert_eq!(read("usr/bin/a").as_deref(), Some("new"));
+        assert_eq!(read("usr/lib/cache").as_deref(), Some("output"));
+        assert_eq!(read("usr/lib/generated/output").as_deref(), Some("output"));
+        assert_eq!(fs::read_link(staging.join("usr/lib/link")).unwrap(), Path::new("cache"));
+        // Changed by the transaction, so the deleting trigger runs again
+        assert_eq!(read("usr/lib/modified").as_deref(), Some("new"));
+        assert!(read("usr/lib/deleted").is_none());
+        assert!(!staging.join("usr/share/removed").exists());
+    }
 }
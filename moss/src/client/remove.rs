@@ -0,0 +1,191 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Planning the removal of installed packages
+
+use std::collections::HashSet;
+
+use crate::{
+    client::plan::{self, Plan, Reason},
+    package,
+    registry::transaction,
+    Package, Registry,
+};
+
+/// Installed packages split by whether they survive a removal
+#[derive(Debug, Clone)]
+pub struct Removal {
+    /// Packages that stay installed
+    pub remaining: HashSet<package::Id>,
+    /// Packages being removed, sorted by name
+    pub removed: Vec<Package>,
+    pub plan: Plan,
+}
+
+/// Plan removing the `requested` packages from the `installed` ones, along with
+/// every package depending on them. With `orphans`, transitive packages that no
+/// remaining explicit package depends on are removed too.
+pub fn plan(
+    registry: &Registry,
+    installed: &[Package],
+    requested: &[package::Id],
+    orphans: bool,
+) -> Result<Removal, transaction::Error> {
+    // Add all installed packages to transaction
+    let mut transaction = registry.transaction_with_installed(installed.iter().map(|p| p.id.clone()).collect())?;
+
+    // Remove all requested packages
+    transaction.remove(requested.to_vec());
+
+    // Finalized tx has all reverse deps removed
+    let without_removed = transaction.finalize().cloned().collect::<HashSet<_>>();
+
+    // Only keep what the remaining explicit packages depend on
+    let remaining = if orphans {
+        let explicit = installed
+            .iter()
+            .filter(|p| p.flags.explicit && without_removed.contains(&p.id))
+            .map(|p| p.id.clone())
+            .collect();
+
+        registry
+            .transaction_with_installed(explicit)?
+            .finalize()
+            .cloned()
+            .collect::<HashSet<_>>()
+    } else {
+        without_removed.clone()
+    };
+
+    let mut removed = installed
+        .iter()
+        .filter(|p| !remaining.contains(&p.id))
+        .cloned()
+        .collect::<Vec<_>>();
+    removed.sort_by_key(|p| p.meta.name.to_string());
+
+    let mut plan = Plan::default();
+    for package in &removed {
+        let reason = if requested.contains(&package.id) {
+            Reason::Requested
+        } else if without_removed.contains(&package.id) {
+            Reason::Orphaned
+        } else {
+            Reason::ReverseDependency {
+                depends_on: plan::depends_on(package, &removed),
+            }
+        };
+        plan.remove(package, reason);
+    }
+
+    Ok(Removal {
+        remaining,
+        removed,
+        plan,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::{plugin, Plugin};
+
+    fn installed(mut package: Package, explicit: bool) -> Package {
+        package.flags = if explicit {
+            package::Flags::new().with_installed().with_explicit()
+        } else {
+            package::Flags::new().with_installed()
+        };
+        package
+    }
+
+    /// Installed packages: tool -> app -> lib, with a leftover transitive stale package
+    fn fixture() -> (Registry, Vec<Package>) {
+        let packages = vec![
+            installed(Package::test("tool", "1.0", 1, &["app"]), true),
+            installed(Package::test("app", "1.0", 1, &["lib"]), true),
+            installed(Package::test("lib", "1.0", 1, &[]), false),
+            installed(Package::test("stale", "1.0", 1, &[]), false),
+        ];
+
+        let mut registry = Registry::default();
+        registry.add_plugin(Plugin::Test(plugin::Test::new(0, packages.clone())));
+
+        (registry, packages)
+    }
+
+    fn id(id: &str) -> package::Id {
+        package::Id::from(id.to_string())
+    }
+
+    fn reasons(removal: &Removal) -> Vec<(&str, Reason)> {
+        removal
+            .plan
+            .removed
+            .iter()
+            .map(|change| (change.name.as_str(), change.reason.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn reverse_dependencies() {
+        let (registry, installed) = fixture();
+
+        let removal = plan(&registry, &installed, &[id("lib-1.0")], false).unwrap();
+        assert_eq!(removal.remaining, HashSet::from([id("stale-1.0")]));
+        assert_eq!(
+            reasons(&removal),
+            vec![
+                (
+                    "app",
+                    Reason::ReverseDependency {
+                        depends_on: vec!["lib".into()]
+                    }
+                ),
+                ("lib", Reason::Requested),
+                (
+                    "tool",
+                    Reason::ReverseDependency {
+                        depends_on: vec!["app".into()]
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn orphans() {
+        let (registry, installed) = fixture();
+
+        // Only transitive packages nothing explicit depends on go
+        let removal = plan(&registry, &installed, &[], true).unwrap();
+        assert_eq!(
+            removal.remaining,
+            HashSet::from([id("tool-1.0"), id("app-1.0"), id("lib-1.0")])
+        );
+        assert_eq!(reasons(&removal), vec![("stale", Reason::Orphaned)]);
+
+        // Dependencies orphaned by the removal go with it
+        let removal = plan(&registry, &installed, &[id("app-1.0")], true).unwrap();
+        assert!(removal.remaining.is_empty());
+        assert_eq!(
+            reasons(&removal),
+            vec![
+                ("app", Reason::Requested),
+                ("lib", Reason::Orphaned),
+                ("stale", Reason::Orphaned),
+                (
+                    "tool",
+                    Reason::ReverseDependency {
+                        depends_on: vec!["app".into()]
+                    }
+                ),
+            ]
+        );
+
+        // Nothing is orphaned without asking
+        let removal = plan(&registry, &installed, &[], false).unwrap();
+        assert!(removal.removed.is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Search packages by the paths of their files
+//!
+//! Installed packages are searched through the layout database. Available
+//! packages can only be searched in repositories publishing a `stone.files`
+//! list alongside their index, as written by `moss index --files`.
+
+use std::{fs::File, io, str::FromStr};
+
+use serde::Serialize;
+use stone::{payload::layout, read::PayloadKind};
+use thiserror::Error;
+use vfs::tree::BlitFile;
+
+use super::{Client, PendingFile};
+use crate::{db, package, repository};
+
+/// A path pattern, i.e. `bash`, `bin/*sh` or `/usr/lib/libz.so*`
+///
+/// Absolute patterns match whole paths, while relative ones match the
+/// trailing components of a path
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pattern: fnmatch::Pattern,
+    absolute: bool,
+}
+
+impl Pattern {
+    /// Returns true if `path` matches this pattern
+    pub fn matches(&self, path: &str) -> bool {
+        if self.absolute {
+            self.pattern.match_path(path).is_some()
+        } else {
+            path.match_indices('/')
+                .any(|(index, _)| self.pattern.match_path(&path[index + 1..]).is_some())
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = fnmatch::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            pattern: s.parse()?,
+            absolute: s.starts_with('/'),
+        })
+    }
+}
+
+/// A file matching a [`Pattern`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Found {
+    /// Absolute path within the installation, i.e. `/usr/bin/bash`
+    pub path: String,
+    /// Name of the package providing the file
+    pub name: String,
+    pub id: String,
+    /// Repository the package is available from, unless installed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<repository::Id>,
+}
+
+/// Find the installed packages owning files matching `pattern`
+pub fn installed(client: &Client, pattern: &Pattern) -> Result<Vec<Found>, Error> {
+    let packages = client
+        .registry
+        .list_installed(package::Flags::default())
+        .map(|package| (package.id, package.meta.name))
+        .collect::<Vec<_>>();
+
+    let mut found = client
+        .layout_db
+        .query(packages.iter().map(|(id, _)| id))?
+        .into_iter()
+        .filter_map(|(id, layout)| {
+            let path = matching_path(pattern, &id, layout)?;
+            let (_, name) = packages.iter().find(|(package, _)| *package == id)?;
+
+            Some(Found {
+                path,
+                name: name.to_string(),
+                id: id.to_string(),
+                repository: None,
+            })
+        })
+        .collect::<Vec<_>>();
+    found.sort_by(|a, b| a.path.cmp(&b.path).then_with(|| a.name.cmp(&b.name)));
+
+    Ok(found)
+}
+
+/// Find the available packages providing files matching `pattern`, returning them
+/// along with the number of repositories that could be searched
+pub fn available(client: &Client, pattern: &Pattern) -> Result<(Vec<Found>, usize), Error> {
+    let mut found = vec![];
+    let mut searched = 0;
+
+    for (repository, path) in client.repositories.file_lists() {
+        let mut file = File::open(&path).map_err(Error::OpenFileList)?;
+        let mut reader = stone::read(&mut file)?;
+
+        // Each package's meta is directly followed by its layouts
+        let mut package = None;
+
+        for payload in reader.payloads()? {
+            match payload? {
+                PayloadKind::Meta(meta) => {
+                    let meta = package::Meta::from_stone_payload(&meta.body)?;
+                    package = meta.hash.clone().map(|hash| (package::Id::from(hash), meta.name));
+                }
+                PayloadKind::Layout(layouts) => {
+                    let Some((id, name)) = &package else {
+                        continue;
+                    };
+
+                    found.extend(layouts.body.into_iter().filter_map(|layout| {
+                        Some(Found {
+                            path: matching_path(pattern, id, layout)?,
+                            name: name.to_string(),
+                            id: id.to_string(),
+                            repository: Some(repository.clone()),
+                        })
+                    }));
+                }
+                _ => {}
+            }
+        }
+
+        searched += 1;
+    }
+    found.sort_by(|a, b| a.path.cmp(&b.path).then_with(|| a.name.cmp(&b.name)));
+
+    Ok((found, searched))
+}
+
+/// The path of `layout` if it matches `pattern`, ignoring directories as they're shared by many packages
+fn matching_path(pattern: &Pattern, id: &package::Id, layout: layout::Layout) -> Option<String> {
+    if matches!(layout.entry, layout::Entry::Directory(_)) {
+        return None;
+    }
+
+    let path = PendingFile { id: id.clone(), layout }.path();
+    pattern.matches(&path).then_some(path)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("open file list")]
+    OpenFileList(#[source] io::Error),
+
+    #[error("read file list")]
+    ReadStone(#[from] stone::read::Error),
+
+    #[error(transparent)]
+    MissingMetaField(#[from] package::MissingMetaFieldError),
+
+    #[error("db")]
+    DB(#[from] db::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let name = "bash".parse::<Pattern>().unwrap();
+        assert!(name.matches("/usr/bin/bash"));
+        assert!(!name.matches("/usr/bin/rbash"));
+
+        let relative = "bin/*sh".parse::<Pattern>().unwrap();
+        assert!(relative.matches("/usr/bin/bash"));
+        assert!(!relative.matches("/usr/sbin/nologin"));
+
+        let absolute = "/usr/lib/libstdc++.so*".parse::<Pattern>().unwrap();
+        assert!(absolute.matches("/usr/lib/libstdc++.so.6"));
+        assert!(!absolute.matches("/usr/lib32/libstdc++.so.6"));
+        assert!(!"/lib/libz.so".parse::<Pattern>().unwrap().matches("/usr/lib/libz.so"));
+    }
+}
@@ -0,0 +1,203 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Inspect and re-run the triggers of the active state
+//!
+//! Unlike during a transaction, handlers are compiled against every path of the
+//! active state, and transaction triggers run directly against the live `/usr`
+//! rather than a staging tree.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use thiserror::Error;
+use triggers::format::{Handler, Trigger};
+
+use super::{
+    create_root_links,
+    postblit::{self, TriggerRunner, TriggerScope},
+    Client, Scope,
+};
+use crate::{db, installation};
+
+/// When a trigger runs during a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Kind {
+    /// Against the staging tree, before it's activated
+    Transaction,
+    /// Against the system, once the new state is activated
+    System,
+}
+
+/// A trigger installed in the active state
+#[derive(Debug, Serialize)]
+pub struct Installed {
+    pub kind: Kind,
+    /// Why the trigger won't run, if inhibited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inhibited: Option<String>,
+    #[serde(flatten)]
+    pub trigger: Trigger,
+}
+
+/// A handler compiled against the active state
+#[derive(Debug, Serialize)]
+pub struct Compiled {
+    pub kind: Kind,
+    pub trigger: String,
+    pub handler: Handler,
+}
+
+/// List the triggers installed in the active state, by kind then name
+pub fn installed(client: &Client) -> Vec<Installed> {
+    let live = live_scope(client);
+
+    [Kind::Transaction, Kind::System]
+        .into_iter()
+        .flat_map(|kind| {
+            let scope = trigger_scope(client, &live, kind);
+            let environment = scope.environment();
+            let triggers = postblit::load(scope);
+
+            // Includes triggers inhibited through those they need, unless the definitions are broken
+            let inhibited = triggers::Collection::new(&triggers, &environment)
+                .map(|collection| {
+                    collection
+                        .inhibited()
+                        .map(|(name, reason)| (name.to_string(), reason.to_string()))
+                        .collect::<BTreeMap<_, _>>()
+                })
+                .unwrap_or_default();
+
+            let mut installed = triggers
+                .into_iter()
+                .map(|trigger| Installed {
+                    kind,
+                    inhibited: inhibited.get(&trigger.name).cloned().or_else(|| {
+                        trigger
+                            .inhibitors
+                            .as_ref()
+                            .and_then(|inhibitors| environment.inhibits(inhibitors))
+                    }),
+                    trigger,
+                })
+                .collect::<Vec<_>>();
+            installed.sort_by(|a, b| a.trigger.name.cmp(&b.trigger.name));
+            installed
+        })
+        .collect()
+}
+
+/// Compile the handlers of the active state in the order they'd run, limited to the
+/// triggers in `names` unless empty
+pub fn compile(client: &Client, names: &[String]) -> Result<Vec<Compiled>, Error> {
+    let live = live_scope(client);
+
+    Ok(runners(client, &live, names)?
+        .into_iter()
+        .flat_map(|(kind, runners)| {
+            runners.into_iter().map(move |runner| Compiled {
+                kind,
+                trigger: runner.handler().trigger().to_string(),
+                handler: runner.handler().handler().clone(),
+            })
+        })
+        .collect())
+}
+
+/// Execute the handlers of the active state in order, limited to the triggers in
+/// `names` unless empty, returning the number of handlers executed
+///
+/// Output is appended to the trigger log of the active state, and failures are
+/// handled as they would be during a transaction
+pub fn run(client: &Client, names: &[String]) -> Result<usize, Error> {
+    client.ensure_exclusive()?;
+
+    let state = client.installation.active_state.ok_or(Error::NoActiveState)?;
+    let live = live_scope(client);
+    let runners = runners(client, &live, names)?;
+    let count = runners.iter().map(|(_, runners)| runners.len()).sum();
+
+    create_root_links(&client.installation.isolation_dir()).map_err(postblit::Error::from)?;
+
+    let mut executor = postblit::Executor::new(&client.installation, state);
+    for (_, runners) in runners {
+        executor.run(runners)?;
+    }
+    client.finish_triggers(executor, Some(state))?;
+
+    Ok(count)
+}
+
+/// Compile the triggers of the active state by kind, in the order they'd run
+fn runners<'a>(
+    client: &'a Client,
+    live: &'a Scope,
+    names: &[String],
+) -> Result<Vec<(Kind, Vec<TriggerRunner<'a>>)>, Error> {
+    let state = client.installation.active_state.ok_or(Error::NoActiveState)?;
+    let state = client.state_db.get(state)?;
+    let fstree = client.vfs(state.selections.iter().map(|selection| &selection.package))?;
+
+    let scopes = [Kind::Transaction, Kind::System].map(|kind| (kind, trigger_scope(client, live, kind)));
+
+    let known = scopes
+        .iter()
+        .flat_map(|(_, scope)| postblit::load(*scope))
+        .map(|trigger| trigger.name)
+        .collect::<BTreeSet<_>>();
+    if let Some(name) = names.iter().find(|name| !known.contains(*name)) {
+        return Err(Error::UnknownTrigger(name.clone()));
+    }
+
+    scopes
+        .into_iter()
+        .map(|(kind, scope)| {
+            let runners = postblit::triggers(scope, &fstree, None)?
+                .into_iter()
+                .filter(|runner| names.is_empty() || names.iter().any(|name| name == runner.handler().trigger()))
+                .collect();
+
+            Ok((kind, runners))
+        })
+        .collect()
+}
+
+/// The active state is laid out like an ephemeral root, with triggers operating
+/// directly on its `/usr` rather than a staging tree
+fn live_scope(client: &Client) -> Scope {
+    Scope::Ephemeral {
+        blit_root: client.installation.root.clone(),
+    }
+}
+
+fn trigger_scope<'a>(client: &'a Client, live: &'a Scope, kind: Kind) -> TriggerScope<'a> {
+    match kind {
+        Kind::Transaction => TriggerScope::Transaction(&client.installation, live),
+        Kind::System => TriggerScope::System(&client.installation, live),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no active state")]
+    NoActiveState,
+
+    #[error("unknown trigger: {0}")]
+    UnknownTrigger(String),
+
+    #[error("client")]
+    Client(#[from] super::Error),
+
+    #[error("postblit")]
+    PostBlit(#[from] postblit::Error),
+
+    #[error("installation")]
+    Installation(#[from] installation::Error),
+
+    #[error("db")]
+    DB(#[from] db::Error),
+}
@@ -0,0 +1,527 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Verify the active `/usr` tree against the layout database
+//!
+//! Every file is expected to be a hardlink into the asset store with the
+//! recorded mode and content, device nodes, FIFOs and sockets with the
+//! recorded mode, and everything owned as recorded. Damaged files can be
+//! restored by relinking them from their asset, unpacking (and if need be
+//! fetching) the package again when the asset itself is missing or damaged.
+
+use std::{
+    collections::HashSet,
+    fmt,
+    fs::{self, File},
+    io::{self, Read},
+    os::unix::fs::{lchown, symlink, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use rayon::prelude::*;
+use serde::Serialize;
+use stone::payload::layout;
+use thiserror::Error;
+use vfs::tree::BlitFile;
+use xxhash_rust::xxh3::Xxh3;
+
+use super::{cache, create_special_file, postblit, Client};
+use crate::{package, runtime, Installation};
+
+/// A damaged file in the active installation
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    /// Package owning the file
+    pub package: package::Id,
+    /// Absolute path within the installation, i.e. `/usr/bin/bash`
+    pub path: String,
+    #[serde(flatten)]
+    pub issue: Issue,
+    #[serde(skip)]
+    layout: layout::Layout,
+}
+
+/// What's wrong with a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "issue", rename_all = "kebab-case")]
+pub enum Issue {
+    /// The file doesn't exist
+    Missing,
+    /// A different type of file exists in its place
+    TypeChanged { expected: String, found: String },
+    /// Permissions differ from the layout
+    ModeChanged { expected: u32, found: u32 },
+    /// Owning user or group differ from the layout
+    OwnerChanged { expected: Owner, found: Owner },
+    /// Content doesn't match the recorded hash
+    Modified,
+    /// Symlink points somewhere else
+    SymlinkChanged { expected: String, found: String },
+    /// Content is intact, but not a hardlink to the asset store
+    Unlinked,
+}
+
+impl Issue {
+    /// Whether only the metadata of an otherwise intact file is affected
+    fn is_metadata(&self) -> bool {
+        matches!(self, Issue::ModeChanged { .. } | Issue::OwnerChanged { .. })
+    }
+}
+
+/// Owning user and group of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.uid, self.gid)
+    }
+}
+
+/// Outcome of repairing damaged files
+#[derive(Debug, Default)]
+pub struct Repair {
+    /// Number of files restored
+    pub repaired: usize,
+    /// Number of files left damaged, as their asset couldn't be restored
+    pub unrepaired: usize,
+    /// Packages that couldn't be fetched again, along with why
+    pub failed: Vec<(package::Id, String)>,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Missing => write!(f, "missing"),
+            Issue::TypeChanged { expected, found } => write!(f, "expected {expected}, found {found}"),
+            Issue::ModeChanged { expected, found } => write!(f, "mode changed from {expected:04o} to {found:04o}"),
+            Issue::OwnerChanged { expected, found } => write!(f, "owner changed from {expected} to {found}"),
+            Issue::Modified => write!(f, "content modified"),
+            Issue::SymlinkChanged { expected, found } => write!(f, "symlink points to {found} instead of {expected}"),
+            Issue::Unlinked => write!(f, "not linked to the asset store"),
+        }
+    }
+}
+
+/// Verify the files of `packages` in the active state
+pub fn verify(client: &Client, packages: &[package::Id]) -> Result<Vec<Problem>, Error> {
+    if client.is_ephemeral() || client.installation.active_state.is_none() {
+        return Err(Error::NoActiveState);
+    }
+
+    let installation = &client.installation;
+    let files = client.vfs(packages)?.iter().collect::<Vec<_>>();
+
+    let mut problems = files
+        .par_iter()
+        .map(|file| check(installation, &file.id, &file.path(), &file.layout))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    // Parents sort before their children, which repair relies on
+    problems.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(problems)
+}
+
+/// Restore all damaged files
+///
+/// Packages that fail to be fetched again are reported in the [`Repair`], and only
+/// files needing one of their missing assets are left damaged.
+pub fn repair(client: &Client, problems: &[Problem]) -> Result<Repair, Error> {
+    // Relinking is only as good as the asset, so ensure those are intact first
+    let mut checked = HashSet::new();
+    let mut refetch = vec![];
+
+    for problem in problems {
+        let layout::Entry::Regular(hash, _) = &problem.layout.entry else {
+            continue;
+        };
+        if problem.issue.is_metadata() || !checked.insert(*hash) {
+            continue;
+        }
+
+        let asset = asset_path(&client.installation, *hash);
+        let intact = asset.exists() && hash_file(&asset)? == *hash;
+
+        if !intact {
+            // Ensure unpacking doesn't skip it
+            if asset.exists() {
+                fs::remove_file(&asset)?;
+            }
+            if !refetch.contains(&problem.package) {
+                refetch.push(problem.package.clone());
+            }
+        }
+    }
+
+    let mut repair = Repair::default();
+
+    // Fetch one at a time, so a single failure doesn't hold up the rest
+    for package in client.resolve_packages(&refetch)? {
+        if let Err(error) = runtime::block_on(client.cache_packages(&[&package])) {
+            repair.failed.push((package.id, postblit::error_chain(&error)));
+        }
+    }
+
+    let mut paths = problems.iter().map(|p| p.path.as_str()).collect::<Vec<_>>();
+    paths.dedup();
+
+    for path in paths {
+        let issues = problems.iter().filter(|p| p.path == path).collect::<Vec<_>>();
+        let target = target_path(&client.installation, path);
+        let layout = &issues[0].layout;
+
+        // Damaged assets were removed, so a missing one's package couldn't be fetched
+        let asset_lost = match &layout.entry {
+            layout::Entry::Regular(hash, _) => !asset_path(&client.installation, *hash).exists(),
+            _ => false,
+        };
+
+        if issues.iter().all(|p| p.issue.is_metadata()) {
+            restore_metadata(&target, layout)?;
+        } else if asset_lost {
+            repair.unrepaired += 1;
+            continue;
+        } else {
+            restore(&client.installation, &target, layout)?;
+        }
+
+        repair.repaired += 1;
+    }
+
+    Ok(repair)
+}
+
+/// Compare a single file on disk against its layout
+fn check(
+    installation: &Installation,
+    package: &package::Id,
+    path: &str,
+    layout: &layout::Layout,
+) -> Result<Vec<Problem>, Error> {
+    let problem = |issue| Problem {
+        package: package.clone(),
+        path: path.to_string(),
+        issue,
+        layout: layout.clone(),
+    };
+
+    let target = target_path(installation, path);
+    let metadata = match fs::symlink_metadata(&target) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![problem(Issue::Missing)]),
+        Err(error) => return Err(error.into()),
+    };
+
+    let expected = entry_kind(&layout.entry);
+    let found = file_kind(&metadata.file_type());
+    if expected != found {
+        return Ok(vec![problem(Issue::TypeChanged {
+            expected: expected.to_string(),
+            found: found.to_string(),
+        })]);
+    }
+
+    let mut problems = vec![];
+
+    match &layout.entry {
+        layout::Entry::Regular(hash, _) => {
+            if hash_file(&target)? != *hash {
+                problems.push(problem(Issue::Modified));
+            } else {
+                let asset = fs::metadata(asset_path(installation, *hash)).ok();
+                let linked = asset.is_some_and(|asset| asset.dev() == metadata.dev() && asset.ino() == metadata.ino());
+
+                if !linked {
+                    problems.push(problem(Issue::Unlinked));
+                }
+            }
+        }
+        layout::Entry::Symlink(source, _) => {
+            let found = fs::read_link(&target)?;
+            if found != Path::new(source) {
+                problems.push(problem(Issue::SymlinkChanged {
+                    expected: source.clone(),
+                    found: found.display().to_string(),
+                }));
+            }
+        }
+        _ => {}
+    }
+
+    let owner = Owner {
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+    };
+    let expected = Owner {
+        uid: layout.uid,
+        gid: layout.gid,
+    };
+    if owner != expected {
+        problems.push(problem(Issue::OwnerChanged { expected, found: owner }));
+    }
+
+    let mode = metadata.mode() & 0o7777;
+    if explicit_mode(layout) && mode != layout.mode & 0o7777 {
+        problems.push(problem(Issue::ModeChanged {
+            expected: layout.mode & 0o7777,
+            found: mode,
+        }));
+    }
+
+    Ok(problems)
+}
+
+/// Symlinks have no mode of their own and directories are subject to the umask
+/// when blitting, everything else has its mode set explicitly
+fn explicit_mode(layout: &layout::Layout) -> bool {
+    !matches!(layout.entry, layout::Entry::Symlink(..) | layout::Entry::Directory(_))
+}
+
+/// Reset the ownership and mode of the intact file at `target` to `layout`
+fn restore_metadata(target: &Path, layout: &layout::Layout) -> Result<(), Error> {
+    // Ownership first, as changing it clears any setuid / setgid bits
+    lchown(target, Some(layout.uid), Some(layout.gid))?;
+    if explicit_mode(layout) {
+        fs::set_permissions(target, fs::Permissions::from_mode(layout.mode & 0o7777))?;
+    }
+
+    Ok(())
+}
+
+/// Recreate the file at `target` as described by `layout`
+fn restore(installation: &Installation, target: &Path, layout: &layout::Layout) -> Result<(), Error> {
+    // Anything in the way is removed, only empty directories though
+    match fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_dir() => {
+            if !matches!(layout.entry, layout::Entry::Directory(_)) {
+                fs::remove_dir(target)?;
+            }
+        }
+        Ok(_) => fs::remove_file(target)?,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error.into()),
+    }
+
+    match &layout.entry {
+        layout::Entry::Regular(hash, _) => {
+            fs::hard_link(asset_path(installation, *hash), target)?;
+            restore_metadata(target, layout)?;
+        }
+        layout::Entry::Symlink(source, _) => {
+            symlink(source, target)?;
+            restore_metadata(target, layout)?;
+        }
+        layout::Entry::Directory(_) if !target.exists() => {
+            fs::create_dir(target)?;
+            fs::set_permissions(target, fs::Permissions::from_mode(layout.mode & 0o7777))?;
+            restore_metadata(target, layout)?;
+        }
+        layout::Entry::Directory(_) => restore_metadata(target, layout)?,
+        layout::Entry::CharacterDevice(..)
+        | layout::Entry::BlockDevice(..)
+        | layout::Entry::Fifo(_)
+        | layout::Entry::Socket(_) => {
+            create_special_file(None, &target.to_string_lossy(), layout).map_err(io::Error::from)?
+        }
+    }
+
+    Ok(())
+}
+
+/// The xxh128 digest of the file at `path`, as used to address the asset store
+fn hash_file(path: &Path) -> Result<u128, Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = Xxh3::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.digest128())
+}
+
+fn asset_path(installation: &Installation, hash: u128) -> PathBuf {
+    cache::asset_path(installation, &format!("{hash:02x}"))
+}
+
+/// Resolve the installation `path` within the root
+fn target_path(installation: &Installation, path: &str) -> PathBuf {
+    installation.root.join(path.trim_start_matches('/'))
+}
+
+fn entry_kind(entry: &layout::Entry) -> &'static str {
+    match entry {
+        layout::Entry::Regular(..) => "regular file",
+        layout::Entry::Symlink(..) => "symlink",
+        layout::Entry::Directory(_) => "directory",
+        layout::Entry::CharacterDevice(..) => "character device",
+        layout::Entry::BlockDevice(..) => "block device",
+        layout::Entry::Fifo(_) => "fifo",
+        layout::Entry::Socket(_) => "socket",
+    }
+}
+
+fn file_kind(file_type: &fs::FileType) -> &'static str {
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_file() {
+        "regular file"
+    } else if file_type.is_char_device() {
+        "character device"
+    } else if file_type.is_block_device() {
+        "block device"
+    } else if file_type.is_fifo() {
+        "fifo"
+    } else {
+        "socket"
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no active state to verify")]
+    NoActiveState,
+
+    #[error("client")]
+    Client(#[from] super::Error),
+
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_regular_file() {
+        let root = std::env::temp_dir().join(format!("moss-verify-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("usr")).unwrap();
+        let installation = Installation::open(&root).unwrap();
+
+        let target = root.join("usr/file");
+        fs::write(&target, b"content").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let hash = hash_file(&target).unwrap();
+        let (uid, gid) = (nix::unistd::getuid().as_raw(), nix::unistd::getgid().as_raw());
+        let layout = layout::Layout {
+            uid,
+            gid,
+            mode: 0o100644,
+            tag: 0,
+            entry: layout::Entry::Regular(hash, "file".into()),
+        };
+        let id = package::Id::from("test".to_string());
+        let issues = |layout: &layout::Layout| {
+            check(&installation, &id, "/usr/file", layout)
+                .unwrap()
+                .into_iter()
+                .map(|p| p.issue)
+                .collect::<Vec<_>>()
+        };
+
+        // Not yet in the asset store
+        assert_eq!(issues(&layout), vec![Issue::Unlinked]);
+
+        let asset = asset_path(&installation, hash);
+        fs::create_dir_all(asset.parent().unwrap()).unwrap();
+        fs::hard_link(&target, &asset).unwrap();
+        assert!(issues(&layout).is_empty());
+
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(
+            issues(&layout),
+            vec![Issue::ModeChanged {
+                expected: 0o644,
+                found: 0o600
+            }]
+        );
+
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o644)).unwrap();
+        let owner = layout::Layout {
+            uid: uid + 1,
+            ..layout.clone()
+        };
+        assert_eq!(
+            issues(&owner),
+            vec![Issue::OwnerChanged {
+                expected: Owner { uid: uid + 1, gid },
+                found: Owner { uid, gid }
+            }]
+        );
+
+        fs::write(&target, b"changed").unwrap();
+        assert_eq!(issues(&layout), vec![Issue::Modified]);
+
+        fs::remove_file(&target).unwrap();
+        assert_eq!(issues(&layout), vec![Issue::Missing]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn restore_fifo() {
+        let root = std::env::temp_dir().join(format!("moss-verify-fifo-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("usr")).unwrap();
+        let installation = Installation::open(&root).unwrap();
+
+        // Owned by us so restoring doesn't need privileges
+        let layout = layout::Layout {
+            uid: nix::unistd::getuid().as_raw(),
+            gid: nix::unistd::getgid().as_raw(),
+            mode: 0o010620,
+            tag: 0,
+            entry: layout::Entry::Fifo("fifo".into()),
+        };
+        let id = package::Id::from("test".to_string());
+        let target = root.join("usr/fifo");
+        let issues = || {
+            check(&installation, &id, "/usr/fifo", &layout)
+                .unwrap()
+                .into_iter()
+                .map(|p| p.issue)
+                .collect::<Vec<_>>()
+        };
+
+        fs::write(&target, b"in the way").unwrap();
+        assert_eq!(
+            issues(),
+            vec![Issue::TypeChanged {
+                expected: "fifo".into(),
+                found: "regular file".into()
+            }]
+        );
+
+        restore(&installation, &target, &layout).unwrap();
+        assert!(issues().is_empty());
+
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(
+            issues(),
+            vec![Issue::ModeChanged {
+                expected: 0o620,
+                found: 0o600
+            }]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
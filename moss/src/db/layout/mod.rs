@@ -153,14 +153,19 @@ fn decode_entry(
         }
         "symlink" => Some(Entry::Symlink(entry_value1?, entry_value2?)),
         "directory" => Some(Entry::Directory(entry_value1?)),
-        "character-device" => Some(Entry::CharacterDevice(entry_value1?)),
-        "block-device" => Some(Entry::BlockDevice(entry_value1?)),
+        // Device numbers weren't always recorded
+        "character-device" => Some(Entry::CharacterDevice(decode_device(entry_value2), entry_value1?)),
+        "block-device" => Some(Entry::BlockDevice(decode_device(entry_value2), entry_value1?)),
         "fifo" => Some(Entry::Fifo(entry_value1?)),
         "socket" => Some(Entry::Socket(entry_value1?)),
         _ => None,
     }
 }
 
+fn decode_device(value: Option<String>) -> u64 {
+    value.and_then(|device| device.parse().ok()).unwrap_or_default()
+}
+
 fn encode_entry(entry: payload::layout::Entry) -> (&'static str, Option<String>, Option<String>) {
     use payload::layout::Entry;
 
@@ -168,8 +173,8 @@ fn encode_entry(entry: payload::layout::Entry) -> (&'static str, Option<String>,
         Entry::Regular(hash, name) => ("regular", Some(hash.to_string()), Some(name)),
         Entry::Symlink(a, b) => ("symlink", Some(a), Some(b)),
         Entry::Directory(name) => ("directory", Some(name), None),
-        Entry::CharacterDevice(name) => ("character-device", Some(name), None),
-        Entry::BlockDevice(name) => ("block-device", Some(name), None),
+        Entry::CharacterDevice(device, name) => ("character-device", Some(name), Some(device.to_string())),
+        Entry::BlockDevice(device, name) => ("block-device", Some(name), Some(device.to_string())),
         Entry::Fifo(name) => ("fifo", Some(name), None),
         Entry::Socket(name) => ("socket", Some(name), None),
     }
@@ -242,4 +247,46 @@ mod test {
 
         assert_eq!(count, all.len());
     }
+
+    #[test]
+    fn special_files() {
+        use payload::layout::Entry;
+
+        let database = Database::new(":memory:").unwrap();
+
+        let entries = [
+            Entry::CharacterDevice(0x0501, "dev/console".into()),
+            Entry::BlockDevice(0x0800, "dev/sda".into()),
+            Entry::Fifo("run/initctl".into()),
+            Entry::Socket("run/socket".into()),
+        ];
+        let layouts = entries
+            .into_iter()
+            .map(|entry| {
+                (
+                    package::Id::from("test".to_string()),
+                    payload::Layout {
+                        uid: 0,
+                        gid: 5,
+                        mode: 0o20620,
+                        tag: 0,
+                        entry,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+
+        database.batch_add(layouts.clone()).unwrap();
+
+        let mut all = database.all().unwrap();
+        all.sort_by(|(_, a), (_, b)| a.entry.target().cmp(b.entry.target()));
+
+        assert_eq!(all, layouts);
+
+        // Device numbers weren't always recorded
+        assert_eq!(
+            decode_entry("block-device".into(), Some("dev/sda".into()), None),
+            Some(Entry::BlockDevice(0, "dev/sda".into()))
+        );
+    }
 }
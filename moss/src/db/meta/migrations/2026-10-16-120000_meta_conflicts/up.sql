@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS meta_conflicts (
+    package TEXT NOT NULL,
+    conflict TEXT NOT NULL,
+    PRIMARY KEY (package, conflict),
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS meta_deltas (
+    package TEXT NOT NULL,
+    from_package TEXT NOT NULL,
+    uri TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    PRIMARY KEY (package, from_package),
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
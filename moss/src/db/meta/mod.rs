@@ -70,6 +70,16 @@ impl Database {
                 .load_iter(conn)?
                 .map(|p| Ok(p?.provider))
                 .collect::<Result<_, Error>>()?;
+            let conflicts = model::Conflict::belonging_to(&meta)
+                .select(model::Conflict::as_select())
+                .load_iter(conn)?
+                .map(|c| Ok(c?.conflict))
+                .collect::<Result<_, Error>>()?;
+            let deltas = model::Delta::belonging_to(&meta)
+                .select(model::Delta::as_select())
+                .load_iter(conn)?
+                .map(|d| Ok(d?.into()))
+                .collect::<Result<_, Error>>()?;
 
             Ok(Meta {
                 name: meta.name,
@@ -84,9 +94,11 @@ impl Database {
                 licenses,
                 dependencies,
                 providers,
+                conflicts,
                 uri: meta.uri,
                 hash: meta.hash,
                 download_size: meta.download_size.map(|size| size as u64),
+                deltas,
             })
         })
     }
@@ -106,6 +118,30 @@ impl Database {
         })
     }
 
+    /// Returns the identity & version of packages with the given provider,
+    /// without loading the rest of their metadata
+    pub fn provider_candidates(&self, provider: &Provider) -> Result<Vec<package::Candidate>, Error> {
+        self.conn.exec(|conn| {
+            model::meta::table
+                .inner_join(model::meta_providers::table)
+                .select(model::Candidate::as_select())
+                .filter(model::meta_providers::provider.eq(provider.to_string()))
+                .load_iter::<model::Candidate, _>(conn)?
+                .map(|result| {
+                    let candidate = result?;
+
+                    Ok(package::Candidate {
+                        id: candidate.package.into(),
+                        name: candidate.name,
+                        version_identifier: candidate.version_identifier,
+                        source_release: candidate.source_release as u64,
+                        build_release: candidate.build_release as u64,
+                    })
+                })
+                .collect()
+        })
+    }
+
     pub fn query(&self, filter: Option<Filter>) -> Result<Vec<(package::Id, Meta)>, Error> {
         self.conn.exec(|conn| {
             let map_row = |result| {
@@ -126,9 +162,11 @@ impl Database {
                         licenses: Default::default(),
                         dependencies: Default::default(),
                         providers: Default::default(),
+                        conflicts: Default::default(),
                         uri: meta.uri,
                         hash: meta.hash,
                         download_size: meta.download_size.map(|size| size as u64),
+                        deltas: Default::default(),
                     },
                 ))
             };
@@ -206,6 +244,28 @@ impl Database {
                         }
                         Ok(())
                     })?;
+
+                // Add conflicts
+                model::Conflict::belonging_to(chunk)
+                    .load_iter::<model::Conflict, _>(conn)?
+                    .try_for_each::<_, Result<_, Error>>(|result| {
+                        let row = result?;
+                        if let Some(meta) = entries.get_mut(&row.package.into()) {
+                            meta.conflicts.insert(row.conflict);
+                        }
+                        Ok(())
+                    })?;
+
+                // Add deltas
+                model::Delta::belonging_to(chunk)
+                    .load_iter::<model::Delta, _>(conn)?
+                    .try_for_each::<_, Result<_, Error>>(|result| {
+                        let row = result?;
+                        if let Some(meta) = entries.get_mut(&row.package.clone().into()) {
+                            meta.deltas.push(row.into());
+                        }
+                        Ok(())
+                    })?;
             }
 
             Ok(entries.into_iter().collect())
@@ -281,6 +341,29 @@ impl Database {
                     })
                 })
                 .collect::<Vec<_>>();
+            let conflicts = packages
+                .iter()
+                .flat_map(|(package, meta)| {
+                    meta.conflicts.iter().map(|conflict| {
+                        (
+                            model::meta_conflicts::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                            model::meta_conflicts::conflict.eq(conflict.to_string()),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            let deltas = packages
+                .iter()
+                .flat_map(|(package, meta)| {
+                    meta.deltas.iter().map(|delta| model::NewDelta {
+                        package: package.as_ref(),
+                        from_package: &delta.from,
+                        uri: &delta.uri,
+                        hash: &delta.hash,
+                        size: delta.size as i64,
+                    })
+                })
+                .collect::<Vec<_>>();
 
             conn.transaction(|conn| {
                 batch_remove_impl(&ids, conn)?;
@@ -295,6 +378,12 @@ impl Database {
                 diesel::insert_into(model::meta_providers::table)
                     .values(providers)
                     .execute(conn)?;
+                diesel::insert_into(model::meta_conflicts::table)
+                    .values(conflicts)
+                    .execute(conn)?;
+                diesel::insert_into(model::meta_deltas::table)
+                    .values(deltas)
+                    .execute(conn)?;
                 Ok(())
             })
         })
@@ -329,7 +418,9 @@ mod model {
         Selectable,
     };
 
-    pub use crate::db::meta::schema::{meta, meta_dependencies, meta_licenses, meta_providers};
+    pub use crate::db::meta::schema::{
+        meta, meta_conflicts, meta_deltas, meta_dependencies, meta_licenses, meta_providers,
+    };
     use crate::package;
 
     #[derive(Queryable, Selectable, Identifiable)]
@@ -352,6 +443,18 @@ mod model {
         pub download_size: Option<i64>,
     }
 
+    #[derive(Queryable, Selectable, Identifiable)]
+    #[diesel(table_name = meta)]
+    #[diesel(primary_key(package))]
+    pub struct Candidate {
+        pub package: String,
+        #[diesel(deserialize_as = String)]
+        pub name: package::Name,
+        pub version_identifier: String,
+        pub source_release: i32,
+        pub build_release: i32,
+    }
+
     #[derive(Queryable, Selectable, Identifiable)]
     #[diesel(table_name = meta)]
     #[diesel(primary_key(package))]
@@ -392,6 +495,51 @@ mod model {
         pub provider: crate::Provider,
     }
 
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = meta_conflicts)]
+    #[diesel(primary_key(package, conflict))]
+    #[diesel(belongs_to(Meta, foreign_key = package))]
+    #[diesel(belongs_to(PackageId, foreign_key = package))]
+    pub struct Conflict {
+        pub package: String,
+        #[diesel(deserialize_as = String)]
+        pub conflict: crate::Provider,
+    }
+
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = meta_deltas)]
+    #[diesel(primary_key(package, from_package))]
+    #[diesel(belongs_to(Meta, foreign_key = package))]
+    #[diesel(belongs_to(PackageId, foreign_key = package))]
+    pub struct Delta {
+        pub package: String,
+        pub from_package: String,
+        pub uri: String,
+        pub hash: String,
+        pub size: i64,
+    }
+
+    impl From<Delta> for package::meta::Delta {
+        fn from(delta: Delta) -> Self {
+            Self {
+                from: delta.from_package,
+                uri: delta.uri,
+                hash: delta.hash,
+                size: delta.size as u64,
+            }
+        }
+    }
+
+    #[derive(Insertable)]
+    #[diesel(table_name = meta_deltas)]
+    pub struct NewDelta<'a> {
+        pub package: &'a str,
+        pub from_package: &'a str,
+        pub uri: &'a str,
+        pub hash: &'a str,
+        pub size: i64,
+    }
+
     #[derive(Insertable)]
     #[diesel(table_name = meta)]
     pub struct NewMeta<'a> {
@@ -429,13 +577,26 @@ mod test {
 
         let payloads = stone.payloads().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
         let meta_payload = payloads.iter().find_map(PayloadKind::meta).unwrap();
-        let meta = Meta::from_stone_payload(&meta_payload.body).unwrap();
+        let mut meta = Meta::from_stone_payload(&meta_payload.body).unwrap();
+        meta.dependencies
+            .insert(Dependency::from_name("soname(libz.so.1(x86_64)) release >= 5").unwrap());
+        meta.conflicts
+            .insert(Provider::from_name("name(bash-completion-legacy)").unwrap());
+        meta.deltas.push(package::meta::Delta {
+            from: "abc".into(),
+            uri: "bash-completion.delta.stone".into(),
+            hash: "def".into(),
+            size: 1024,
+        });
 
         let id = package::Id::from("test".to_string());
 
         db.add(id.clone(), meta.clone()).unwrap();
 
         assert_eq!(&meta.name, &"bash-completion".to_string().into());
+        assert_eq!(db.get(&id).unwrap().dependencies, meta.dependencies);
+        assert_eq!(db.get(&id).unwrap().conflicts, meta.conflicts);
+        assert_eq!(db.get(&id).unwrap().deltas, meta.deltas);
 
         // Now retrieve by provider.
         let lookup = Filter::Provider(Provider {
@@ -444,6 +605,20 @@ mod test {
         });
         let fetched = db.query(Some(lookup)).unwrap();
         assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].1.conflicts, meta.conflicts);
+        assert_eq!(fetched[0].1.deltas, meta.deltas);
+
+        let candidates = db
+            .provider_candidates(&Provider::from_name("bash-completion").unwrap())
+            .unwrap();
+        assert_eq!(
+            candidates,
+            vec![package::Candidate::from(&package::Package {
+                id: id.clone(),
+                meta: meta.clone(),
+                flags: package::Flags::new(),
+            })]
+        );
 
         db.remove(&id).unwrap();
 
@@ -18,6 +18,23 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    meta_conflicts (package, conflict) {
+        package -> Text,
+        conflict -> Text,
+    }
+}
+
+diesel::table! {
+    meta_deltas (package, from_package) {
+        package -> Text,
+        from_package -> Text,
+        uri -> Text,
+        hash -> Text,
+        size -> BigInt,
+    }
+}
+
 diesel::table! {
     meta_dependencies (package, dependency) {
         package -> Text,
@@ -39,8 +56,17 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(meta_conflicts -> meta (package));
+diesel::joinable!(meta_deltas -> meta (package));
 diesel::joinable!(meta_dependencies -> meta (package));
 diesel::joinable!(meta_licenses -> meta (package));
 diesel::joinable!(meta_providers -> meta (package));
 
-diesel::allow_tables_to_appear_in_same_query!(meta, meta_dependencies, meta_licenses, meta_providers,);
+diesel::allow_tables_to_appear_in_same_query!(
+    meta,
+    meta_conflicts,
+    meta_deltas,
+    meta_dependencies,
+    meta_licenses,
+    meta_providers,
+);
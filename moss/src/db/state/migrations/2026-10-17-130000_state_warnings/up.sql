@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS state_warnings (
+    state_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    PRIMARY KEY (state_id, position),
+    FOREIGN KEY (state_id) REFERENCES state(id) ON DELETE CASCADE
+);
@@ -62,6 +62,12 @@ impl Database {
                     })
                 })
                 .collect::<Result<_, Error>>()?;
+            let warnings = model::Warning::belonging_to(&state)
+                .select(model::Warning::as_select())
+                .order(model::state_warnings::position)
+                .load_iter(conn)?
+                .map(|result| Ok(result?.message))
+                .collect::<Result<_, Error>>()?;
 
             Ok(State {
                 id: state.id.into(),
@@ -70,6 +76,7 @@ impl Database {
                 selections,
                 created: state.created.0,
                 kind: state.kind,
+                warnings,
             })
         })
     }
@@ -113,6 +120,34 @@ impl Database {
             .and_then(|id| self.get(id))
     }
 
+    /// Record `warnings` against `state`, following any already recorded
+    pub fn add_warnings(&self, state: &state::Id, warnings: &[String]) -> Result<(), Error> {
+        self.conn.exec(|conn| {
+            conn.transaction(|conn| {
+                let state_id = i32::from(*state);
+                let existing = model::state_warnings::table
+                    .filter(model::state_warnings::state_id.eq(state_id))
+                    .count()
+                    .get_result::<i64>(conn)? as i32;
+
+                let warnings = warnings
+                    .iter()
+                    .enumerate()
+                    .map(|(index, message)| model::NewWarning {
+                        state_id,
+                        position: existing + index as i32,
+                        message,
+                    })
+                    .collect::<Vec<_>>();
+
+                diesel::insert_into(model::state_warnings::table)
+                    .values(warnings)
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
     pub fn remove(&self, state: &state::Id) -> Result<(), Error> {
         self.batch_remove(Some(state))
     }
@@ -141,7 +176,7 @@ mod model {
 
     use crate::{db::Timestamp, package, state::Kind};
 
-    pub use super::schema::{state, state_selections};
+    pub use super::schema::{state, state_selections, state_warnings};
 
     #[derive(Queryable, Selectable, Identifiable)]
     #[diesel(table_name = state)]
@@ -168,6 +203,16 @@ mod model {
         pub reason: Option<String>,
     }
 
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = state_warnings)]
+    #[diesel(primary_key(state_id, position))]
+    #[diesel(belongs_to(State))]
+    pub struct Warning {
+        pub state_id: i32,
+        pub position: i32,
+        pub message: String,
+    }
+
     #[derive(Queryable, Selectable, Identifiable)]
     #[diesel(table_name = state)]
     #[diesel(check_for_backend(Sqlite))]
@@ -194,6 +239,14 @@ mod model {
         pub explicit: bool,
         pub reason: Option<&'a str>,
     }
+
+    #[derive(Insertable)]
+    #[diesel(table_name = state_warnings)]
+    pub struct NewWarning<'a> {
+        pub state_id: i32,
+        pub position: i32,
+        pub message: &'a str,
+    }
 }
 
 #[cfg(test)]
@@ -227,5 +280,12 @@ mod test {
         assert_eq!(state.description.as_deref(), Some("test"));
 
         assert_eq!(state.selections, selections);
+        assert!(state.warnings.is_empty());
+
+        database.add_warnings(&state.id, &["first".into()]).unwrap();
+        database
+            .add_warnings(&state.id, &["second".into(), "third".into()])
+            .unwrap();
+        assert_eq!(database.get(state.id).unwrap().warnings, ["first", "second", "third"]);
     }
 }
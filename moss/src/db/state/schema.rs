@@ -20,6 +20,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    state_warnings (state_id, position) {
+        state_id -> Integer,
+        position -> Integer,
+        message -> Text,
+    }
+}
+
 diesel::joinable!(state_selections -> state (state_id));
+diesel::joinable!(state_warnings -> state (state_id));
 
-diesel::allow_tables_to_appear_in_same_query!(state, state_selections,);
+diesel::allow_tables_to_appear_in_same_query!(state, state_selections, state_warnings,);
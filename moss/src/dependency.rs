@@ -16,13 +16,14 @@
 //! As one might expect, a [`Provider`] is the inverse of a dependency. It is used to record
 //! the capabilities of a package such that others may depend on it through resolution.
 //!
-//! We acknowledge the current dependency format, while powerful, does not allow expressing
-//! relationship constraints. This was a deliberate decision due to the rolling nature of
-//! Serpent OS, however more expressive dependency types will be introduced in the next major
-//! stone format.
-use std::str::FromStr;
+//! A dependency may optionally carry a [`Constraint`] on the version or release of the
+//! package providing it, i.e. `name(foo) >= 1.2` or `soname(libz.so.1(x86_64)) release>=5`.
+//! The stone format has no dedicated field for constraints, so they're appended to the
+//! dependency target when encoded.
+use std::{cmp::Ordering, fmt, str::FromStr};
 
 use derive_more::Display;
+use serde::{Serialize, Serializer};
 use stone::payload;
 use thiserror::Error;
 
@@ -95,16 +96,18 @@ impl From<Kind> for payload::meta::Dependency {
     }
 }
 
-/// A Dependency in moss is simplistic in that it only contains
-/// a target and a Kind, ie. `pkgconfig(zlib)`
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Display)]
-#[display(fmt = "{kind}({name})")]
+/// A Dependency in moss contains a target and a Kind, ie. `pkgconfig(zlib)`,
+/// optionally constrained to specific versions of the provider, ie. `name(zlib) >= 1.3`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Dependency {
     /// Specific type of dependency
     pub kind: Kind,
 
     /// Bare target (i.e. `libz.so.1(x86_64)`)
     pub name: String,
+
+    /// Requirement on the package providing this dependency
+    pub constraint: Option<Constraint>,
 }
 
 impl Dependency {
@@ -119,17 +122,70 @@ impl Dependency {
     /// ```
     ///     use moss::Dependency;
     ///     let dep = Dependency::from_name("pkgconfig(zlib)").unwrap();
+    ///     let dep = Dependency::from_name("zlib >= 1.3").unwrap();
     /// ```
     pub fn from_name(name: &str) -> Result<Self, ParseError> {
-        if name.contains('(') {
-            Dependency::from_str(name)
+        let (target, constraint) = split_constraint(name)?;
+
+        if target.contains('(') {
+            let (kind, name) = parse(target)?;
+
+            Ok(Dependency { kind, name, constraint })
         } else {
             Ok(Dependency {
                 kind: Kind::PackageName,
-                name: name.to_owned(),
+                name: target.to_owned(),
+                constraint,
             })
         }
     }
+
+    /// Returns true if a package at `version` & `release` satisfies
+    /// the constraint of this dependency, if any
+    pub fn allows(&self, version: &str, release: u64) -> bool {
+        self.constraint
+            .as_ref()
+            .is_none_or(|constraint| constraint.allows(version, release))
+    }
+
+    /// The dependency target as encoded in a stone payload, with
+    /// any constraint appended
+    pub fn payload_name(&self) -> String {
+        match &self.constraint {
+            Some(constraint) => format!("{} {constraint}", self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Construct a dependency from a stone payload target which
+    /// may have a constraint appended
+    pub fn from_payload(kind: Kind, name: &str) -> Self {
+        match split_constraint(name) {
+            Ok((target, constraint)) => Self {
+                kind,
+                name: target.to_owned(),
+                constraint,
+            },
+            // Not a valid constraint, treat it all as the target
+            Err(_) => Self {
+                kind,
+                name: name.to_owned(),
+                constraint: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self.kind, self.name)?;
+
+        if let Some(constraint) = &self.constraint {
+            write!(f, " {constraint}")?;
+        }
+
+        Ok(())
+    }
 }
 
 /// Partial ordering comparator for dependencies
@@ -151,9 +207,10 @@ impl FromStr for Dependency {
     type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (kind, name) = parse(s)?;
+        let (target, constraint) = split_constraint(s)?;
+        let (kind, name) = parse(target)?;
 
-        Ok(Self { kind, name })
+        Ok(Self { kind, name, constraint })
     }
 }
 
@@ -166,6 +223,13 @@ impl TryFrom<String> for Dependency {
     }
 }
 
+/// Serialize in the same `kind(name)` form accepted by [`Dependency::from_name`]
+impl Serialize for Dependency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 /// A provider is the inverse of a [`Dependency`] - providing the matching requirement
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Display)]
 #[display(fmt = "{kind}({name})")]
@@ -227,6 +291,198 @@ impl TryFrom<String> for Provider {
     }
 }
 
+/// Serialize in the same `kind(name)` form accepted by [`Provider::from_name`]
+impl Serialize for Provider {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Which property of the providing package a [`Constraint`] applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum Field {
+    /// The human readable version identifier, i.e. `1.2.13`
+    Version,
+    /// The source release number
+    Release,
+}
+
+/// Comparison operator of a [`Constraint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, strum::EnumString)]
+pub enum Operator {
+    #[strum(serialize = "<")]
+    Less,
+    #[strum(serialize = "<=")]
+    LessOrEqual,
+    #[strum(serialize = "=", serialize = "==")]
+    Equal,
+    #[strum(serialize = "!=")]
+    NotEqual,
+    #[strum(serialize = ">=")]
+    GreaterOrEqual,
+    #[strum(serialize = ">")]
+    Greater,
+}
+
+impl Operator {
+    /// Returns true if the `ordering` of a candidate relative
+    /// to the required value satisfies this operator
+    fn accepts(self, ordering: Ordering) -> bool {
+        match self {
+            Operator::Less => ordering.is_lt(),
+            Operator::LessOrEqual => ordering.is_le(),
+            Operator::Equal => ordering.is_eq(),
+            Operator::NotEqual => ordering.is_ne(),
+            Operator::GreaterOrEqual => ordering.is_ge(),
+            Operator::Greater => ordering.is_gt(),
+        }
+    }
+}
+
+/// A requirement on the version or release of the package satisfying a [`Dependency`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Constraint {
+    pub field: Field,
+    pub operator: Operator,
+    pub value: String,
+}
+
+impl Constraint {
+    /// Returns true if a package at `version` & `release` satisfies this constraint
+    pub fn allows(&self, version: &str, release: u64) -> bool {
+        let ordering = match self.field {
+            Field::Version => compare_versions(version, &self.value),
+            // Validated when parsed
+            Field::Release => release.cmp(&self.value.parse().unwrap_or_default()),
+        };
+
+        self.operator.accepts(ordering)
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.field {
+            Field::Version => write!(f, "{} {}", self.operator, self.value),
+            Field::Release => write!(f, "{} {} {}", self.field, self.operator, self.value),
+        }
+    }
+}
+
+/// Parse a constraint such as `>= 1.2`, `release>=5` or `version = 2.0`
+impl FromStr for Constraint {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError(s.to_string());
+
+        let s = s.trim();
+        let operator_start = s.find(is_operator_char).ok_or_else(invalid)?;
+        let field = match s[..operator_start].trim() {
+            "" => Field::Version,
+            field => field.parse().map_err(|_| invalid())?,
+        };
+
+        let rest = &s[operator_start..];
+        let operator_end = rest.find(|c| !is_operator_char(c)).unwrap_or(rest.len());
+        let operator = rest[..operator_end].parse::<Operator>().map_err(|_| invalid())?;
+
+        let value = rest[operator_end..].trim();
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        if field == Field::Release && value.parse::<u64>().is_err() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            field,
+            operator,
+            value: value.to_owned(),
+        })
+    }
+}
+
+fn is_operator_char(c: char) -> bool {
+    matches!(c, '<' | '>' | '=' | '!')
+}
+
+/// Split a dependency string into its target & optional constraint
+fn split_constraint(s: &str) -> Result<(&str, Option<Constraint>), ParseError> {
+    let s = s.trim();
+
+    match s.find(|c: char| c.is_whitespace() || is_operator_char(c)) {
+        Some(index) => Ok((&s[..index], Some(s[index..].parse()?))),
+        None => Ok((s, None)),
+    }
+}
+
+/// Compare two version identifiers segment by segment, where a segment is
+/// a run of digits (compared numerically) or letters (compared lexically).
+/// Numeric segments sort after alphabetic ones and trailing letters denote a
+/// pre-release, so `1.0` > `1.0rc1` > `1.0beta`.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a = version_segments(a);
+    let mut b = version_segments(b);
+
+    loop {
+        let ordering = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(a), None) => return pre_release_ordering(a),
+            (None, Some(b)) => return pre_release_ordering(b).reverse(),
+            (Some(a), Some(b)) => {
+                let a_numeric = a.starts_with(|c: char| c.is_ascii_digit());
+                let b_numeric = b.starts_with(|c: char| c.is_ascii_digit());
+
+                match (a_numeric, b_numeric) {
+                    (true, true) => {
+                        let a = a.trim_start_matches('0');
+                        let b = b.trim_start_matches('0');
+                        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+                    }
+                    (true, false) => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    (false, false) => a.cmp(b),
+                }
+            }
+        };
+
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+}
+
+/// Ordering of a version with the extra `segment` relative to the same version without
+fn pre_release_ordering(segment: &str) -> Ordering {
+    if segment.starts_with(|c: char| c.is_ascii_digit()) {
+        Ordering::Greater
+    } else {
+        Ordering::Less
+    }
+}
+
+/// Split a version into its runs of digits & letters, ignoring separators
+fn version_segments(version: &str) -> impl Iterator<Item = &str> {
+    let mut rest = version;
+
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches(|c: char| !(c.is_ascii_digit() || c.is_alphabetic()));
+        let first = rest.chars().next()?;
+        let end = if first.is_ascii_digit() {
+            rest.find(|c: char| !c.is_ascii_digit())
+        } else {
+            rest.find(|c: char| !c.is_alphabetic())
+        }
+        .unwrap_or(rest.len());
+
+        let (segment, remaining) = rest.split_at(end);
+        rest = remaining;
+        Some(segment)
+    })
+}
+
 /// Parse the [`Kind`] of dependency or provider from the string
 fn parse(s: &str) -> Result<(Kind, String), ParseError> {
     let (kind, rest) = s.split_once('(').ok_or(ParseError(s.to_string()))?;
@@ -246,3 +502,55 @@ fn parse(s: &str) -> Result<(Kind, String), ParseError> {
 #[derive(Debug, Error)]
 #[error("Invalid dependency type: {0}")]
 pub struct ParseError(String);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_constraints() {
+        let dep = Dependency::from_name("name(foo) >= 1.2").unwrap();
+        assert_eq!(dep.kind, Kind::PackageName);
+        assert_eq!(dep.name, "foo");
+        assert_eq!(
+            dep.constraint,
+            Some(Constraint {
+                field: Field::Version,
+                operator: Operator::GreaterOrEqual,
+                value: "1.2".into()
+            })
+        );
+        assert_eq!(dep.to_string(), "name(foo) >= 1.2");
+
+        let dep = Dependency::from_name("soname(libz.so.1(x86_64)) release>=5").unwrap();
+        assert_eq!(dep.name, "libz.so.1(x86_64)");
+        assert_eq!(dep.to_string(), "soname(libz.so.1(x86_64)) release >= 5");
+        assert_eq!(dep.to_string().parse::<Dependency>().unwrap(), dep);
+
+        let dep = Dependency::from_name("foo<2").unwrap();
+        assert_eq!(dep.name, "foo");
+        assert_eq!(dep.constraint.unwrap().operator, Operator::Less);
+
+        assert!(Dependency::from_name("pkgconfig(zlib)").unwrap().constraint.is_none());
+        assert!(Dependency::from_name("foo >=").is_err());
+        assert!(Dependency::from_name("foo release >= five").is_err());
+        assert!(Dependency::from_name("foo => 1").is_err());
+
+        let dep = Dependency::from_payload(Kind::SharedLibrary, "libz.so.1(x86_64) release >= 5");
+        assert_eq!(dep.payload_name(), "libz.so.1(x86_64) release >= 5");
+        assert!(dep.allows("1.3", 5));
+        assert!(!dep.allows("1.3", 4));
+    }
+
+    #[test]
+    fn version_ordering() {
+        assert_eq!(compare_versions("1.2", "1.2"), Ordering::Equal);
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.2.1", "1.2"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("2.0a", "2.0b"), Ordering::Less);
+        assert_eq!(compare_versions("1.02", "1.2"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0", "1.0rc1"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0rc1", "1.0beta"), Ordering::Greater);
+    }
+}
@@ -2,6 +2,8 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::time::Duration;
+
 pub const NAME: &str = env!("CARGO_PKG_NAME");
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const GIT_HASH: Option<&str> = option_env!("GIT_HASH");
@@ -13,5 +15,13 @@ pub const MAX_NETWORK_CONCURRENCY: usize = 8;
 pub const FILE_READ_BUFFER_SIZE: usize = 4 * 1024 * 1024;
 /// Threshold to begin chunking file during read, 16 KiB
 pub const FILE_READ_CHUNK_THRESHOLD: usize = 16 * 1024;
+/// Default number of retries for a failed download
+pub const DOWNLOAD_RETRIES: u32 = 3;
+/// Upper bound on the number of retries for a failed download
+pub const MAX_DOWNLOAD_RETRIES: u32 = 16;
+/// Delay before the first download retry, doubled on each subsequent attempt
+pub const DOWNLOAD_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Upper bound on the delay between download retries
+pub const MAX_DOWNLOAD_RETRY_DELAY: Duration = Duration::from_secs(30);
 /// DB batch size
 pub const DB_BATCH_SIZE: usize = 1000;
@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Package holds & pins
+//!
+//! A held package is kept at its installed version by `install` and `sync`.
+//! A hold may instead pin the package to a repository and / or version
+//! constraint, in which case it can still change but only to candidates
+//! matching the pin.
+
+use std::{collections::BTreeMap, fmt};
+
+use config::Config;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dependency::{Constraint, ParseError},
+    repository,
+};
+
+/// A hold on a single package
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hold {
+    /// Only select candidates from this repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<repository::Id>,
+    /// Only select candidates satisfying this constraint, i.e. `>= 6.8` or `release = 12`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl Hold {
+    /// Returns true if this hold pins the package rather than keeping it as installed
+    pub fn is_pin(&self) -> bool {
+        self.repository.is_some() || self.version.is_some()
+    }
+
+    /// The version constraint of this pin, if any
+    pub fn constraint(&self) -> Result<Option<Constraint>, ParseError> {
+        self.version.as_deref().map(parse_version).transpose()
+    }
+}
+
+impl fmt::Display for Hold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.repository, &self.version) {
+            (None, None) => write!(f, "held"),
+            (Some(repository), None) => write!(f, "pinned to {repository}"),
+            (None, Some(version)) => write!(f, "pinned to {version}"),
+            (Some(repository), Some(version)) => write!(f, "pinned to {repository}, {version}"),
+        }
+    }
+}
+
+/// Parse a version pin, where a bare version such as `6.8` pins that exact version
+pub fn parse_version(version: &str) -> Result<Constraint, ParseError> {
+    if version.contains(['<', '>', '=', '!']) {
+        version.parse()
+    } else {
+        format!("= {version}").parse()
+    }
+}
+
+/// A map of holds by package name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Map(BTreeMap<String, Hold>);
+
+impl Map {
+    pub fn with(items: impl IntoIterator<Item = (String, Hold)>) -> Self {
+        Self(items.into_iter().collect())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Hold> {
+        self.0.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Hold)> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn merge(self, other: Self) -> Self {
+        Self(self.0.into_iter().chain(other.0).collect())
+    }
+}
+
+impl IntoIterator for Map {
+    type Item = (String, Hold);
+    type IntoIter = std::collections::btree_map::IntoIter<String, Hold>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl Config for Map {
+    fn domain() -> String {
+        "hold".into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn version_pins() {
+        let exact = parse_version("6.8").unwrap();
+        assert!(exact.allows("6.8", 1));
+        assert!(!exact.allows("6.9", 1));
+
+        let range = parse_version("< 7").unwrap();
+        assert!(range.allows("6.9", 1));
+        assert!(!range.allows("7.0", 1));
+
+        let release = parse_version("release >= 4").unwrap();
+        assert!(release.allows("1.0", 4));
+        assert!(!release.allows("1.0", 3));
+
+        assert!(parse_version(">=").is_err());
+    }
+}
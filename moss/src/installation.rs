@@ -5,15 +5,21 @@
 //! Encapsulation of a target installation filesystem
 
 use std::{
-    fs,
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    os::fd::AsRawFd,
     path::{Path, PathBuf},
 };
 
 use log::{trace, warn};
-use nix::unistd::{access, AccessFlags, Uid};
+use nix::{
+    errno::Errno,
+    fcntl::{flock, FlockArg},
+    unistd::{access, getpid, AccessFlags, Uid},
+};
 use thiserror::Error;
 
-use crate::state;
+use crate::{environment, state};
 
 /// System mutability - do we have readwrite?
 #[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
@@ -40,6 +46,18 @@ pub struct Installation {
     /// Custom cache directory location,
     /// otherwise derived from root
     pub cache_dir: Option<PathBuf>,
+
+    /// Wait for a contended [`Lock`] instead of failing
+    lock_wait: bool,
+
+    /// Take the [`Lock`] exclusively, for operations which mutate the installation
+    exclusive_lock: bool,
+
+    /// Abort transactions when a trigger fails, instead of recording a warning
+    pub fail_on_trigger_error: bool,
+
+    /// Number of times a failed download is retried
+    pub download_retries: u32,
 }
 
 impl Installation {
@@ -69,6 +87,10 @@ impl Installation {
         // TODO: Should we instead fail if root doesn't exist?
         ensure_dirs_exist(&root);
 
+        if root.join(".moss").join("journal").exists() {
+            warn!("Found an interrupted transaction, run `moss repair` to recover");
+        }
+
         // Root? Always RW. Otherwise, check access for W
         let mutability = if Uid::effective().is_root() || access(&root, AccessFlags::W_OK).is_ok() {
             Mutability::ReadWrite
@@ -84,6 +106,10 @@ impl Installation {
             mutability,
             active_state,
             cache_dir: None,
+            lock_wait: false,
+            exclusive_lock: false,
+            fail_on_trigger_error: false,
+            download_retries: environment::DOWNLOAD_RETRIES,
         })
     }
 
@@ -104,6 +130,86 @@ impl Installation {
         })
     }
 
+    /// Construct an Installation which waits for other processes to release
+    /// the installation [`Lock`] instead of failing
+    pub fn with_lock_wait(self, wait: bool) -> Self {
+        Self {
+            lock_wait: wait,
+            ..self
+        }
+    }
+
+    /// Construct an Installation whose [`Lock`] is taken exclusively, which is
+    /// required before mutating it
+    pub fn with_exclusive_lock(self, exclusive: bool) -> Self {
+        Self {
+            exclusive_lock: exclusive,
+            ..self
+        }
+    }
+
+    /// Construct an Installation which aborts transactions when a trigger fails
+    pub fn with_fail_on_trigger_error(self, fail: bool) -> Self {
+        Self {
+            fail_on_trigger_error: fail,
+            ..self
+        }
+    }
+
+    /// Construct an Installation which retries failed downloads `retries` times
+    pub fn with_download_retries(self, retries: u32) -> Self {
+        Self {
+            download_retries: retries,
+            ..self
+        }
+    }
+
+    /// Take an advisory [`Lock`] on the installation, exclusively if configured
+    /// with [`Installation::with_exclusive_lock`] and otherwise shared
+    ///
+    /// Locks are never upgraded, since releasing a shared lock for an exclusive
+    /// one would let another process mutate the installation in between
+    ///
+    /// If we lack write access and no lock file exists yet, nothing can mutate
+    /// the installation and an inert lock is returned.
+    pub fn lock(&self) -> Result<Lock, Error> {
+        let path = self.moss_path("lock");
+
+        let file = match File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+        {
+            Ok(file) => Some(file),
+            Err(_) if self.read_only() => File::open(&path).ok(),
+            Err(error) => return Err(Error::Lock(error)),
+        };
+
+        let lock = Lock {
+            file,
+            exclusive: self.exclusive_lock,
+            wait: self.lock_wait,
+        };
+
+        lock.acquire()?;
+
+        Ok(lock)
+    }
+
+    /// Re-read the currently active system state Id, since it may have been
+    /// changed by another process before we took the [`Lock`]
+    pub fn refresh_active_state(&mut self) {
+        self.active_state = self.read_active_state();
+    }
+
+    /// Read the currently active system state Id from disk, which differs from
+    /// [`Installation::active_state`] once a transaction has promoted its new state
+    pub fn read_active_state(&self) -> Option<state::Id> {
+        read_state_id(&self.root)
+    }
+
     /// Return true if we lack write access
     pub fn read_only(&self) -> bool {
         matches!(self.mutability, Mutability::ReadOnly)
@@ -139,6 +245,16 @@ impl Installation {
         self.moss_path("repo").join(path)
     }
 
+    /// Build a log path relative to the moss root
+    pub fn log_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.moss_path("log").join(path)
+    }
+
+    /// Return the path of the transaction journal
+    pub fn journal_path(&self) -> PathBuf {
+        self.moss_path("journal")
+    }
+
     /// Build a path relative to the moss system roots tree
     pub fn root_path(&self, path: impl AsRef<Path>) -> PathBuf {
         self.moss_path("root").join(path)
@@ -165,6 +281,72 @@ impl Installation {
     }
 }
 
+/// Advisory lock on an [`Installation`], preventing concurrent
+/// transactions. Released on drop.
+#[derive(Debug)]
+pub struct Lock {
+    file: Option<File>,
+    exclusive: bool,
+    wait: bool,
+}
+
+impl Lock {
+    /// Whether the lock is held exclusively, allowing the installation to be mutated
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    fn acquire(&self) -> Result<(), Error> {
+        let Some(mut file) = self.file.as_ref() else {
+            return Ok(());
+        };
+
+        let arg = match (self.exclusive, self.wait) {
+            (false, false) => FlockArg::LockSharedNonblock,
+            (false, true) => FlockArg::LockShared,
+            (true, false) => FlockArg::LockExclusiveNonblock,
+            (true, true) => FlockArg::LockExclusive,
+        };
+
+        match flock(file.as_raw_fd(), arg) {
+            Ok(()) => {}
+            Err(Errno::EWOULDBLOCK) => return Err(self.holder().map_or(Error::Locked, Error::LockedBy)),
+            Err(errno) => return Err(Error::Lock(errno.into())),
+        }
+
+        // Let contending processes know who holds the lock
+        if self.exclusive {
+            let _ = file.set_len(0);
+            let _ = file.rewind();
+            let _ = write!(file, "{}", getpid());
+        }
+
+        Ok(())
+    }
+
+    /// Pid of the exclusive holder, if any
+    fn holder(&self) -> Option<i32> {
+        let mut file = self.file.as_ref()?;
+        let mut contents = String::new();
+
+        file.rewind().ok()?;
+        file.read_to_string(&mut contents).ok()?;
+
+        contents.trim().parse().ok()
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // Clear our pid before the lock is released on close
+        if let Some(file) = &self.file {
+            if self.exclusive {
+                let _ = file.set_len(0);
+            }
+        }
+    }
+}
+
 /// In older versions of moss, the `/usr` entry was a symlink
 /// to an active state. In newer versions, the state is recorded
 /// within the installation tree. (`/usr/.stateID`)
@@ -232,4 +414,43 @@ pub enum Error {
     RootInvalid,
     #[error("Cache dir is invalid")]
     CacheInvalid,
+    #[error("installation is locked by pid {0}, use --wait to wait for it")]
+    LockedBy(i32),
+    #[error("installation is locked by another process, use --wait to wait for it")]
+    Locked,
+    #[error("lock")]
+    Lock(#[source] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lock_contention() {
+        let root = std::env::temp_dir().join(format!("moss-lock-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let installation = Installation::open(&root).unwrap();
+        let exclusive = installation.clone().with_exclusive_lock(true);
+
+        // Readers share
+        let reader = installation.lock().unwrap();
+        let other = installation.lock().unwrap();
+        assert!(!reader.is_exclusive());
+        assert!(matches!(exclusive.lock(), Err(Error::Locked)));
+        drop((reader, other));
+
+        // Writers don't
+        let writer = exclusive.lock().unwrap();
+        assert!(writer.is_exclusive());
+        let pid = getpid().as_raw();
+        assert!(matches!(installation.lock(), Err(Error::LockedBy(p)) if p == pid));
+        assert!(matches!(exclusive.lock(), Err(Error::LockedBy(p)) if p == pid));
+        drop(writer);
+
+        installation.lock().unwrap();
+        exclusive.lock().unwrap();
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
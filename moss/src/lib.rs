@@ -14,6 +14,7 @@ pub mod client;
 pub mod db;
 pub mod dependency;
 pub mod environment;
+pub mod hold;
 pub mod installation;
 pub mod package;
 pub mod registry;
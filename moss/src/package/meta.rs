@@ -5,6 +5,7 @@
 use std::collections::BTreeSet;
 
 use derive_more::{AsRef, Display, From, Into};
+use serde::Serialize;
 use stone::payload;
 use thiserror::Error;
 
@@ -15,7 +16,8 @@ use crate::{dependency, Dependency, Provider};
 pub struct Id(pub(super) String);
 
 /// The name of a [`super::Package`]
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, AsRef, From, Into, Display)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, AsRef, From, Into, Display, Serialize)]
+#[serde(transparent)]
 pub struct Name(String);
 
 impl Name {
@@ -25,13 +27,15 @@ impl Name {
 }
 
 /// The metadata of a [`super::Package`]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Meta {
     /// Package name
     pub name: Name,
     /// Human readable version identifier
+    #[serde(rename = "version")]
     pub version_identifier: String,
     /// Package release as set in stone.yml
+    #[serde(rename = "release")]
     pub source_release: u64,
     /// Build machinery specific build release
     pub build_release: u64,
@@ -51,12 +55,30 @@ pub struct Meta {
     pub dependencies: BTreeSet<Dependency>,
     /// All providers, including name()
     pub providers: BTreeSet<Provider>,
+    /// Providers this package can't be installed alongside
+    pub conflicts: BTreeSet<Provider>,
     /// If relevant: uri to fetch from
     pub uri: Option<String>,
     /// If relevant: hash for the download
     pub hash: Option<String>,
     /// How big is this package in the repo..?
     pub download_size: Option<u64>,
+    /// If relevant: deltas to this package from previous releases
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deltas: Vec<Delta>,
+}
+
+/// A delta package, containing only the assets missing from a previous release
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Delta {
+    /// Hash of the package this delta applies to
+    pub from: String,
+    /// Uri to fetch from
+    pub uri: String,
+    /// Hash for the download
+    pub hash: String,
+    /// Size of the delta in the repo
+    pub size: u64,
 }
 
 impl Meta {
@@ -81,13 +103,17 @@ impl Meta {
         let dependencies = payload.iter().filter_map(meta_dependency).collect();
         let providers = payload
             .iter()
-            .filter_map(meta_provider)
+            .filter_map(|meta| meta_provider(meta, payload::meta::Tag::Provides))
             // Add package name as provider
             .chain(Some(Provider {
                 kind: dependency::Kind::PackageName,
                 name: name.clone(),
             }))
             .collect();
+        let conflicts = payload
+            .iter()
+            .filter_map(|meta| meta_provider(meta, payload::meta::Tag::Conflicts))
+            .collect();
 
         Ok(Meta {
             name: Name::from(name),
@@ -102,9 +128,12 @@ impl Meta {
             licenses,
             dependencies,
             providers,
+            conflicts,
             uri,
             hash,
             download_size,
+            // Published separately from the index, see [`Delta::from_stone_payload`]
+            deltas: vec![],
         })
     }
 
@@ -134,7 +163,7 @@ impl Meta {
         .chain(
             self.dependencies
                 .into_iter()
-                .map(|dep| (Tag::Depends, Kind::Dependency(dep.kind.into(), dep.name))),
+                .map(|dep| (Tag::Depends, Kind::Dependency(dep.kind.into(), dep.payload_name()))),
         )
         .chain(
             self.providers
@@ -143,6 +172,11 @@ impl Meta {
                 .filter(|provider| provider.kind != dependency::Kind::PackageName)
                 .map(|provider| (Tag::Provides, Kind::Provider(provider.kind.into(), provider.name))),
         )
+        .chain(
+            self.conflicts
+                .into_iter()
+                .map(|conflict| (Tag::Conflicts, Kind::Provider(conflict.kind.into(), conflict.name))),
+        )
         .map(|(tag, kind)| payload::Meta { tag, kind })
         .collect()
     }
@@ -156,6 +190,38 @@ impl Meta {
     }
 }
 
+impl Delta {
+    /// Read a delta along with the hash of the package it produces
+    ///
+    /// Deltas are published in a `stone.deltas` file alongside the repository
+    /// index rather than in it, as older clients reject unknown index records
+    pub fn from_stone_payload(payload: &[payload::Meta]) -> Result<(String, Self), MissingMetaFieldError> {
+        let target = find_meta_string(payload, payload::meta::Tag::PackageHash)?;
+        let from = find_meta_string(payload, payload::meta::Tag::DeltaFrom)?;
+        let uri = find_meta_string(payload, payload::meta::Tag::DeltaURI)?;
+        let hash = find_meta_string(payload, payload::meta::Tag::DeltaHash)?;
+        let size = find_meta_u64(payload, payload::meta::Tag::DeltaSize)?;
+
+        Ok((target, Self { from, uri, hash, size }))
+    }
+
+    /// Records of this delta to the package with hash `target`
+    pub fn to_stone_payload(self, target: String) -> Vec<payload::Meta> {
+        use payload::meta::{Kind, Tag};
+
+        vec![
+            (Tag::PackageHash, Kind::String(target)),
+            (Tag::DeltaFrom, Kind::String(self.from)),
+            (Tag::DeltaURI, Kind::String(self.uri)),
+            (Tag::DeltaHash, Kind::String(self.hash)),
+            (Tag::DeltaSize, Kind::Uint64(self.size)),
+        ]
+        .into_iter()
+        .map(|(tag, kind)| payload::Meta { tag, kind })
+        .collect()
+    }
+}
+
 fn find_meta_string(meta: &[payload::Meta], tag: payload::meta::Tag) -> Result<String, MissingMetaFieldError> {
     meta.iter()
         .find_map(|meta| meta_string(meta, tag))
@@ -194,27 +260,87 @@ fn meta_string(meta: &payload::Meta, tag: payload::meta::Tag) -> Option<String>
 }
 
 fn meta_dependency(meta: &payload::Meta) -> Option<Dependency> {
-    if let payload::meta::Kind::Dependency(kind, name) = meta.kind.clone() {
-        Some(Dependency {
-            kind: dependency::Kind::from(kind),
-            name,
-        })
+    if let payload::meta::Kind::Dependency(kind, name) = &meta.kind {
+        Some(Dependency::from_payload(dependency::Kind::from(*kind), name))
     } else {
         None
     }
 }
 
-fn meta_provider(meta: &payload::Meta) -> Option<Provider> {
-    if let payload::meta::Kind::Provider(kind, name) = meta.kind.clone() {
-        Some(Provider {
-            kind: dependency::Kind::from(kind),
-            name,
-        })
-    } else {
-        None
+fn meta_provider(meta: &payload::Meta, tag: payload::meta::Tag) -> Option<Provider> {
+    match (meta.tag, &meta.kind) {
+        (meta_tag, payload::meta::Kind::Provider(kind, name)) if meta_tag == tag => Some(Provider {
+            kind: dependency::Kind::from(*kind),
+            name: name.clone(),
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+impl Meta {
+    /// Metadata for tests, providing only its name
+    pub fn test(name: &str, version: &str) -> Self {
+        Self {
+            name: Name::from(name.to_string()),
+            version_identifier: version.into(),
+            source_release: 1,
+            build_release: 1,
+            architecture: Default::default(),
+            summary: Default::default(),
+            description: Default::default(),
+            source_id: Default::default(),
+            homepage: Default::default(),
+            licenses: Default::default(),
+            dependencies: Default::default(),
+            providers: [Provider::from_name(name).unwrap()].into_iter().collect(),
+            conflicts: Default::default(),
+            uri: Default::default(),
+            hash: Default::default(),
+            download_size: Default::default(),
+            deltas: Default::default(),
+        }
     }
 }
 
 #[derive(Debug, Error)]
 #[error("Missing metadata field: {0:?}")]
 pub struct MissingMetaFieldError(pub payload::meta::Tag);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deltas_roundtrip() {
+        let bytes = include_bytes!("../../../test/bash-completion-2.11-1-1-x86_64.stone");
+        let mut stone = stone::read_bytes(bytes).unwrap();
+        let payloads = stone.payloads().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        let meta_payload = payloads.iter().find_map(stone::read::PayloadKind::meta).unwrap();
+
+        let mut meta = Meta::from_stone_payload(&meta_payload.body).unwrap();
+        assert!(meta.deltas.is_empty());
+
+        let delta = Delta {
+            from: "aaa".into(),
+            uri: "a.delta.stone".into(),
+            hash: "111".into(),
+            size: 10,
+        };
+        meta.deltas = vec![delta.clone()];
+
+        // Index records never carry deltas, so older clients can read them
+        let payload = meta.clone().to_stone_payload();
+        assert!(payload
+            .iter()
+            .all(|record| !matches!(record.tag, payload::meta::Tag::DeltaFrom | payload::meta::Tag::DeltaURI)));
+        assert!(Meta::from_stone_payload(&payload).unwrap().deltas.is_empty());
+
+        let mut payload = delta.clone().to_stone_payload("ccc".into());
+        assert_eq!(Delta::from_stone_payload(&payload).unwrap(), ("ccc".into(), delta));
+
+        // Incomplete deltas can't be fetched
+        payload.retain(|record| record.tag != payload::meta::Tag::DeltaHash);
+        assert!(Delta::from_stone_payload(&payload).is_err());
+    }
+}
@@ -4,6 +4,9 @@
 
 use derive_more::{AsRef, Display, From, Into};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::Provider;
 
 pub use self::meta::{Meta, MissingMetaFieldError, Name};
 
@@ -11,8 +14,11 @@ pub mod meta;
 pub mod render;
 
 /// Unique ID of a [`Package`]
-#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, From, Into, AsRef, Display)]
+#[derive(
+    Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, From, Into, AsRef, Display, Serialize, Deserialize,
+)]
 #[as_ref(forward)]
+#[serde(transparent)]
 pub struct Id(String);
 
 impl From<Id> for meta::Id {
@@ -27,13 +33,50 @@ impl From<meta::Id> for Id {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Package {
     pub id: Id,
     pub meta: Meta,
     pub flags: Flags,
 }
 
+impl Package {
+    /// Returns the provider causing this package to conflict with `other`, if any.
+    /// Conflicts apply in both directions, so either package may declare it.
+    pub fn conflict_with<'a>(&'a self, other: &'a Package) -> Option<&'a Provider> {
+        let declared = |package: &'a Package, other: &Package| {
+            package
+                .meta
+                .conflicts
+                .iter()
+                .find(|conflict| other.meta.providers.contains(conflict))
+        };
+
+        if self.id == other.id {
+            return None;
+        }
+
+        declared(self, other).or_else(|| declared(other, self))
+    }
+
+    /// Available package `name-version` for tests, of the given release & with dependencies
+    #[cfg(test)]
+    pub fn test(name: &str, version: &str, release: u64, dependencies: &[&str]) -> Self {
+        Self {
+            id: Id(format!("{name}-{version}")),
+            meta: Meta {
+                source_release: release,
+                dependencies: dependencies
+                    .iter()
+                    .map(|d| crate::Dependency::from_name(d).unwrap())
+                    .collect(),
+                ..Meta::test(name, version)
+            },
+            flags: Flags::new().with_available(),
+        }
+    }
+}
+
 impl PartialOrd for Package {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -50,7 +93,46 @@ impl Ord for Package {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// The identity & version of a [`Package`], enough to choose between the
+/// providers of a dependency without loading their full metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub id: Id,
+    pub name: Name,
+    pub version_identifier: String,
+    pub source_release: u64,
+    pub build_release: u64,
+}
+
+impl From<&Package> for Candidate {
+    fn from(package: &Package) -> Self {
+        Self {
+            id: package.id.clone(),
+            name: package.meta.name.clone(),
+            version_identifier: package.meta.version_identifier.clone(),
+            source_release: package.meta.source_release,
+            build_release: package.meta.build_release,
+        }
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Same order as [`Package`]
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.source_release
+            .cmp(&other.source_release)
+            .reverse()
+            .then_with(|| self.build_release.cmp(&other.build_release).reverse())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
 pub struct Flags {
     /// Package is available for installation.
     pub available: bool,
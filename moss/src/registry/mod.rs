@@ -8,12 +8,13 @@
 use itertools::Itertools;
 
 use crate::package::{self, Package};
-use crate::Provider;
+use crate::{repository, Provider};
 
 pub use self::plugin::Plugin;
 pub use self::transaction::Transaction;
 
 pub mod plugin;
+mod solver;
 pub mod transaction;
 
 /// A registry is composed of multiple "query plugins" that
@@ -58,6 +59,15 @@ impl Registry {
         self.query(move |plugin| plugin.query_provider_id_only(provider, flags))
     }
 
+    /// Optimized version of `by_provider` returning [`package::Candidate`] only
+    pub fn candidates_by_provider<'a>(
+        &'a self,
+        provider: &'a Provider,
+        flags: package::Flags,
+    ) -> impl Iterator<Item = package::Candidate> + 'a {
+        self.query(move |plugin| plugin.query_provider_candidates(provider, flags))
+    }
+
     /// Return a sorted stream of [`Package`] by name
     pub fn by_name<'a>(
         &'a self,
@@ -93,6 +103,13 @@ impl Registry {
         self.list(flags.with_available())
     }
 
+    /// Returns true if the package `id` is available from `repository`
+    pub fn in_repository(&self, id: &package::Id, repository: &repository::Id) -> bool {
+        self.plugins
+            .iter()
+            .any(|plugin| matches!(plugin, Plugin::Repository(repo) if repo.id() == repository && repo.package(id).is_some()))
+    }
+
     /// Return a new transaction for this registry
     pub fn transaction(&self) -> Result<Transaction<'_>, transaction::Error> {
         transaction::new(self)
@@ -120,21 +137,8 @@ mod test {
         let package = |id: &str, release| Package {
             id: package::Id::from(id.to_string()),
             meta: package::Meta {
-                name: package::Name::from(id.to_string()),
-                version_identifier: Default::default(),
                 source_release: release,
-                build_release: Default::default(),
-                architecture: Default::default(),
-                summary: Default::default(),
-                description: Default::default(),
-                source_id: Default::default(),
-                homepage: Default::default(),
-                licenses: Default::default(),
-                dependencies: Default::default(),
-                providers: Default::default(),
-                uri: Default::default(),
-                hash: Default::default(),
-                download_size: Default::default(),
+                ..package::Meta::test(id, "1.0")
             },
             flags: package::Flags::default(),
         };
@@ -173,23 +177,7 @@ mod test {
 
         let package = |id: &str, flags| Package {
             id: package::Id::from(id.to_string()),
-            meta: package::Meta {
-                name: package::Name::from(id.to_string()),
-                version_identifier: Default::default(),
-                source_release: Default::default(),
-                build_release: Default::default(),
-                architecture: Default::default(),
-                summary: Default::default(),
-                description: Default::default(),
-                source_id: Default::default(),
-                homepage: Default::default(),
-                licenses: Default::default(),
-                dependencies: Default::default(),
-                providers: Default::default(),
-                uri: Default::default(),
-                hash: Default::default(),
-                download_size: Default::default(),
-            },
+            meta: package::Meta::test(id, "1.0"),
             flags,
         };
 
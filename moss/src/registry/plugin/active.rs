@@ -114,6 +114,29 @@ impl Active {
         }
     }
 
+    pub fn query_provider_candidates(&self, provider: &Provider, flags: package::Flags) -> Vec<package::Candidate> {
+        if flags.installed || flags == package::Flags::default() {
+            // TODO: Error handling
+            let candidates = match self.db.provider_candidates(provider) {
+                Ok(candidates) => candidates,
+                Err(error) => {
+                    warn!("failed to query repository packages: {error}");
+                    return vec![];
+                }
+            };
+
+            candidates
+                .into_iter()
+                .filter(|candidate| {
+                    self.installed_package(candidate.id.clone())
+                        .is_some_and(|(_, package_flags)| !flags.explicit || package_flags.explicit)
+                })
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+
     pub fn priority(&self) -> u64 {
         u64::MAX
     }
@@ -4,7 +4,10 @@
 
 use std::fs::File;
 use std::io;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use crate::package::{self, meta, Meta, MissingMetaFieldError, Package};
 use crate::Provider;
@@ -53,6 +56,13 @@ impl Cobble {
         self.packages.get(&meta_id).map(|state| state.package(id.clone()))
     }
 
+    /// Return the on-disk path of a package that was added to the cobble set
+    pub fn path(&self, id: &package::Id) -> Option<&Path> {
+        let meta_id = meta::Id::from(id.clone());
+
+        self.packages.get(&meta_id).map(|state| state.path.as_path())
+    }
+
     fn query(&self, flags: package::Flags, filter: impl Fn(&Meta) -> bool) -> Vec<Package> {
         if flags.available {
             self.packages
@@ -99,7 +109,6 @@ impl State {
         Package {
             id,
             meta: self.meta.clone(),
-            // TODO: Is this correct flag?
             flags: package::Flags::new().with_available(),
         }
     }
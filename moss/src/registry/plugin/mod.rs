@@ -20,7 +20,7 @@ pub use self::repository::Repository;
 pub use self::test::Test;
 
 mod active;
-mod cobble;
+pub mod cobble;
 mod repository;
 
 /// A [`Registry`] plugin that enables querying [`Package`] information.
@@ -104,6 +104,30 @@ impl Plugin {
         })
     }
 
+    /// Returns the [`package::Candidate`]s with matching `provider` and `flags`
+    pub fn query_provider_candidates(
+        &self,
+        provider: &Provider,
+        flags: package::Flags,
+    ) -> package::Sorted<Vec<package::Candidate>> {
+        package::Sorted::new(match self {
+            Plugin::Active(plugin) => plugin.query_provider_candidates(provider, flags),
+            Plugin::Cobble(plugin) => plugin
+                .query_provider(provider, flags)
+                .iter()
+                .map(package::Candidate::from)
+                .collect(),
+            Plugin::Repository(plugin) => plugin.query_provider_candidates(provider, flags),
+
+            #[cfg(test)]
+            Plugin::Test(plugin) => plugin
+                .query_provider(provider, flags)
+                .iter()
+                .map(package::Candidate::from)
+                .collect(),
+        })
+    }
+
     /// Returns a list of packages with matching `package_name` and `flags`
     pub fn query_name(&self, package_name: &package::Name, flags: package::Flags) -> package::Sorted<Vec<Package>> {
         package::Sorted::new(match self {
@@ -20,6 +20,10 @@ impl Repository {
         Self { active }
     }
 
+    pub fn id(&self) -> &repository::Id {
+        &self.active.id
+    }
+
     pub fn priority(&self) -> u64 {
         self.active.repository.priority.into()
     }
@@ -27,16 +31,32 @@ impl Repository {
     pub fn package(&self, id: &package::Id) -> Option<Package> {
         let result = self.active.db.get(id);
 
+        let resolve = |relative: &str| {
+            self.active
+                .repository
+                .uri
+                .join(relative)
+                .ok()
+                .map(|url| url.to_string())
+        };
+
         match result {
             Ok(meta) => Some(Package {
                 id: id.clone(),
                 meta: package::Meta {
                     // TODO: Is there a more type-safe way to do this vs mutation? Can
                     // a new type help here?
-                    uri: meta
-                        .uri
-                        .and_then(|relative| self.active.repository.uri.join(&relative).ok())
-                        .map(|url| url.to_string()),
+                    uri: meta.uri.as_deref().and_then(resolve),
+                    deltas: meta
+                        .deltas
+                        .into_iter()
+                        .filter_map(|delta| {
+                            Some(package::meta::Delta {
+                                uri: resolve(&delta.uri)?,
+                                ..delta
+                            })
+                        })
+                        .collect(),
                     ..meta
                 },
                 flags: package::Flags::new().with_available(),
@@ -104,6 +124,21 @@ impl Repository {
             vec![]
         }
     }
+
+    pub fn query_provider_candidates(&self, provider: &Provider, flags: package::Flags) -> Vec<package::Candidate> {
+        if flags.available || flags == package::Flags::default() {
+            // TODO: Error handling
+            match self.active.db.provider_candidates(provider) {
+                Ok(candidates) => candidates,
+                Err(error) => {
+                    warn!("failed to query repository packages: {error}");
+                    vec![]
+                }
+            }
+        } else {
+            vec![]
+        }
+    }
 }
 
 impl PartialEq for Repository {
@@ -0,0 +1,1114 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Dependency resolution for [`Transaction`]s
+//!
+//! Resolution is a satisfiability problem over candidate packages, each one a
+//! variable that holds when the package is selected. Clauses constraining them
+//! are generated as packages get selected:
+//!
+//! - a selected package requires one of the candidates of each dependency
+//! - only one package of each name can be selected
+//! - conflicting packages can't be selected together
+//! - packages excluded by a hold can't be selected
+//!
+//! Dependencies are decided one candidate at a time in order of preference:
+//! packages already selected, then installed packages, then available packages
+//! by repository priority. When a decision leads to a conflict, the clauses
+//! behind it are combined into a new clause ruling out the same failure, and
+//! the solver jumps back to the most recent decision it implicates. If no
+//! solution exists, the clauses behind the final conflict are rendered as a
+//! [`Derivation`] explaining why.
+//!
+//! [`Transaction`]: super::Transaction
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    fmt,
+    rc::Rc,
+};
+
+use super::transaction::{Error, Lookup};
+use crate::{package, Dependency, Package, Provider, Registry};
+
+/// A selected package and the packages satisfying each of its dependencies
+pub(super) type Resolved = (package::Id, Vec<package::Id>);
+
+/// Index of a candidate package, which holds when it's selected
+type Var = usize;
+
+/// A [`Var`] or its negation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lit {
+    var: Var,
+    selected: bool,
+}
+
+impl Lit {
+    fn selected(var: Var) -> Self {
+        Self { var, selected: true }
+    }
+
+    fn rejected(var: Var) -> Self {
+        Self { var, selected: false }
+    }
+
+    fn negate(self) -> Self {
+        Self {
+            var: self.var,
+            selected: !self.selected,
+        }
+    }
+
+    /// Index into tables kept per literal
+    fn index(self) -> usize {
+        self.var * 2 + self.selected as usize
+    }
+}
+
+/// A disjunction of literals, at least one of which must hold
+#[derive(Debug)]
+struct Clause {
+    literals: Vec<Lit>,
+    origin: Origin,
+}
+
+/// Why a [`Clause`] holds
+#[derive(Debug)]
+enum Origin {
+    /// Requested packages must be selected
+    Requested,
+    /// Locked packages stay selected
+    Locked,
+    /// A selected `package` requires one of the candidates allowed by `dependency`
+    Dependency { package: Var, dependency: Dependency },
+    /// Only one of `package` & `other` can be selected as they share a name
+    Replaces { package: Var, other: Var },
+    /// `package` declares a conflict with `provider`, offered by `other`
+    Conflict {
+        package: Var,
+        other: Var,
+        provider: Provider,
+    },
+    /// Excluded by a hold on its name
+    Held,
+    /// The package couldn't be loaded from the registry
+    Missing,
+    /// Implied by the given clauses, learned from a conflict between them
+    Learned(Vec<usize>),
+}
+
+#[derive(Debug)]
+struct Variable {
+    candidate: package::Candidate,
+    /// The full package, loaded once selected
+    package: Option<Rc<Package>>,
+    /// Locked packages were already resolved together
+    locked: bool,
+    /// Whether the clauses brought by selecting the package were generated
+    expanded: bool,
+    value: Option<bool>,
+    /// Decision level of the assignment
+    level: usize,
+    /// Clause implying the assignment, decisions have none
+    reason: Option<usize>,
+}
+
+/// Resolves a consistent set of packages for a transaction
+pub(super) struct Solver<'a> {
+    registry: &'a Registry,
+    lookup: Lookup,
+    /// The only candidates allowed for some package names
+    restricted: &'a BTreeMap<package::Name, Vec<package::Id>>,
+
+    variables: Vec<Variable>,
+    ids: HashMap<package::Id, Var>,
+    names: BTreeMap<package::Name, Vec<Var>>,
+    /// Candidates per provider, in order of preference
+    candidates: HashMap<Provider, Vec<Var>>,
+    /// Requested & locked packages
+    pinned: Vec<Var>,
+
+    clauses: Vec<Clause>,
+    /// Clauses containing each literal
+    occurrences: Vec<Vec<usize>>,
+    /// Clauses yet to be checked against the assignment
+    unchecked: VecDeque<usize>,
+    /// Dependency clauses, the most recent decided first
+    pending: Vec<usize>,
+
+    /// Assignments, in order
+    trail: Vec<Lit>,
+    /// Length of the trail before each decision
+    decisions: Vec<usize>,
+    /// Number of assignments propagated so far
+    propagated: usize,
+}
+
+impl<'a> Solver<'a> {
+    pub fn new(
+        registry: &'a Registry,
+        lookup: Lookup,
+        restricted: &'a BTreeMap<package::Name, Vec<package::Id>>,
+    ) -> Self {
+        Self {
+            registry,
+            lookup,
+            restricted,
+            variables: vec![],
+            ids: HashMap::new(),
+            names: BTreeMap::new(),
+            candidates: HashMap::new(),
+            pinned: vec![],
+            clauses: vec![],
+            occurrences: vec![],
+            unchecked: VecDeque::new(),
+            pending: vec![],
+            trail: vec![],
+            decisions: vec![],
+            propagated: 0,
+        }
+    }
+
+    /// Select all `incoming` packages and their dependencies alongside the already
+    /// resolved `locked` packages, returning the newly selected packages
+    pub fn solve(mut self, locked: &[package::Id], incoming: &[package::Id]) -> Result<Vec<Resolved>, Error> {
+        for id in locked {
+            let package = self
+                .registry
+                .by_id(id)
+                .next()
+                .ok_or(Error::NoCandidate(id.to_string()))?;
+            let var = self.pin(package, true);
+            self.add(vec![Lit::selected(var)], Origin::Locked);
+        }
+
+        let mut requested = vec![];
+        for id in incoming {
+            if self.ids.get(id).is_some_and(|var| self.pinned.contains(var)) {
+                continue;
+            }
+
+            let package = self
+                .registry
+                .by_id(id)
+                .next()
+                .ok_or(Error::NoCandidate(id.to_string()))?;
+
+            let incompatibility = if self.is_held_back(&package.meta.name, &package.id) {
+                Some(Rejection::Held)
+            } else {
+                self.incompatibility(&package)
+            };
+            if let Some(rejection) = incompatibility {
+                return Err(Error::Unsolvable(Derivation {
+                    cause: Box::new(Cause::Requested {
+                        package: label(&package::Candidate::from(&package)),
+                        rejection,
+                    }),
+                }));
+            }
+
+            requested.push(self.pin(package, false));
+        }
+        // Required in reverse so requests are resolved in the order given
+        for var in requested.iter().rev() {
+            self.add(vec![Lit::selected(*var)], Origin::Requested);
+        }
+
+        self.search().map_err(Error::Unsolvable)?;
+
+        let requested_set = requested.iter().copied().collect::<HashSet<_>>();
+        let selected = requested.iter().copied().chain(
+            self.trail
+                .iter()
+                .filter(|lit| lit.selected && !self.variables[lit.var].locked && !requested_set.contains(&lit.var))
+                .map(|lit| lit.var),
+        );
+
+        Ok(selected
+            .map(|var| {
+                let variable = &self.variables[var];
+                let dependencies = variable
+                    .package
+                    .iter()
+                    .flat_map(|package| &package.meta.dependencies)
+                    .filter_map(|dependency| self.satisfied_by(dependency))
+                    .collect();
+
+                (variable.candidate.id.clone(), dependencies)
+            })
+            .collect())
+    }
+
+    /// Assign every candidate, learning from each conflict, until all clauses hold
+    fn search(&mut self) -> Result<(), Derivation> {
+        loop {
+            if let Err(conflict) = self.propagate() {
+                let level = self.clauses[conflict]
+                    .literals
+                    .iter()
+                    .map(|lit| self.variables[lit.var].level)
+                    .max()
+                    .unwrap_or_default();
+                if level == 0 {
+                    return Err(self.explain(conflict));
+                }
+
+                self.backtrack(level);
+                let (literals, sources) = self.analyze(conflict);
+
+                // Jump back to the latest decision the learned clause implicates,
+                // where it asserts the opposite of the decision that failed
+                let target = literals[1..]
+                    .iter()
+                    .map(|lit| self.variables[lit.var].level)
+                    .max()
+                    .unwrap_or_default();
+                self.backtrack(target);
+
+                let asserted = literals[0];
+                let clause = self.add(literals, Origin::Learned(sources));
+                self.assign(asserted, Some(clause));
+
+                continue;
+            }
+
+            match self.decide() {
+                Some(lit) => {
+                    self.decisions.push(self.trail.len());
+                    self.assign(lit, None);
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Assign everything implied by the clauses, returning the clause falsified if any
+    fn propagate(&mut self) -> Result<(), usize> {
+        loop {
+            if let Some(clause) = self.unchecked.pop_front() {
+                self.check(clause)?;
+            } else if let Some(&lit) = self.trail.get(self.propagated) {
+                self.propagated += 1;
+
+                if lit.selected {
+                    self.expand(lit.var);
+                }
+
+                let falsified = lit.negate().index();
+                for i in 0..self.occurrences[falsified].len() {
+                    self.check(self.occurrences[falsified][i])?;
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Assign the only open literal of `clause` if all others are false, or fail with
+    /// `clause` if they all are
+    fn check(&mut self, clause: usize) -> Result<(), usize> {
+        let mut open = None;
+
+        for &lit in &self.clauses[clause].literals {
+            match self.value(lit) {
+                Some(true) => return Ok(()),
+                Some(false) => {}
+                None if open.is_some() => return Ok(()),
+                None => open = Some(lit),
+            }
+        }
+
+        match open {
+            Some(lit) => {
+                self.assign(lit, Some(clause));
+                Ok(())
+            }
+            None => Err(clause),
+        }
+    }
+
+    /// Pick the preferred open candidate of the most recent unsatisfied dependency
+    fn decide(&self) -> Option<Lit> {
+        self.pending.iter().rev().find_map(|&clause| {
+            let literals = &self.clauses[clause].literals;
+
+            // Only dependencies of selected packages need satisfying
+            if self.value(literals[0]) != Some(false) || literals.iter().any(|lit| self.value(*lit) == Some(true)) {
+                return None;
+            }
+
+            literals[1..].iter().find(|lit| self.value(**lit).is_none()).copied()
+        })
+    }
+
+    /// Resolve the clauses behind `conflict` up to the first assignment at the current
+    /// decision level responsible for it, returning a clause asserting its negation
+    /// first along with the clauses it was learned from
+    fn analyze(&self, conflict: usize) -> (Vec<Lit>, Vec<usize>) {
+        let level = self.decisions.len();
+
+        let mut seen = HashSet::new();
+        let mut literals = vec![];
+        let mut sources = vec![conflict];
+        let mut open = 0;
+        let mut clause = conflict;
+        let mut index = self.trail.len();
+
+        loop {
+            for lit in &self.clauses[clause].literals {
+                if seen.insert(lit.var) {
+                    if self.variables[lit.var].level == level {
+                        open += 1;
+                    } else {
+                        literals.push(*lit);
+                    }
+                }
+            }
+
+            let assigned = loop {
+                index -= 1;
+                if seen.contains(&self.trail[index].var) {
+                    break self.trail[index];
+                }
+            };
+
+            open -= 1;
+            if open == 0 {
+                literals.insert(0, assigned.negate());
+                return (literals, sources);
+            }
+
+            clause = self.variables[assigned.var]
+                .reason
+                .expect("only decisions are unexplained");
+            sources.push(clause);
+        }
+    }
+
+    /// Undo all assignments made after decision `level`
+    fn backtrack(&mut self, level: usize) {
+        let Some(&start) = self.decisions.get(level) else {
+            return;
+        };
+
+        for lit in self.trail.drain(start..) {
+            let variable = &mut self.variables[lit.var];
+            variable.value = None;
+            variable.reason = None;
+        }
+        self.decisions.truncate(level);
+        self.propagated = self.propagated.min(start);
+    }
+
+    fn assign(&mut self, lit: Lit, reason: Option<usize>) {
+        let variable = &mut self.variables[lit.var];
+        variable.value = Some(lit.selected);
+        variable.level = self.decisions.len();
+        variable.reason = reason;
+
+        self.trail.push(lit);
+    }
+
+    fn value(&self, lit: Lit) -> Option<bool> {
+        self.variables[lit.var].value.map(|value| value == lit.selected)
+    }
+
+    /// Add a clause, to be checked on the next propagation
+    fn add(&mut self, literals: Vec<Lit>, origin: Origin) -> usize {
+        let clause = self.clauses.len();
+
+        for lit in &literals {
+            self.occurrences[lit.index()].push(clause);
+        }
+        self.clauses.push(Clause { literals, origin });
+        self.unchecked.push_back(clause);
+
+        clause
+    }
+
+    /// Generate the clauses brought by selecting `var`
+    fn expand(&mut self, var: Var) {
+        if self.variables[var].expanded {
+            return;
+        }
+        self.variables[var].expanded = true;
+
+        let Some(package) = self.load(var) else {
+            self.add(vec![Lit::rejected(var)], Origin::Missing);
+            return;
+        };
+
+        // Locked packages had their dependencies resolved already
+        if !self.variables[var].locked {
+            let mut clauses = vec![];
+
+            for dependency in &package.meta.dependencies {
+                let mut literals = vec![Lit::rejected(var)];
+                for candidate in self.candidates(&provider(dependency)) {
+                    if self.allows(dependency, candidate) {
+                        literals.push(Lit::selected(candidate));
+                    }
+                }
+
+                clauses.push(self.add(
+                    literals,
+                    Origin::Dependency {
+                        package: var,
+                        dependency: dependency.clone(),
+                    },
+                ));
+            }
+
+            // Queued in reverse so dependencies are decided in declared order
+            self.pending.extend(clauses.into_iter().rev());
+        }
+
+        // Installed packages were already accepted together
+        if matches!(self.lookup, Lookup::InstalledOnly) {
+            return;
+        }
+
+        for conflict in &package.meta.conflicts {
+            for other in self.candidates(conflict) {
+                if other != var && !(self.variables[var].locked && self.variables[other].locked) {
+                    self.add(
+                        vec![Lit::rejected(var), Lit::rejected(other)],
+                        Origin::Conflict {
+                            package: var,
+                            other,
+                            provider: conflict.clone(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Add a requested or `locked` package, returning its variable
+    fn pin(&mut self, package: Package, locked: bool) -> Var {
+        let var = self.variable(package::Candidate::from(&package), locked);
+
+        self.variables[var].package = Some(Rc::new(package));
+        self.pinned.push(var);
+
+        var
+    }
+
+    /// Returns the variable of `candidate`, adding it along with the clauses
+    /// restricting it if it's new
+    fn variable(&mut self, candidate: package::Candidate, locked: bool) -> Var {
+        if let Some(var) = self.ids.get(&candidate.id) {
+            return *var;
+        }
+
+        let var = self.variables.len();
+        self.ids.insert(candidate.id.clone(), var);
+        self.occurrences.extend([vec![], vec![]]);
+
+        if !locked && self.is_held_back(&candidate.name, &candidate.id) {
+            self.add(vec![Lit::rejected(var)], Origin::Held);
+        }
+
+        let others = self.names.get(&candidate.name).cloned().unwrap_or_default();
+        if matches!(self.lookup, Lookup::Global) {
+            for other in others {
+                if !(locked && self.variables[other].locked) {
+                    self.add(
+                        vec![Lit::rejected(other), Lit::rejected(var)],
+                        Origin::Replaces { package: var, other },
+                    );
+                }
+            }
+        }
+        self.names.entry(candidate.name.clone()).or_default().push(var);
+
+        self.variables.push(Variable {
+            candidate,
+            package: None,
+            locked,
+            expanded: false,
+            value: None,
+            level: 0,
+            reason: None,
+        });
+
+        var
+    }
+
+    /// Returns true if a restriction on `name` excludes the package `id`
+    fn is_held_back(&self, name: &package::Name, id: &package::Id) -> bool {
+        self.restricted.get(name).is_some_and(|allowed| !allowed.contains(id))
+    }
+
+    /// Returns why `package` can't be installed alongside the requested & locked packages, if so
+    fn incompatibility(&self, package: &Package) -> Option<Rejection> {
+        // Installed packages were already accepted together
+        if matches!(self.lookup, Lookup::InstalledOnly) {
+            return None;
+        }
+
+        let pinned = || {
+            self.pinned
+                .iter()
+                .filter_map(|var| self.variables[*var].package.as_deref())
+                .filter(|other| other.id != package.id)
+        };
+
+        pinned()
+            .find(|other| other.meta.name == package.meta.name)
+            .map(|other| Rejection::Replaces {
+                other: label(&package::Candidate::from(other)),
+            })
+            .or_else(|| {
+                pinned().find_map(|other| {
+                    Some(Rejection::Conflict {
+                        other: label(&package::Candidate::from(other)),
+                        provider: package.conflict_with(other)?.to_string(),
+                    })
+                })
+            })
+    }
+
+    /// Returns true if `dependency` is satisfied by the candidate `var`
+    fn allows(&self, dependency: &Dependency, var: Var) -> bool {
+        let candidate = &self.variables[var].candidate;
+        dependency.allows(&candidate.version_identifier, candidate.source_release)
+    }
+
+    /// Returns the selected package satisfying `dependency`, if any
+    fn satisfied_by(&self, dependency: &Dependency) -> Option<package::Id> {
+        self.candidates.get(&provider(dependency))?.iter().find_map(|&var| {
+            (self.value(Lit::selected(var)) == Some(true) && self.allows(dependency, var))
+                .then(|| self.variables[var].candidate.id.clone())
+        })
+    }
+
+    /// All candidates offering `provider` regardless of constraint, in order of preference
+    fn candidates(&mut self, provider: &Provider) -> Vec<Var> {
+        if let Some(vars) = self.candidates.get(provider) {
+            return vars.clone();
+        }
+
+        let installed = self
+            .registry
+            .candidates_by_provider(provider, package::Flags::new().with_installed());
+        let found = match self.lookup {
+            Lookup::InstalledOnly => installed.collect::<Vec<_>>(),
+            Lookup::Global => installed
+                .chain(
+                    self.registry
+                        .candidates_by_provider(provider, package::Flags::new().with_available()),
+                )
+                .collect(),
+        };
+
+        // Requested & locked packages are selected regardless
+        let mut vars = self
+            .pinned
+            .iter()
+            .copied()
+            .filter(|var| {
+                self.variables[*var]
+                    .package
+                    .as_ref()
+                    .is_some_and(|package| package.meta.providers.contains(provider))
+            })
+            .collect::<Vec<_>>();
+        for candidate in found {
+            let var = self.variable(candidate, false);
+            if !vars.contains(&var) {
+                vars.push(var);
+            }
+        }
+
+        self.candidates.insert(provider.clone(), vars.clone());
+        vars
+    }
+
+    /// Load the full package of `var`
+    fn load(&mut self, var: Var) -> Option<Rc<Package>> {
+        let variable = &mut self.variables[var];
+
+        if variable.package.is_none() {
+            variable.package = self.registry.by_id(&variable.candidate.id).next().map(Rc::new);
+        }
+
+        variable.package.clone()
+    }
+
+    /// Explain a `conflict` between clauses that hold regardless of any decision
+    fn explain(&self, conflict: usize) -> Derivation {
+        // Trace the assignments behind the conflict back to the clauses implying them
+        let mut involved = vec![];
+        let mut traced = HashSet::new();
+        let mut queue = vec![conflict];
+        while let Some(clause) = queue.pop() {
+            involved.push(clause);
+            for lit in &self.clauses[clause].literals {
+                if traced.insert(lit.var) {
+                    queue.extend(self.variables[lit.var].reason);
+                }
+            }
+        }
+
+        // Learned clauses stand in for the ones they were learned from
+        let mut core = BTreeSet::new();
+        let mut visited = HashSet::new();
+        while let Some(clause) = involved.pop() {
+            if !visited.insert(clause) {
+                continue;
+            }
+            match &self.clauses[clause].origin {
+                Origin::Learned(sources) => involved.extend(sources),
+                _ => {
+                    core.insert(clause);
+                }
+            }
+        }
+
+        // Start from a dependency of the requested packages where possible
+        let dependency_of = |var: Option<Var>| {
+            core.iter().copied().find(|clause| match &self.clauses[*clause].origin {
+                Origin::Dependency { package, .. } => var.is_none_or(|var| var == *package),
+                _ => false,
+            })
+        };
+        let root = self
+            .pinned
+            .iter()
+            .find_map(|var| dependency_of(Some(*var)))
+            .or_else(|| dependency_of(None));
+
+        let cause = root
+            .and_then(|clause| self.dependency_cause(clause, &core, &mut vec![]))
+            .or_else(|| {
+                core.iter()
+                    .flat_map(|clause| &self.clauses[*clause].literals)
+                    .filter(|lit| !lit.selected)
+                    .find_map(|lit| {
+                        Some(Cause::Requested {
+                            package: label(&self.variables[lit.var].candidate),
+                            rejection: self.rejection(lit.var, &core, &mut vec![])?,
+                        })
+                    })
+            })
+            .unwrap_or(Cause::Unsatisfiable);
+
+        Derivation { cause: Box::new(cause) }
+    }
+
+    /// Explain why none of the candidates of the dependency `clause` can be selected.
+    /// The packages whose dependencies are being explained are on `path`.
+    fn dependency_cause(&self, clause: usize, core: &BTreeSet<usize>, path: &mut Vec<Var>) -> Option<Cause> {
+        let Clause {
+            literals,
+            origin: Origin::Dependency { package, dependency },
+        } = &self.clauses[clause]
+        else {
+            return None;
+        };
+
+        path.push(*package);
+        let rejected = literals[1..]
+            .iter()
+            .filter_map(|lit| {
+                Some((
+                    label(&self.variables[lit.var].candidate),
+                    self.rejection(lit.var, core, path)?,
+                ))
+            })
+            .collect::<Vec<_>>();
+        path.pop();
+
+        let found = if rejected.is_empty() {
+            self.candidates
+                .get(&provider(dependency))
+                .into_iter()
+                .flatten()
+                .map(|var| label(&self.variables[*var].candidate))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        Some(Cause::Dependency {
+            package: label(&self.variables[*package].candidate),
+            dependency: dependency.to_string(),
+            found,
+            rejected,
+        })
+    }
+
+    /// Explain why `var` can't be selected, using the clauses of the `core`
+    fn rejection(&self, var: Var, core: &BTreeSet<usize>, path: &mut Vec<Var>) -> Option<Rejection> {
+        let rejecting = || {
+            core.iter()
+                .copied()
+                .filter(move |clause| self.clauses[*clause].literals.contains(&Lit::rejected(var)))
+        };
+        let other =
+            |package: Var, other: Var| label(&self.variables[if package == var { other } else { package }].candidate);
+
+        rejecting()
+            .find_map(|clause| match &self.clauses[clause].origin {
+                Origin::Held => Some(Rejection::Held),
+                Origin::Missing => Some(Rejection::Missing),
+                Origin::Replaces { package, other: o } => Some(Rejection::Replaces {
+                    other: other(*package, *o),
+                }),
+                Origin::Conflict {
+                    package,
+                    other: o,
+                    provider,
+                } => Some(Rejection::Conflict {
+                    other: other(*package, *o),
+                    provider: provider.to_string(),
+                }),
+                _ => None,
+            })
+            .or_else(|| {
+                if path.contains(&var) {
+                    return None;
+                }
+
+                let clause = rejecting().find(|clause| {
+                    matches!(self.clauses[*clause].origin, Origin::Dependency { package, .. } if package == var)
+                })?;
+
+                Some(Rejection::Requires(Box::new(
+                    self.dependency_cause(clause, core, path)?,
+                )))
+            })
+    }
+}
+
+/// Explains why no solution could be found
+#[derive(Debug, Clone)]
+pub struct Derivation {
+    cause: Box<Cause>,
+}
+
+#[derive(Debug, Clone)]
+enum Cause {
+    /// A requested package can't be installed
+    Requested { package: String, rejection: Rejection },
+    /// No candidate of a dependency can be installed
+    Dependency {
+        package: String,
+        dependency: String,
+        /// Candidates not satisfying the constraint, when none were tried
+        found: Vec<String>,
+        rejected: Vec<(String, Rejection)>,
+    },
+    /// The conflicting requirements couldn't be traced back to a package
+    Unsatisfiable,
+}
+
+/// Why a candidate was rejected
+#[derive(Debug, Clone)]
+enum Rejection {
+    /// Conflicts with a selected package
+    Conflict { other: String, provider: String },
+    /// Another version of the same package is selected
+    Replaces { other: String },
+    /// Excluded by a hold on its name
+    Held,
+    /// Couldn't be loaded from the registry
+    Missing,
+    /// Its own dependencies couldn't be satisfied
+    Requires(Box<Cause>),
+}
+
+impl Cause {
+    /// The package this cause is about
+    fn subject(&self) -> Option<&str> {
+        match self {
+            Cause::Requested { package, .. } | Cause::Dependency { package, .. } => Some(package),
+            Cause::Unsatisfiable => None,
+        }
+    }
+
+    fn render(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        match self {
+            Cause::Requested { package, rejection } => rejection.render(f, package, depth),
+            Cause::Unsatisfiable => write!(f, "the requested packages can't be installed together"),
+            Cause::Dependency {
+                package,
+                dependency,
+                found,
+                rejected,
+            } => {
+                if !rejected.is_empty() {
+                    write!(f, "{package} requires {dependency}, but no candidate can be installed")?;
+
+                    for (candidate, rejection) in rejected {
+                        write!(f, "\n{:indent$}", "", indent = (depth + 1) * 2)?;
+                        rejection.render(f, candidate, depth + 1)?;
+                    }
+
+                    Ok(())
+                } else if !found.is_empty() {
+                    write!(
+                        f,
+                        "{package} requires {dependency}, which no candidate satisfies (found {})",
+                        found.join(", ")
+                    )
+                } else {
+                    write!(f, "{package} requires {dependency}, which no package provides")
+                }
+            }
+        }
+    }
+}
+
+impl Rejection {
+    fn render(&self, f: &mut fmt::Formatter<'_>, candidate: &str, depth: usize) -> fmt::Result {
+        match self {
+            Rejection::Conflict { other, provider } => write!(f, "{candidate} conflicts with {other} ({provider})"),
+            Rejection::Replaces { other } => write!(f, "{candidate} can't be installed alongside {other}"),
+            Rejection::Held => write!(f, "{candidate} is excluded by a hold"),
+            Rejection::Missing => write!(f, "{candidate} can't be found"),
+            Rejection::Requires(cause) if cause.subject() == Some(candidate) => cause.render(f, depth),
+            Rejection::Requires(cause) => {
+                write!(f, "{candidate}: ")?;
+                cause.render(f, depth)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Derivation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.cause.render(f, 0)
+    }
+}
+
+/// The unconstrained [`Provider`] satisfying `dependency`
+fn provider(dependency: &Dependency) -> Provider {
+    Provider {
+        kind: dependency.kind,
+        name: dependency.name.clone(),
+    }
+}
+
+/// Identify a specific candidate of a package by name and version
+fn label(candidate: &package::Candidate) -> String {
+    format!(
+        "{} {}-{}",
+        candidate.name, candidate.version_identifier, candidate.source_release
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::{plugin, Plugin};
+
+    fn provides(mut package: Package, providers: &[&str]) -> Package {
+        package
+            .meta
+            .providers
+            .extend(providers.iter().map(|p| Provider::from_name(p).unwrap()));
+        package
+    }
+
+    fn conflicts(mut package: Package, conflicts: &[&str]) -> Package {
+        package
+            .meta
+            .conflicts
+            .extend(conflicts.iter().map(|p| Provider::from_name(p).unwrap()));
+        package
+    }
+
+    fn installed(mut package: Package) -> Package {
+        package.flags = package::Flags::new().with_installed();
+        package
+    }
+
+    /// Registry made up of `(priority, packages)` fixtures
+    fn registry(fixtures: Vec<(u64, Vec<Package>)>) -> Registry {
+        let mut registry = Registry::default();
+        for (priority, packages) in fixtures {
+            registry.add_plugin(Plugin::Test(plugin::Test::new(priority, packages)));
+        }
+        registry
+    }
+
+    /// Resolve the `requested` ids, returning the sorted ids of the solution
+    fn resolve(registry: &Registry, requested: &[&str]) -> Result<Vec<String>, Error> {
+        let mut tx = registry.transaction()?;
+        tx.add(requested.iter().map(|id| package::Id::from(id.to_string())).collect())?;
+        let mut ids = tx.finalize().map(|id| id.to_string()).collect::<Vec<_>>();
+        ids.sort();
+        Ok(ids)
+    }
+
+    #[test]
+    fn backtracking() {
+        // Greedily picking the preferred foo-2.0 & lib-2.0 leaves bar unsatisfiable
+        let registry = registry(vec![(
+            10,
+            vec![
+                Package::test("app", "1.0", 1, &["foo", "bar"]),
+                Package::test("foo", "2.0", 2, &["lib >= 2"]),
+                Package::test("foo", "1.0", 1, &["lib"]),
+                Package::test("bar", "1.0", 1, &["lib < 2"]),
+                Package::test("lib", "2.0", 2, &[]),
+                Package::test("lib", "1.0", 1, &[]),
+            ],
+        )]);
+
+        assert_eq!(
+            resolve(&registry, &["app-1.0"]).unwrap(),
+            vec!["app-1.0", "bar-1.0", "foo-1.0", "lib-1.0"]
+        );
+    }
+
+    #[test]
+    fn preferences() {
+        let available = vec![
+            (20, vec![Package::test("lib", "2.0", 2, &[])]),
+            (
+                10,
+                vec![
+                    Package::test("app", "1.0", 1, &["lib"]),
+                    Package::test("lib", "1.0", 1, &[]),
+                ],
+            ),
+        ];
+
+        // Highest priority repository wins
+        let fresh = registry(available.clone());
+        assert_eq!(resolve(&fresh, &["app-1.0"]).unwrap(), vec!["app-1.0", "lib-2.0"]);
+
+        // Unless an installed package already satisfies the dependency
+        let mut with_installed = available;
+        with_installed.push((0, vec![installed(Package::test("lib", "1.0", 1, &[]))]));
+        let existing = registry(with_installed);
+        assert_eq!(resolve(&existing, &["app-1.0"]).unwrap(), vec!["app-1.0", "lib-1.0"]);
+
+        // But never at the expense of a constraint
+        let constrained = registry(vec![
+            (20, vec![Package::test("lib", "2.0", 2, &[])]),
+            (10, vec![Package::test("app", "2.0", 1, &["lib >= 2"])]),
+            (0, vec![installed(Package::test("lib", "1.0", 1, &[]))]),
+        ]);
+        assert_eq!(resolve(&constrained, &["app-2.0"]).unwrap(), vec!["app-2.0", "lib-2.0"]);
+    }
+
+    #[test]
+    fn conflict_alternatives() {
+        let registry = registry(vec![
+            (
+                20,
+                vec![conflicts(
+                    provides(Package::test("vim", "9.0", 1, &[]), &["editor"]),
+                    &["nano"],
+                )],
+            ),
+            (
+                10,
+                vec![
+                    Package::test("app", "1.0", 1, &["editor"]),
+                    provides(Package::test("elvis", "2.2", 1, &[]), &["editor"]),
+                    Package::test("nano", "8.0", 1, &[]),
+                ],
+            ),
+        ]);
+
+        assert_eq!(resolve(&registry, &["app-1.0"]).unwrap(), vec!["app-1.0", "vim-9.0"]);
+        assert_eq!(
+            resolve(&registry, &["nano-8.0", "app-1.0"]).unwrap(),
+            vec!["app-1.0", "elvis-2.2", "nano-8.0"]
+        );
+    }
+
+    #[test]
+    fn derivation() {
+        let registry = registry(vec![(
+            10,
+            vec![
+                Package::test("app", "1.0", 1, &["foo"]),
+                Package::test("foo", "2.0", 2, &["lib >= 3"]),
+                Package::test("foo", "1.0", 1, &["missing"]),
+                Package::test("lib", "1.0", 1, &[]),
+                Package::test("tool", "1.0", 1, &["foo < 2"]),
+            ],
+        )]);
+
+        let error = resolve(&registry, &["app-1.0"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "app 1.0-1 requires name(foo), but no candidate can be installed
+  foo 2.0-2 requires name(lib) >= 3, which no candidate satisfies (found lib 1.0-1)
+  foo 1.0-1 requires name(missing), which no package provides"
+        );
+
+        // foo-2.0 is ruled out by tool's constraint before its own dependencies matter
+        let error = resolve(&registry, &["tool-1.0", "app-1.0"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "tool 1.0-1 requires name(foo) < 2, but no candidate can be installed
+  foo 1.0-1 requires name(missing), which no package provides"
+        );
+
+        // Two versions of the same package can't be requested together
+        let error = resolve(&registry, &["foo-2.0", "foo-1.0"]).unwrap_err();
+        assert_eq!(error.to_string(), "foo 1.0-1 can't be installed alongside foo 2.0-2");
+    }
+
+    #[test]
+    fn learned_derivation() {
+        // Trying vim first teaches the solver it can't go alongside nano,
+        // which still explains the failure once elvis runs out too
+        let registry = registry(vec![
+            (
+                20,
+                vec![conflicts(
+                    provides(Package::test("vim", "9.0", 1, &[]), &["editor"]),
+                    &["nano"],
+                )],
+            ),
+            (
+                10,
+                vec![
+                    Package::test("app", "1.0", 1, &["editor"]),
+                    provides(Package::test("elvis", "2.2", 1, &["missing"]), &["editor"]),
+                    Package::test("nano", "8.0", 1, &[]),
+                ],
+            ),
+        ]);
+
+        let error = resolve(&registry, &["nano-8.0", "app-1.0"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "app 1.0-1 requires name(editor), but no candidate can be installed
+  vim 9.0-1 conflicts with nano 8.0-1 (name(nano))
+  elvis 2.2-1 requires name(missing), which no package provides"
+        );
+    }
+
+    #[test]
+    fn backjumping() {
+        // None of the choices for a have anything to do with b failing,
+        // so they're neither retried nor part of the explanation
+        let registry = registry(vec![(
+            10,
+            vec![
+                Package::test("app", "1.0", 1, &["a", "b"]),
+                Package::test("a", "3.0", 3, &[]),
+                Package::test("a", "2.0", 2, &[]),
+                Package::test("a", "1.0", 1, &[]),
+            ],
+        )]);
+
+        let error = resolve(&registry, &["app-1.0"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "app 1.0-1 requires name(b), which no package provides"
+        );
+    }
+}
@@ -2,24 +2,20 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::collections::BTreeMap;
+
 use dag::Dag;
 use thiserror::Error;
 
-use crate::{package, Provider, Registry};
-
-enum ProviderFilter {
-    /// Must be installed
-    InstalledOnly(Provider),
+pub use super::solver::Derivation;
+use super::solver::Solver;
+use crate::{package, Package, Provider, Registry};
 
-    /// Filter the lookup to current selection scope
-    Selections(Provider),
-
-    // Look beyond installed/selections
-    All(Provider),
-}
-
-enum Lookup {
+/// Where dependencies may be resolved from
+pub(super) enum Lookup {
+    /// Only installed packages
     InstalledOnly,
+    /// Installed packages, followed by all available packages
     Global,
 }
 
@@ -31,6 +27,9 @@ pub struct Transaction<'a> {
 
     // unique set of package ids
     packages: Dag<package::Id>,
+
+    // the only candidates allowed for some package names
+    restricted: BTreeMap<package::Name, Vec<package::Id>>,
 }
 
 /// Construct a new Transaction wrapped around the underlying Registry
@@ -40,6 +39,7 @@ pub(super) fn new(registry: &Registry) -> Result<Transaction<'_>, Error> {
     Ok(Transaction {
         registry,
         packages: Dag::default(),
+        restricted: BTreeMap::new(),
     })
 }
 
@@ -56,6 +56,11 @@ impl<'a> Transaction<'a> {
         self.update(incoming, Lookup::Global)
     }
 
+    /// Only allow `candidates` to be selected for packages named `name`, i.e. to honour holds
+    pub fn restrict(&mut self, name: package::Name, candidates: Vec<package::Id>) {
+        self.restricted.insert(name, candidates);
+    }
+
     /// Remove a set of packages and their reverse dependencies
     pub fn remove(&mut self, packages: Vec<package::Id>) {
         // Get transposed subgraph
@@ -74,83 +79,42 @@ impl<'a> Transaction<'a> {
         self.packages.topo()
     }
 
+    /// The dependency graph, with edges from each package to its dependencies
+    pub fn graph(&self) -> &Dag<package::Id> {
+        &self.packages
+    }
+
     /// Update internal package graph with all incoming packages & their deps
     fn update(&mut self, incoming: Vec<package::Id>, lookup: Lookup) -> Result<(), Error> {
-        let mut items = incoming;
+        let locked = self.packages.iter_nodes().cloned().collect::<Vec<_>>();
+        let resolved = Solver::new(self.registry, lookup, &self.restricted).solve(&locked, &incoming)?;
 
-        loop {
-            if items.is_empty() {
-                break;
-            }
-            let mut next = vec![];
-            for check_id in items.iter() {
-                // Ensure node is added and get it's index
-                let check_node = self.packages.add_node_or_get_index(check_id.clone());
-
-                // Grab this package in question
-                let package = self
-                    .registry
-                    .by_id(check_id)
-                    .next()
-                    .ok_or(Error::NoCandidate(check_id.clone().into()))?;
-                for dependency in package.meta.dependencies.iter() {
-                    let provider = Provider {
-                        kind: dependency.kind,
-                        name: dependency.name.clone(),
-                    };
-
-                    // Now get it resolved
-                    let search = match lookup {
-                        Lookup::Global => self.resolve_installation_provider(provider)?,
-                        Lookup::InstalledOnly => self.resolve_provider(ProviderFilter::InstalledOnly(provider))?,
-                    };
-
-                    // Add dependency node
-                    let need_search = !self.packages.node_exists(&search);
-                    let dep_node = self.packages.add_node_or_get_index(search.clone());
-
-                    // No dag node for it previously
-                    if need_search {
-                        next.push(search.clone());
-                    }
-
-                    // Connect w/ edges (rejects cyclical & duplicate edges)
-                    self.packages.add_edge(check_node, dep_node);
-                }
+        for (package, dependencies) in resolved {
+            let package_node = self.packages.add_node_or_get_index(package);
+
+            for dependency in dependencies {
+                let dependency_node = self.packages.add_node_or_get_index(dependency);
+
+                // Connect w/ edges (rejects cyclical & duplicate edges)
+                self.packages.add_edge(package_node, dependency_node);
             }
-            items = next;
         }
 
         Ok(())
     }
+}
 
-    /// Attempt to resolve the filterered provider
-    fn resolve_provider(&self, filter: ProviderFilter) -> Result<package::Id, Error> {
-        match filter {
-            ProviderFilter::All(provider) => self
-                .registry
-                .by_provider_id_only(&provider, package::Flags::new().with_available())
-                .next()
-                .ok_or(Error::NoCandidate(provider.to_string())),
-            ProviderFilter::InstalledOnly(provider) => self
-                .registry
-                .by_provider_id_only(&provider, package::Flags::new().with_installed())
-                .next()
-                .ok_or(Error::NoCandidate(provider.to_string())),
-            ProviderFilter::Selections(provider) => self
-                .registry
-                .by_provider_id_only(&provider, package::Flags::default())
-                .find(|id| self.packages.node_exists(id))
-                .ok_or(Error::NoCandidate(provider.to_string())),
-        }
-    }
-
-    // Try all strategies to resolve a provider for installation
-    fn resolve_installation_provider(&self, provider: Provider) -> Result<package::Id, Error> {
-        self.resolve_provider(ProviderFilter::Selections(provider.clone()))
-            .or_else(|_| self.resolve_provider(ProviderFilter::InstalledOnly(provider.clone())))
-            .or_else(|_| self.resolve_provider(ProviderFilter::All(provider)))
-    }
+/// Returns the first pair of `packages` that conflict with each other, along with the
+/// provider they conflict on
+pub fn find_conflict(packages: &[Package]) -> Option<(&Package, &Package, &Provider)> {
+    packages
+        .iter()
+        .filter(|package| !package.meta.conflicts.is_empty())
+        .find_map(|package| {
+            packages
+                .iter()
+                .find_map(|other| Some((package, other, package.conflict_with(other)?)))
+        })
 }
 
 #[derive(Debug, Error)]
@@ -158,9 +122,126 @@ pub enum Error {
     #[error("No such name: {0}")]
     NoCandidate(String),
 
+    #[error("{0}")]
+    Unsolvable(Derivation),
+
     #[error("Not yet implemented")]
     NotImplemented,
 
     #[error("meta db")]
     Database(#[from] crate::db::meta::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::{plugin, Plugin};
+
+    #[test]
+    fn constraints() {
+        let mut registry = Registry::default();
+        let id = |id: &str| package::Id::from(id.to_string());
+
+        // Greedy selection would prefer the higher priority lib-1.0
+        registry.add_plugin(Plugin::Test(plugin::Test::new(
+            10,
+            vec![
+                Package::test("lib", "1.0", 1, &[]),
+                Package::test("app", "1.0", 1, &["lib >= 1.5"]),
+                Package::test("old", "1.0", 1, &["name(lib) release<2"]),
+                Package::test("future", "1.0", 1, &["lib >= 3"]),
+            ],
+        )));
+        registry.add_plugin(Plugin::Test(plugin::Test::new(
+            1,
+            vec![Package::test("lib", "2.1", 2, &[])],
+        )));
+
+        let mut tx = registry.transaction().unwrap();
+        tx.add(vec![id("app-1.0")]).unwrap();
+        let finalized = tx.finalize().cloned().collect::<Vec<_>>();
+        assert!(finalized.contains(&id("lib-2.1")));
+        assert!(!finalized.contains(&id("lib-1.0")));
+
+        let mut tx = registry.transaction().unwrap();
+        tx.add(vec![id("old-1.0")]).unwrap();
+        assert!(tx.finalize().any(|p| *p == id("lib-1.0")));
+
+        let mut tx = registry.transaction().unwrap();
+        let error = tx.add(vec![id("future-1.0")]).unwrap_err();
+        assert!(matches!(error, Error::Unsolvable(_)));
+        assert_eq!(
+            error.to_string(),
+            "future 1.0-1 requires name(lib) >= 3, which no candidate satisfies (found lib 1.0-1, lib 2.1-2)"
+        );
+    }
+
+    #[test]
+    fn conflicts() {
+        let mut registry = Registry::default();
+        let id = |id: &str| package::Id::from(id.to_string());
+
+        let mut vim = Package::test("vim", "9.0", 1, &[]);
+        vim.meta.conflicts.insert(Provider::from_name("vi").unwrap());
+
+        registry.add_plugin(Plugin::Test(plugin::Test::new(
+            10,
+            vec![
+                vim,
+                Package::test("vi", "1.0", 1, &[]),
+                Package::test("editor", "1.0", 1, &["vi"]),
+                Package::test("nano", "8.0", 1, &[]),
+            ],
+        )));
+
+        let mut tx = registry.transaction().unwrap();
+        tx.add(vec![id("vim-9.0"), id("nano-8.0")]).unwrap();
+        assert_eq!(tx.finalize().count(), 2);
+
+        let mut tx = registry.transaction().unwrap();
+        let error = tx.add(vec![id("vim-9.0"), id("editor-1.0")]).unwrap_err();
+        assert!(matches!(error, Error::Unsolvable(_)));
+        assert_eq!(
+            error.to_string(),
+            "editor 1.0-1 requires name(vi), but no candidate can be installed\n  vi 1.0-1 conflicts with vim 9.0-1 (name(vi))"
+        );
+    }
+
+    #[test]
+    fn restrictions() {
+        let mut registry = Registry::default();
+        let id = |id: &str| package::Id::from(id.to_string());
+
+        registry.add_plugin(Plugin::Test(plugin::Test::new(
+            10,
+            vec![
+                Package::test("lib", "2.1", 2, &[]),
+                Package::test("app", "1.0", 1, &["lib"]),
+                Package::test("future", "1.0", 1, &["lib >= 2"]),
+            ],
+        )));
+        registry.add_plugin(Plugin::Test(plugin::Test::new(
+            1,
+            vec![Package::test("lib", "1.0", 1, &[])],
+        )));
+
+        // Held at the lower priority candidate
+        let mut tx = registry.transaction().unwrap();
+        tx.restrict(package::Name::from("lib".to_string()), vec![id("lib-1.0")]);
+        tx.add(vec![id("app-1.0")]).unwrap();
+        assert!(tx.finalize().any(|p| *p == id("lib-1.0")));
+
+        let mut tx = registry.transaction().unwrap();
+        tx.restrict(package::Name::from("lib".to_string()), vec![id("lib-1.0")]);
+        let error = tx.add(vec![id("future-1.0")]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "future 1.0-1 requires name(lib) >= 2, but no candidate can be installed\n  lib 2.1-2 is excluded by a hold"
+        );
+
+        let mut tx = registry.transaction().unwrap();
+        tx.restrict(package::Name::from("lib".to_string()), vec![id("lib-1.0")]);
+        let error = tx.add(vec![id("lib-2.1")]).unwrap_err();
+        assert_eq!(error.to_string(), "lib 2.1-2 is excluded by a hold");
+    }
+}
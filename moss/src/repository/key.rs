@@ -0,0 +1,218 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Ed25519 signing & verification of repository index files
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    str::FromStr,
+};
+
+use config::Config;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::repository;
+
+/// File extension of a detached index signature
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// A trusted public key used to verify a repository index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PublicKey(VerifyingKey);
+
+impl PublicKey {
+    /// Verify `signature` over `data` was made with this key
+    pub fn verify(&self, data: &[u8], signature: &[u8]) -> Result<(), Error> {
+        let signature = Signature::from_slice(signature).map_err(|_| Error::MalformedSignature)?;
+
+        self.0.verify(data, &signature).map_err(|_| Error::BadSignature)
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0.as_bytes()))
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s.trim())
+            .ok()
+            .and_then(|bytes| <[u8; PUBLIC_KEY_LENGTH]>::try_from(bytes).ok())
+            .ok_or(Error::MalformedKey)?;
+
+        Ok(Self(VerifyingKey::from_bytes(&bytes).map_err(|_| Error::MalformedKey)?))
+    }
+}
+
+impl TryFrom<String> for PublicKey {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PublicKey> for String {
+    fn from(key: PublicKey) -> Self {
+        key.to_string()
+    }
+}
+
+/// A secret key used to sign a repository index
+pub struct SecretKey(SigningKey);
+
+impl SecretKey {
+    /// Generate a new random key from the OS random number generator
+    pub fn generate() -> Self {
+        Self(SigningKey::generate(&mut OsRng))
+    }
+
+    /// Load a hex encoded key from `path`
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let bytes = hex::decode(fs::read_to_string(path)?.trim())
+            .ok()
+            .and_then(|bytes| <[u8; SECRET_KEY_LENGTH]>::try_from(bytes).ok())
+            .ok_or(Error::MalformedKey)?;
+
+        Ok(Self(SigningKey::from_bytes(&bytes)))
+    }
+
+    /// Save the hex encoded key to `path`, readable only by the owner
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)?;
+
+        writeln!(file, "{}", hex::encode(self.0.to_bytes()))?;
+
+        Ok(())
+    }
+
+    /// The [`PublicKey`] of this key
+    pub fn public(&self) -> PublicKey {
+        PublicKey(self.0.verifying_key())
+    }
+
+    /// Sign `data`, returning the signature bytes
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.0.sign(data).to_bytes().to_vec()
+    }
+}
+
+/// Verify `signature` over `data` was made with any of the trusted `keys`
+pub fn verify(keys: &[PublicKey], data: &[u8], signature: &[u8]) -> Result<(), Error> {
+    let signature = decode_signature(signature)?;
+
+    if keys.iter().any(|key| key.verify(data, &signature).is_ok()) {
+        Ok(())
+    } else {
+        Err(Error::BadSignature)
+    }
+}
+
+/// Hex encode a signature for writing to a detached signature file
+pub fn encode_signature(signature: &[u8]) -> String {
+    format!("{}\n", hex::encode(signature))
+}
+
+/// Decode a detached signature file
+fn decode_signature(contents: &[u8]) -> Result<Vec<u8>, Error> {
+    let contents = std::str::from_utf8(contents).map_err(|_| Error::MalformedSignature)?;
+
+    hex::decode(contents.trim()).map_err(|_| Error::MalformedSignature)
+}
+
+/// Trusted keys of each repository
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Map(HashMap<repository::Id, Vec<PublicKey>>);
+
+impl Map {
+    pub fn with(items: impl IntoIterator<Item = (repository::Id, Vec<PublicKey>)>) -> Self {
+        Self(items.into_iter().collect())
+    }
+
+    pub fn get(&self, id: &repository::Id) -> &[PublicKey] {
+        self.0.get(id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&repository::Id, &[PublicKey])> {
+        self.0.iter().map(|(id, keys)| (id, keys.as_slice()))
+    }
+
+    pub fn merge(mut self, other: Self) -> Self {
+        for (id, keys) in other.0 {
+            let entry = self.0.entry(id).or_default();
+
+            for key in keys {
+                if !entry.contains(&key) {
+                    entry.push(key);
+                }
+            }
+        }
+
+        self
+    }
+}
+
+impl Config for Map {
+    fn domain() -> String {
+        "repo-key".into()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("malformed key")]
+    MalformedKey,
+    #[error("malformed signature")]
+    MalformedSignature,
+    #[error("signature doesn't match any trusted key")]
+    BadSignature,
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sign_verify() {
+        let secret = SecretKey::generate();
+        let other = SecretKey::generate();
+        let public = secret.public().to_string().parse::<PublicKey>().unwrap();
+
+        let data = b"stone.index";
+        let signature = encode_signature(&secret.sign(data));
+
+        assert!(verify(&[public], data, signature.as_bytes()).is_ok());
+        assert!(verify(&[other.public(), public], data, signature.as_bytes()).is_ok());
+        assert!(matches!(
+            verify(&[other.public()], data, signature.as_bytes()),
+            Err(Error::BadSignature)
+        ));
+        assert!(matches!(
+            verify(&[public], b"tampered", signature.as_bytes()),
+            Err(Error::BadSignature)
+        ));
+        assert!(matches!(
+            verify(&[public], data, b"nonsense"),
+            Err(Error::MalformedSignature)
+        ));
+    }
+}
@@ -10,15 +10,21 @@ use std::time::Duration;
 
 use futures::{stream, StreamExt, TryStreamExt};
 use itertools::Itertools;
+use log::warn;
 use thiserror::Error;
 use tui::{MultiProgress, ProgressBar, ProgressStyle, Styled};
+use url::Url;
 use xxhash_rust::xxh3::xxh3_64;
 
 use crate::db::meta;
 use crate::{environment, runtime};
 use crate::{package, Installation};
 
-use crate::repository::{self, Repository};
+use crate::repository::{
+    self,
+    key::{self, PublicKey},
+    Repository,
+};
 
 enum Source {
     System(config::Manager),
@@ -39,6 +45,7 @@ pub struct Manager {
     source: Source,
     installation: Installation,
     repositories: HashMap<repository::Id, repository::Active>,
+    keys: key::Map,
 }
 
 impl Manager {
@@ -81,6 +88,14 @@ impl Manager {
             }
             Source::Explicit { repos, .. } => repos.clone(),
         };
+        let keys = match &source {
+            Source::System(config) => config
+                .load::<key::Map>()
+                .into_iter()
+                .reduce(key::Map::merge)
+                .unwrap_or_default(),
+            Source::Explicit { .. } => key::Map::default(),
+        };
 
         // Open all repo meta dbs and collect into hash map
         let repositories = configs
@@ -96,6 +111,7 @@ impl Manager {
             source,
             installation,
             repositories,
+            keys,
         })
     }
 
@@ -121,10 +137,69 @@ impl Manager {
         Ok(())
     }
 
+    /// Trust `key` to sign the index of the [`Repository`] with `id`
+    pub fn add_key(&mut self, id: repository::Id, key: PublicKey) -> Result<(), Error> {
+        let Source::System(config) = &self.source else {
+            return Err(Error::ExplicitUnsupported);
+        };
+
+        let mut keys = self.keys.get(&id).to_vec();
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+
+        let map = key::Map::with([(id.clone(), keys)]);
+        config.save(&id, &map).map_err(Error::SaveConfig)?;
+
+        self.keys = std::mem::take(&mut self.keys).merge(map);
+
+        Ok(())
+    }
+
+    /// Stop trusting `key` for the [`Repository`] with `id`, returns false if
+    /// the key wasn't trusted
+    ///
+    /// Keys defined outside the repository's own key configuration file
+    /// must be removed manually
+    pub fn remove_key(&mut self, id: &repository::Id, key: &PublicKey) -> Result<bool, Error> {
+        let Source::System(config) = &self.source else {
+            return Err(Error::ExplicitUnsupported);
+        };
+
+        let keys = self.keys.get(id);
+        if !keys.contains(key) {
+            return Ok(false);
+        }
+
+        let remaining = keys.iter().filter(|k| *k != key).copied().collect::<Vec<_>>();
+
+        if remaining.is_empty() {
+            // May not exist if the key was configured elsewhere
+            let _ = config.delete::<key::Map>(id);
+        } else {
+            config
+                .save(id, &key::Map::with([(id.clone(), remaining.clone())]))
+                .map_err(Error::SaveConfig)?;
+        }
+
+        self.keys = key::Map::with(
+            self.keys
+                .iter()
+                .map(|(i, keys)| (i.clone(), if i == id { remaining.clone() } else { keys.to_vec() })),
+        );
+
+        Ok(true)
+    }
+
+    /// Returns the keys trusted to sign the index of each [`Repository`]
+    pub fn keys(&self) -> &key::Map {
+        &self.keys
+    }
+
     /// Refresh a [`Repository`] by Id
     pub async fn refresh(&self, id: &repository::Id) -> Result<(), Error> {
         if let Some(repo) = self.repositories.get(id).cloned() {
-            let file = fetch_index(self.source.identifier(), &repo, &self.installation).await?;
+            let file = fetch_index(self.source.identifier(), &repo, self.keys.get(id), &self.installation).await?;
             runtime::unblock(move || update_meta_db(&repo, &file)).await?;
             Ok(())
         } else {
@@ -218,12 +293,20 @@ impl Manager {
         Ok(uninitialized.len())
     }
 
+    /// Returns the file list of each repository that publishes one
+    pub(crate) fn file_lists(&self) -> impl Iterator<Item = (&repository::Id, PathBuf)> + '_ {
+        self.repositories.iter().filter_map(|(id, state)| {
+            let path = cache_dir(self.source.identifier(), &state.repository, &self.installation).join("stone.files");
+            path.exists().then_some((id, path))
+        })
+    }
+
     /// Returns the active repositories held by this manager
     pub(crate) fn active(&self) -> impl Iterator<Item = repository::Active> + '_ {
         self.repositories.values().cloned()
     }
 
-    /// Remove a repository, deleting any related config & cached data
+    /// Remove a repository, deleting any related config, trusted keys & cached data
     pub fn remove(&mut self, id: impl Into<repository::Id>) -> Result<Removal, Error> {
         // Only allow removal for system repo manager
         let Source::System(config) = &self.source else {
@@ -235,6 +318,15 @@ impl Manager {
             return Ok(Removal::NotFound);
         };
 
+        // Stop trusting its keys, which may not exist if configured elsewhere
+        let _ = config.delete::<key::Map>(&repo.id);
+        self.keys = key::Map::with(
+            self.keys
+                .iter()
+                .filter(|(id, _)| **id != repo.id)
+                .map(|(id, keys)| (id.clone(), keys.to_vec())),
+        );
+
         let cache_dir = cache_dir(self.source.identifier(), &repo.repository, &self.installation);
 
         // Remove cache
@@ -255,6 +347,22 @@ impl Manager {
     pub fn list(&self) -> impl ExactSizeIterator<Item = (&repository::Id, &Repository)> {
         self.repositories.iter().map(|(id, state)| (id, &state.repository))
     }
+
+    /// Returns the URI that served the last index refresh of the [`Repository`], if any
+    pub fn last_mirror(&self, id: &repository::Id) -> Option<Url> {
+        let repo = self.repositories.get(id)?;
+        let path = cache_dir(self.source.identifier(), &repo.repository, &self.installation).join("mirror");
+
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    /// Returns fallback URLs for the package `url` on each mirror of the repository it belongs to
+    pub fn mirror_urls(&self, url: &Url) -> Vec<Url> {
+        self.repositories
+            .values()
+            .flat_map(|state| state.repository.mirror_urls(url))
+            .collect()
+    }
 }
 
 /// Directory for the repo cached data (db & stone index), hashed by identifier & repo URI
@@ -277,9 +385,13 @@ fn open_meta_db(identifier: &str, repo: &Repository, installation: &Installation
 
 /// Fetches a stone index file from the repository URL
 /// and saves it to the repo installation path
+///
+/// If any `keys` are trusted for the repository, the index must
+/// have a detached signature made by one of them
 async fn fetch_index(
     identifier: &str,
     state: &repository::Active,
+    keys: &[PublicKey],
     installation: &Installation,
 ) -> Result<PathBuf, Error> {
     let out_dir = cache_dir(identifier, &state.repository, installation);
@@ -287,18 +399,102 @@ async fn fetch_index(
     tokio::fs::create_dir_all(&out_dir).await.map_err(Error::CreateDir)?;
 
     let out_path = out_dir.join("stone.index");
-
-    // Fetch index & write to `out_path`
-    repository::fetch_index(state.repository.uri.clone(), &out_path).await?;
+    // Index isn't used until it's been verified
+    let new_path = out_dir.join("stone.index.new");
+
+    // Fetch index & write to `out_path`, falling back to the next mirror
+    // if the current one is unavailable
+    let mut uris = state.repository.uris().peekable();
+    while let Some(uri) = uris.next() {
+        match fetch_verified_index(&state.id, uri, keys, &new_path).await {
+            Ok(()) => {
+                tokio::fs::rename(&new_path, &out_path)
+                    .await
+                    .map_err(Error::WriteIndex)?;
+
+                // Record which mirror served this refresh
+                tokio::fs::write(out_dir.join("mirror"), uri.as_str())
+                    .await
+                    .map_err(Error::WriteMirror)?;
+
+                fetch_sidecar(state, uri, keys, &out_dir, "stone.files").await;
+                fetch_sidecar(state, uri, keys, &out_dir, "stone.deltas").await;
+                break;
+            }
+            Err(Error::FetchIndex(error)) if error.is_unavailable() && uris.peek().is_some() => {
+                warn!("Repository {} unavailable at {uri}, trying next mirror", state.id);
+            }
+            Err(error) => {
+                let _ = tokio::fs::remove_file(&new_path).await;
+                return Err(error);
+            }
+        }
+    }
 
     Ok(out_path)
 }
 
+/// Fetches an optional `file` published alongside the index at `uri`, such as
+/// the file list used to search available packages by path or the deltas
+/// between package releases
+///
+/// Repositories aren't required to publish them, so any failure leaves the
+/// repository without one
+async fn fetch_sidecar(state: &repository::Active, uri: &Url, keys: &[PublicKey], out_dir: &Path, file: &str) {
+    let out_path = out_dir.join(file);
+    let new_path = out_dir.join(format!("{file}.new"));
+
+    let result = match fetch_verified_index(&state.id, &repository::sidecar_url(uri, file), keys, &new_path).await {
+        Ok(()) => tokio::fs::rename(&new_path, &out_path).await.map_err(Error::WriteIndex),
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = result {
+        let _ = tokio::fs::remove_file(&new_path).await;
+        // Never keep the file of a previous index
+        let _ = tokio::fs::remove_file(&out_path).await;
+
+        if !matches!(&error, Error::FetchIndex(error) if error.is_not_found()) {
+            warn!("Repository {} {file} unavailable: {error}", state.id);
+        }
+    }
+}
+
+/// Fetches the index at `uri` to `out_path`, verifying its signature against `keys`
+async fn fetch_verified_index(
+    id: &repository::Id,
+    uri: &Url,
+    keys: &[PublicKey],
+    out_path: &Path,
+) -> Result<(), Error> {
+    repository::fetch_index(uri.clone(), out_path).await?;
+
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    let signature = match repository::fetch_signature(uri).await {
+        Ok(signature) => signature,
+        Err(error) if error.is_not_found() => return Err(Error::Unsigned(id.clone())),
+        Err(error) => return Err(error.into()),
+    };
+
+    let index = tokio::fs::read(out_path).await.map_err(Error::OpenIndex)?;
+
+    key::verify(keys, &index, &signature).map_err(|error| Error::Signature(id.clone(), error))
+}
+
 /// Updates a stones metadata into the meta db
 fn update_meta_db(state: &repository::Active, index_path: &Path) -> Result<(), Error> {
     // Wipe db since we're refreshing from a new index file
     state.db.wipe()?;
 
+    // A broken delta list only costs bandwidth, full packages remain available
+    let deltas = read_deltas(&index_path.with_file_name("stone.deltas")).unwrap_or_else(|error| {
+        warn!("Repository {} deltas unavailable: {error}", state.id);
+        HashMap::new()
+    });
+
     // Get a stream of payloads
     let mut file = File::open(index_path).map_err(Error::OpenIndex)?;
     let mut reader = stone::read(&mut file)?;
@@ -325,13 +521,14 @@ fn update_meta_db(state: &repository::Active, index_path: &Path) -> Result<(), E
                     }
                 })
                 .map(|payload| {
-                    let meta = package::Meta::from_stone_payload(&payload.body)?;
+                    let mut meta = package::Meta::from_stone_payload(&payload.body)?;
 
                     // Create id from hash of meta
                     let hash = meta
                         .hash
                         .clone()
                         .ok_or(Error::MissingMetaField(stone::payload::meta::Tag::PackageHash))?;
+                    meta.deltas = deltas.get(&hash).cloned().unwrap_or_default();
                     let id = package::Id::from(hash);
 
                     Ok((id, meta))
@@ -350,6 +547,26 @@ fn update_meta_db(state: &repository::Active, index_path: &Path) -> Result<(), E
     Ok(())
 }
 
+/// Reads the deltas published alongside an index, by the hash of the package they produce
+fn read_deltas(path: &Path) -> Result<HashMap<String, Vec<package::meta::Delta>>, Error> {
+    let mut deltas = HashMap::<_, Vec<_>>::new();
+
+    // Not every repository publishes deltas
+    let Ok(mut file) = File::open(path) else {
+        return Ok(deltas);
+    };
+    let mut reader = stone::read(&mut file)?;
+
+    for payload in reader.payloads()? {
+        if let stone::read::PayloadKind::Meta(meta) = payload? {
+            let (target, delta) = package::meta::Delta::from_stone_payload(&meta.body)?;
+            deltas.entry(target).or_default().push(delta);
+        }
+    }
+
+    Ok(deltas)
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Can't modify repos when using explicit configs")]
@@ -362,6 +579,14 @@ pub enum Error {
     RemoveDir(#[source] io::Error),
     #[error("fetch index file")]
     FetchIndex(#[from] repository::FetchError),
+    #[error("repository {0} index is not signed")]
+    Unsigned(repository::Id),
+    #[error("repository {0} index signature")]
+    Signature(repository::Id, #[source] key::Error),
+    #[error("write index file")]
+    WriteIndex(#[source] io::Error),
+    #[error("write mirror file")]
+    WriteMirror(#[source] io::Error),
     #[error("open index file")]
     OpenIndex(#[source] io::Error),
     #[error("read index file")]
@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{collections::HashMap, path::Path};
+use std::{collections::HashMap, iter, path::Path};
 
 use config::Config;
 use derive_more::{Display, From, Into};
@@ -19,6 +19,7 @@ use crate::{db::meta, request};
 
 pub use self::manager::Manager;
 
+pub mod key;
 pub mod manager;
 
 /// A unique [`Repository`] identifier
@@ -42,9 +43,36 @@ impl Id {
 pub struct Repository {
     pub description: String,
     pub uri: Url,
+    /// Ordered fallback index URIs, tried in turn when `uri` is unavailable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<Url>,
     pub priority: Priority,
 }
 
+impl Repository {
+    /// All index URIs in the order they should be tried, starting with the primary `uri`
+    pub fn uris(&self) -> impl Iterator<Item = &Url> {
+        iter::once(&self.uri).chain(&self.mirrors)
+    }
+
+    /// Returns the equivalent of package `url`, resolved relative to the primary `uri`,
+    /// on each mirror in order. Returns nothing if `url` doesn't belong to this repository.
+    pub fn mirror_urls(&self, url: &Url) -> Vec<Url> {
+        let Some(relative) = self.uri.make_relative(url) else {
+            return vec![];
+        };
+
+        if relative.starts_with("../") {
+            return vec![];
+        }
+
+        self.mirrors
+            .iter()
+            .filter_map(|mirror| mirror.join(&relative).ok())
+            .collect()
+    }
+}
+
 /// An active repository that has been
 /// fetched and cached to a meta database
 #[derive(Debug, Clone)]
@@ -131,6 +159,30 @@ async fn fetch_index(url: Url, out_path: impl AsRef<Path>) -> Result<(), FetchEr
     Ok(())
 }
 
+/// The url of `file`, published alongside the index at `url`
+fn sidecar_url(url: &Url, file: &str) -> Url {
+    let mut url = url.clone();
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop().push(file);
+    }
+    url
+}
+
+/// Fetches the detached signature of the index at `url`
+async fn fetch_signature(url: &Url) -> Result<Vec<u8>, FetchError> {
+    let mut url = url.clone();
+    url.set_path(&format!("{}.{}", url.path(), key::SIGNATURE_EXTENSION));
+
+    let mut stream = request::get(url).await?;
+    let mut signature = vec![];
+
+    while let Some(chunk) = stream.next().await {
+        signature.extend_from_slice(&chunk?);
+    }
+
+    Ok(signature)
+}
+
 #[derive(Debug, Error)]
 pub enum FetchError {
     #[error("request")]
@@ -138,3 +190,70 @@ pub enum FetchError {
     #[error("io")]
     Io(#[from] io::Error),
 }
+
+impl FetchError {
+    /// Returns true if the index may be fetched from another mirror
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            FetchError::Request(error) => error.is_transient(),
+            FetchError::Io(_) => false,
+        }
+    }
+
+    /// Returns true if the requested resource doesn't exist
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            FetchError::Request(error) => error.is_not_found(),
+            FetchError::Io(error) => error.kind() == io::ErrorKind::NotFound,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sidecar_urls() {
+        let url = "https://dev.serpentos.com/volatile/x86_64/stone.index".parse().unwrap();
+        assert_eq!(
+            sidecar_url(&url, "stone.files").as_str(),
+            "https://dev.serpentos.com/volatile/x86_64/stone.files"
+        );
+        assert_eq!(
+            sidecar_url(&url, "stone.deltas").as_str(),
+            "https://dev.serpentos.com/volatile/x86_64/stone.deltas"
+        );
+    }
+
+    #[test]
+    fn mirror_urls() {
+        let repo = Repository {
+            description: String::default(),
+            uri: "https://dev.serpentos.com/volatile/x86_64/stone.index".parse().unwrap(),
+            mirrors: vec![
+                "https://mirror.example.com/serpent/volatile/x86_64/stone.index"
+                    .parse()
+                    .unwrap(),
+                "file:///srv/volatile/stone.index".parse().unwrap(),
+            ],
+            priority: Priority::new(0),
+        };
+
+        let url = "https://dev.serpentos.com/volatile/x86_64/pool/n/nano/nano-7.2-1-1-x86_64.stone"
+            .parse()
+            .unwrap();
+        let mirrors = repo.mirror_urls(&url);
+
+        assert_eq!(
+            mirrors.iter().map(Url::as_str).collect::<Vec<_>>(),
+            [
+                "https://mirror.example.com/serpent/volatile/x86_64/pool/n/nano/nano-7.2-1-1-x86_64.stone",
+                "file:///srv/volatile/pool/n/nano/nano-7.2-1-1-x86_64.stone",
+            ]
+        );
+
+        let foreign = "https://example.com/nano.stone".parse().unwrap();
+        assert!(repo.mirror_urls(&foreign).is_empty());
+    }
+}
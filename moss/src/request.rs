@@ -2,16 +2,26 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{io, path::PathBuf};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use bytes::Bytes;
 use futures::{
     stream::{self, BoxStream},
     Stream, StreamExt,
 };
+use log::warn;
 use once_cell::sync::Lazy;
+use reqwest::{header, StatusCode};
 use thiserror::Error;
-use tokio::{fs::File, io::AsyncReadExt};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+    time,
+};
 use tokio_util::io::ReaderStream;
 use url::Url;
 
@@ -25,6 +35,138 @@ static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
         .expect("build reqwest client")
 });
 
+/// Download the resource at the provided [`Url`] to `path`
+///
+/// The body is written to a `.part` file next to `path` which is renamed into place once
+/// complete. A `.part` file left behind by an interrupted transfer is resumed with an HTTP
+/// Range request and transient failures are retried up to `retries` times with exponential
+/// backoff.
+///
+/// `on_chunk` receives every byte of the resulting file exactly once and in order, including
+/// those already present in a resumed `.part` file.
+pub async fn download(url: Url, path: &Path, retries: u32, mut on_chunk: impl FnMut(&[u8])) -> Result<(), Error> {
+    let part_path = part_path(path);
+
+    let mut out = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(&part_path)
+        .await?;
+
+    // Replay anything we already have from a previous attempt
+    let mut offset = 0;
+    let mut buffer = vec![0; environment::FILE_READ_BUFFER_SIZE];
+    loop {
+        let read = out.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        on_chunk(&buffer[..read]);
+        offset += read as u64;
+    }
+
+    let mut attempt = 0;
+
+    loop {
+        match transfer(&url, &mut out, &mut offset, &mut on_chunk).await {
+            Ok(()) => break,
+            Err(error) if attempt < retries && error.is_transient() => {
+                let delay = retry_delay(attempt);
+                attempt += 1;
+
+                warn!("Download of {url} failed, retrying in {delay:?} ({attempt}/{retries}): {error}");
+
+                time::sleep(delay).await;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    out.flush().await?;
+    out.sync_all().await?;
+    drop(out);
+
+    fs::rename(&part_path, path).await?;
+
+    Ok(())
+}
+
+/// Delay before retrying a download after `attempt` earlier retries, doubling each
+/// time up to [`environment::MAX_DOWNLOAD_RETRY_DELAY`]
+fn retry_delay(attempt: u32) -> Duration {
+    let factor = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+
+    environment::DOWNLOAD_RETRY_DELAY
+        .saturating_mul(factor)
+        .min(environment::MAX_DOWNLOAD_RETRY_DELAY)
+}
+
+/// Single transfer attempt for `download`, continuing from `offset`
+async fn transfer(url: &Url, out: &mut File, offset: &mut u64, on_chunk: &mut impl FnMut(&[u8])) -> Result<(), Error> {
+    // Bytes at the start of the body we already have
+    let (mut stream, mut skip) = match url_file(url) {
+        Some(path) => (read(path).await?, *offset),
+        None => {
+            let mut request = CLIENT.get(url.clone());
+            if *offset > 0 {
+                request = request.header(header::RANGE, format!("bytes={offset}-"));
+            }
+
+            let response = request.send().await?;
+
+            // Nothing left to fetch
+            if *offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+                return Ok(());
+            }
+
+            let response = response.error_for_status()?;
+
+            // Server may ignore the range and send the whole body
+            let skip = if response.status() == StatusCode::PARTIAL_CONTENT {
+                0
+            } else {
+                *offset
+            };
+
+            (
+                response
+                    .bytes_stream()
+                    .map(|result| result.map_err(Error::Fetch))
+                    .boxed(),
+                skip,
+            )
+        }
+    };
+
+    while let Some(chunk) = stream.next().await {
+        let mut chunk = chunk?;
+
+        if skip > 0 {
+            let skipped = skip.min(chunk.len() as u64);
+            skip -= skipped;
+            chunk = chunk.slice(skipped as usize..);
+        }
+
+        if chunk.is_empty() {
+            continue;
+        }
+
+        out.write_all(&chunk).await?;
+        on_chunk(&chunk);
+        *offset += chunk.len() as u64;
+    }
+
+    Ok(())
+}
+
+/// Returns the path an in-progress [`download`] to `path` is written to
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    part.into()
+}
+
 /// Fetch a resource at the provided [`Url`] and stream response body as bytes
 pub async fn get(url: Url) -> Result<BoxStream<'static, Result<Bytes, Error>>, Error> {
     match url_file(&url) {
@@ -77,3 +219,73 @@ pub enum Error {
     #[error("io")]
     Read(#[from] io::Error),
 }
+
+impl Error {
+    /// Returns true if the request may succeed when retried, such as
+    /// connection errors and server side failures
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Fetch(error) => {
+                error.is_connect()
+                    || error.is_timeout()
+                    || error.is_body()
+                    || error.is_decode()
+                    || error
+                        .status()
+                        .is_some_and(|status| status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS)
+            }
+            Error::Read(_) => false,
+        }
+    }
+
+    /// Returns true if the requested resource doesn't exist
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Error::Fetch(error) => error.status() == Some(StatusCode::NOT_FOUND),
+            Error::Read(error) => error.kind() == io::ErrorKind::NotFound,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STONE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../test/bash-completion-2.11-1-1-x86_64.stone"
+    );
+
+    #[tokio::test]
+    async fn resume_download() {
+        let dir = std::env::temp_dir().join(format!("moss-request-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let expected = std::fs::read(STONE).unwrap();
+        let path = dir.join("download.stone");
+
+        // Interrupted transfer left the first half behind
+        std::fs::write(part_path(&path), &expected[..expected.len() / 2]).unwrap();
+
+        let mut seen = vec![];
+        download(Url::from_file_path(STONE).unwrap(), &path, 0, |bytes| {
+            seen.extend_from_slice(bytes)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(seen, expected);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+        assert!(!part_path(&path).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retry_backoff() {
+        assert_eq!(retry_delay(0), environment::DOWNLOAD_RETRY_DELAY);
+        assert_eq!(retry_delay(2), environment::DOWNLOAD_RETRY_DELAY * 4);
+        assert_eq!(retry_delay(16), environment::MAX_DOWNLOAD_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), environment::MAX_DOWNLOAD_RETRY_DELAY);
+    }
+}
@@ -6,12 +6,14 @@ use std::io::Write;
 
 use chrono::{DateTime, Utc};
 use derive_more::{Display, From, Into};
+use serde::{Deserialize, Serialize};
 use tui::{pretty, Styled};
 
 use crate::package;
 
 /// Unique identifier for [`State`]
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, From, Into, Display)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, From, Into, Display, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Id(i32);
 
 impl Id {
@@ -22,9 +24,10 @@ impl Id {
 }
 
 /// State types
-#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString, Serialize)]
 #[repr(u8)]
 #[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
 pub enum Kind {
     /// Automatically constructed state
     Transaction,
@@ -38,7 +41,7 @@ impl TryFrom<String> for Kind {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct State {
     /// Unique identifier for this state
     pub id: Id,
@@ -52,12 +55,15 @@ pub struct State {
     pub created: DateTime<Utc>,
     /// Relevant type for this State
     pub kind: Kind,
+    /// Problems encountered while applying this state, such as failed triggers
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
 }
 
 /// The Selection records the presence of a package ID in a [`State`]
 /// It also records whether it was selected as a transitive dependency,
 /// along with an optional human-readable reason
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Selection {
     pub package: package::Id,
     /// Marks whether the package was explicitly installed
@@ -94,6 +100,19 @@ impl Selection {
     }
 }
 
+/// Mark the `packages` among `selections` as explicitly installed or as
+/// transitive, returning the packages whose selection changed
+pub fn mark(selections: &mut [Selection], packages: &[package::Id], explicit: bool) -> Vec<package::Id> {
+    selections
+        .iter_mut()
+        .filter(|selection| packages.contains(&selection.package) && selection.explicit != explicit)
+        .map(|selection| {
+            selection.explicit = explicit;
+            selection.package.clone()
+        })
+        .collect()
+}
+
 /// Columnar display encapsulation for a [`State`]
 pub struct ColumnDisplay<'a>(pub &'a State);
 
@@ -106,3 +125,34 @@ impl<'a> pretty::ColumnDisplay for ColumnDisplay<'a> {
         let _ = write!(writer, "State {}{:width$}", self.0.id.to_string().bold(), " ",);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mark_selections() {
+        let id = |id: &str| package::Id::from(id.to_string());
+        let mut selections = vec![
+            Selection::explicit(id("nano")),
+            Selection {
+                explicit: false,
+                ..Selection::explicit(id("zlib")).reason("dependency")
+            },
+            Selection::explicit(id("vim")),
+        ];
+
+        // Only selections that actually flip are reported
+        let changed = mark(&mut selections, &[id("zlib"), id("vim"), id("missing")], true);
+        assert_eq!(changed, vec![id("zlib")]);
+        assert!(selections.iter().all(|s| s.explicit));
+        assert_eq!(selections[1].reason.as_deref(), Some("dependency"));
+
+        let changed = mark(&mut selections, &[id("nano")], false);
+        assert_eq!(changed, vec![id("nano")]);
+        assert_eq!(
+            selections.iter().map(|s| s.explicit).collect::<Vec<_>>(),
+            vec![false, true, true]
+        );
+    }
+}